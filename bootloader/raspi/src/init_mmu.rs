@@ -1,10 +1,10 @@
 use aarch64_cpu::{
     asm::barrier,
-    registers::{MAIR_EL1, SCTLR_EL1, TCR_EL1, TTBR0_EL1, TTBR1_EL1},
+    registers::{ID_AA64MMFR0_EL1, MAIR_EL1, SCTLR_EL1, TCR_EL1, TTBR0_EL1, TTBR1_EL1},
 };
 
 use raspi::memory::page_table::Lvl0TableDescriptor;
-use tock_registers::interfaces::{ReadWriteable, Writeable};
+use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
 
 pub fn init_mmu(ttbr0: *const Lvl0TableDescriptor, ttbr1: *const Lvl0TableDescriptor) {
     MAIR_EL1.write(MAIR_EL1::Attr0_Device::nonGathering_nonReordering_noEarlyWriteAck);
@@ -20,8 +20,21 @@ pub fn init_mmu(ttbr0: *const Lvl0TableDescriptor, ttbr1: *const Lvl0TableDescri
     let t0sz = (64 - 48) as u64;
     let t1sz = (64 - 48) as u64;
 
+    // The kernel tags TTBR0 (user) mappings with an ASID taken from TTBR0_EL1. Use 16 bit ASIDs
+    // whenever the core supports them, otherwise fall back to the architectural minimum of 8 bits.
+    let asid_size = match ID_AA64MMFR0_EL1.read(ID_AA64MMFR0_EL1::ASIDBits) {
+        0b0010 => TCR_EL1::AS::ASID16Bits,
+        _ => TCR_EL1::AS::ASID8Bits,
+    };
+
     // 4KiB granule, caching enabled
-    TCR_EL1.write(TCR_EL1::IPS::Bits_48 + TCR_EL1::T0SZ.val(t0sz) + TCR_EL1::T1SZ.val(t1sz));
+    TCR_EL1.write(
+        TCR_EL1::IPS::Bits_48
+            + TCR_EL1::T0SZ.val(t0sz)
+            + TCR_EL1::T1SZ.val(t1sz)
+            + TCR_EL1::A1::TTBR0
+            + asid_size,
+    );
     barrier::isb(barrier::SY);
     SCTLR_EL1.modify(SCTLR_EL1::M::Enable + SCTLR_EL1::C::Cacheable + SCTLR_EL1::I::Cacheable);
     barrier::isb(barrier::SY);
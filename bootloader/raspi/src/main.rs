@@ -79,6 +79,7 @@ static MEM_MAP: OnceCell<RawDummylock, Dummylock<MemoryMap>> = OnceCell::new();
 static KERNEL_START_ADDR: OnceCell<RawDummylock, u64> = OnceCell::new();
 static MEMORY_LINEAR_MAP_START: OnceCell<RawDummylock, u64> = OnceCell::new();
 static KERNEL_STACKS_VIRT_TOP: OnceCell<RawDummylock, [u64; 4]> = OnceCell::new();
+static DTB_ADDR: OnceCell<RawDummylock, u64> = OnceCell::new();
 
 // Called by core_x_start asm function
 #[no_mangle]
@@ -304,6 +305,7 @@ pub extern "C" fn main(dtb_ptr: *const u8) -> ! {
         .set(memory_linear_map_start)
         .unwrap();
     KERNEL_STACKS_VIRT_TOP.set(kernel_stacks_virt_top).unwrap();
+    DTB_ADDR.set(dtb_ptr as u64).unwrap();
 
     // SAFETY: All read-only statics must be initialized by this point
     // Transfer control to the kernel
@@ -344,21 +346,23 @@ fn jump_to_kernel(core_num: u64) -> ! {
     // but we know that no bootloader code will ever execute again after we jump to the kernel.
     // We can create a new OnceCell in kernel space by copying the memory map at this pointer, soundly.
     let mem_map_addr = MEM_MAP.get().unwrap().lock().deref() as *const MemoryMap;
+    // The kernel's arguments go straight into x0 to x5, and the stack and entry point are kept
+    // clear of them, so that setting up one argument can never overwrite another
     unsafe {
-        asm!("mov sp, {stack}", 
-        "mov x0, {core_num}",
-        "mov x1, {memory_linear_map_start}", 
-        "mov x2, {kernel_end}",
-        "mov x3, {memory_map_addr}",
-        "br {entry}", 
-        stack = in(reg) KERNEL_STACKS_VIRT_TOP.get().unwrap()[core_num as usize], 
-        core_num = in(reg) core_num,
-        memory_linear_map_start = in(reg) *MEMORY_LINEAR_MAP_START.get().unwrap(),
-        kernel_end = in(reg) KERNEL_STACKS_VIRT_TOP.get().unwrap()[3].next_multiple_of(page_size),
-        memory_map_addr = in(reg) mem_map_addr,
-        entry = in(reg) *KERNEL_START_ADDR.get().unwrap());
+        asm!(
+            "mov sp, x6",
+            "mov x4, sp",
+            "br x7",
+            in("x0") core_num,
+            in("x1") *MEMORY_LINEAR_MAP_START.get().unwrap(),
+            in("x2") KERNEL_STACKS_VIRT_TOP.get().unwrap()[3].next_multiple_of(page_size),
+            in("x3") mem_map_addr,
+            in("x5") *DTB_ADDR.get().unwrap(),
+            in("x6") KERNEL_STACKS_VIRT_TOP.get().unwrap()[core_num as usize],
+            in("x7") *KERNEL_START_ADDR.get().unwrap(),
+            options(noreturn)
+        );
     }
-    loop {}
 }
 
 fn reserve_memory_regions(
@@ -371,7 +375,7 @@ fn reserve_memory_regions(
     unsafe {
         // Sound because this memory region will be protected by the memory map for the entire
         // lifetime of the os
-        // After the kernel boots it is free to reclaim this mem
+        // After the kernel boots it copies the blob and reclaims this mem
         dtb = DevTree::from_raw_pointer(dtb_ptr).expect("Failed to read dtb! Err");
     }
 
@@ -0,0 +1,38 @@
+use alloc::boxed::Box;
+use core::{ptr::read_unaligned, slice::from_raw_parts};
+use generic_once_cell::OnceCell;
+use raspi::concurrency::mutex::RawMutex;
+
+use crate::memory::phys_to_virt;
+
+const FDT_MAGIC: u32 = 0xd00dfeed;
+
+// Heap copy of the flattened device tree blob, so that the frames the firmware placed it in can be
+// reclaimed
+static DEVICE_TREE: OnceCell<RawMutex, Box<[u8]>> = OnceCell::new();
+
+/// Copies the device tree blob at physical address ```dtb_addr``` onto the kernel heap.
+///
+/// Returns Err if there is no valid blob at ```dtb_addr```, or if the device tree was already
+/// initialized. Must be called before the ```DtReserved``` memory is reclaimed.
+pub fn init(dtb_addr: u64) -> Result<(), ()> {
+    let header = phys_to_virt(dtb_addr) as *const u32;
+    // The header fields are big endian, and the blob is only guaranteed to be 8 byte aligned
+    let (magic, total_size) = unsafe {
+        (
+            u32::from_be(read_unaligned(header)),
+            u32::from_be(read_unaligned(header.add(1))),
+        )
+    };
+    if magic != FDT_MAGIC {
+        return Err(());
+    }
+
+    let blob = unsafe { from_raw_parts(header as *const u8, total_size as usize) };
+    DEVICE_TREE.set(Box::from(blob)).map_err(|_| ())
+}
+
+/// Returns the kernel's copy of the device tree blob, if it has been initialized.
+pub fn blob() -> Option<&'static [u8]> {
+    DEVICE_TREE.get().map(|blob| &**blob)
+}
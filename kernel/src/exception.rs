@@ -0,0 +1,128 @@
+use raspi::exception::{
+    esr::{Abort, FaultKind},
+    Cpu_Context,
+};
+
+use crate::{
+    ipi, kprintln,
+    memory::{address_space, stack::find_guard, user_access},
+    percpu,
+    process::{self, scheduler, KILLED_STATUS},
+};
+
+/// The kernel's synchronous exception handler, registered with the raspi exception vectors.
+///
+/// Returns false for any exception the kernel does not know how to handle, which results in a panic
+/// that dumps the saved CPU state.
+pub fn handle_synchronous(context: &mut Cpu_Context) -> bool {
+    percpu::inc(percpu!(stats.synchronous_exceptions));
+    match context.esr().abort(context.far_el1) {
+        Some(abort) => handle_abort(context, abort),
+        // Anything else user code can trigger, such as an undefined instruction, only concerns the
+        // process itself
+        None if context.from_user() => {
+            kprintln!(
+                "Illegal instruction: ESR {:#x}, pc {:#x}",
+                context.esr_el1,
+                context.elr_el1
+            );
+            process::kill_current(context, KILLED_STATUS);
+            true
+        }
+        None => false,
+    }
+}
+
+/// The kernel's IRQ handler, registered with the raspi exception vectors.
+pub fn handle_irq(context: &mut Cpu_Context) {
+    let irq_depth = percpu!(irq_depth);
+    irq_depth.set(irq_depth.get() + 1);
+    percpu::inc(percpu!(stats.irqs));
+    #[cfg(feature = "lockdep")]
+    raspi::concurrency::lockdep::enter_interrupt();
+
+    // IPIs and the timer are the only interrupts that are enabled so far
+    if !ipi::handle_irq(context) {
+        panic!("Unexpected IRQ! Dumping CPU State: \n\n{}", context);
+    }
+
+    #[cfg(feature = "lockdep")]
+    raspi::concurrency::lockdep::exit_interrupt();
+    irq_depth.set(irq_depth.get() - 1);
+
+    // An interrupted process holds no kernel locks, so the interrupt is left like a system call,
+    // running the timeouts that expired
+    if context.from_user() {
+        scheduler::reschedule(context);
+    }
+}
+
+fn handle_abort(context: &mut Cpu_Context, abort: Abort) -> bool {
+    let addr = match abort.address {
+        Some(addr) => addr,
+        None => return unresolved_abort(context, abort, 0),
+    };
+
+    if !abort.from_user {
+        if let Some(guard) = find_guard(addr) {
+            panic!(
+                "Stack overflow on core {} / thread {}! Faulting address {:#x} is in the guard page \
+                 below the stack at {:#x}. Dumping CPU State: \n\n{}",
+                guard.core, guard.owner, addr, guard.end, context
+            );
+        }
+    }
+
+    // Only the lower half is made up of per address space areas that can be populated on demand
+    let resolvable = matches!(
+        abort.kind,
+        FaultKind::Translation(_) | FaultKind::Permission(_)
+    );
+    if resolvable && addr >> 48 == 0 {
+        percpu::inc(percpu!(stats.page_faults));
+        if let Some(space) = address_space::current() {
+            // User code holds no kernel locks, and the kernel never accesses user memory with the
+            // lock held, so both can wait for whoever holds it. Any other kernel code may itself
+            // hold the lock, in which case the fault cannot be resolved
+            let waits = abort.from_user || user_access::is_user_access(context.elr_el1);
+            let space = match waits {
+                true => Some(space.lock()),
+                false => space.try_lock(),
+            };
+            if let Some(mut space) = space {
+                if space.handle_fault(addr, abort.access).is_ok() {
+                    return true;
+                }
+            }
+        }
+    }
+
+    unresolved_abort(context, abort, addr)
+}
+
+fn unresolved_abort(context: &mut Cpu_Context, abort: Abort, addr: u64) -> bool {
+    // A bad user pointer passed to the kernel is reported to the code that tried to access it
+    if !abort.from_user && user_access::fixup_exception(context) {
+        return true;
+    }
+    invalid_access(context, abort, addr)
+}
+
+fn invalid_access(context: &mut Cpu_Context, abort: Abort, addr: u64) -> bool {
+    if abort.from_user {
+        kprintln!(
+            "Segmentation fault: {:?} {:?} at {:#x}, pc {:#x}",
+            abort.kind,
+            abort.access,
+            addr,
+            context.elr_el1
+        );
+        process::kill_current(context, KILLED_STATUS);
+        return true;
+    }
+
+    panic!(
+        "Kernel oops: {:?} {:?} at {:#x}. Dumping CPU State: \n\n{}",
+        abort.kind, abort.access, addr, context
+    );
+}
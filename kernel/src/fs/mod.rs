@@ -1,7 +1,108 @@
-use alloc::vec::Vec;
-use fatfs::Seek;
+use alloc::{string::String, vec::Vec};
+use fatfs::{FileSystem, FsOptions, Read, Seek, SeekFrom};
+use generic_once_cell::OnceCell;
+use raspi::concurrency::mutex::{Mutex, RawMutex};
 
-use crate::peripherals::EMMC2;
+use crate::{peripherals::EMMC2, syscall::Errno};
+
+/// The FAT filesystem on the SD card.
+pub type FatFileSystem = FileSystem<Fat32FileSystem>;
+
+pub static FILESYSTEM: OnceCell<RawMutex, Mutex<FatFileSystem>> = OnceCell::new();
+
+/// Mounts the filesystem on the SD card. The EMMC2 controller must be initialized.
+pub fn init() -> Result<(), ()> {
+    let fs = FileSystem::new(Fat32FileSystem::new(), FsOptions::new()).map_err(|_| ())?;
+    FILESYSTEM.set(Mutex::new(fs)).map_err(|_| ())
+}
+
+/// Returns the mounted filesystem.
+pub fn filesystem() -> Result<&'static Mutex<FatFileSystem>, Errno> {
+    FILESYSTEM.get().ok_or(Errno::EIO)
+}
+
+// Paths are always relative to the root directory, with or without a leading slash
+fn relative(path: &str) -> &str {
+    path.trim_start_matches('/')
+}
+
+fn map_error(error: fatfs::Error<()>) -> Errno {
+    match error {
+        fatfs::Error::NotFound => Errno::ENOENT,
+        fatfs::Error::AlreadyExists => Errno::EEXIST,
+        fatfs::Error::InvalidInput => Errno::EINVAL,
+        fatfs::Error::InvalidFileNameLength => Errno::ENAMETOOLONG,
+        fatfs::Error::NotEnoughSpace => Errno::ENOSPC,
+        _ => Errno::EIO,
+    }
+}
+
+/// Returns Ok if ```path``` names a regular file.
+pub fn check_file(path: &str) -> Result<(), Errno> {
+    let fs = filesystem()?.lock();
+    let result = fs.root_dir().open_file(relative(path)).map(|_| ());
+    result.map_err(map_error)
+}
+
+/// Reads from the file at ```path```, starting ```offset``` bytes in. Returns the number of bytes
+/// read, which is only short at the end of the file.
+pub fn read_at(path: &str, offset: u64, buf: &mut [u8]) -> Result<usize, Errno> {
+    let fs = filesystem()?.lock();
+    let mut file = fs.root_dir().open_file(relative(path)).map_err(map_error)?;
+    file.seek(SeekFrom::Start(offset)).map_err(map_error)?;
+
+    let mut total = 0;
+    while total < buf.len() {
+        match file.read(&mut buf[total..]).map_err(map_error)? {
+            0 => break,
+            read => total += read,
+        }
+    }
+    Ok(total)
+}
+
+/// Reads the entire file at ```path```.
+pub fn read_file(path: &str) -> Result<Vec<u8>, Errno> {
+    let fs = filesystem()?.lock();
+    let mut file = fs.root_dir().open_file(relative(path)).map_err(map_error)?;
+
+    let mut data = Vec::new();
+    let mut chunk = [0u8; 512];
+    loop {
+        match file.read(&mut chunk).map_err(map_error)? {
+            0 => return Ok(data),
+            read => data.extend_from_slice(&chunk[..read]),
+        }
+    }
+}
+
+/// An entry of a directory.
+pub struct DirEntry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+}
+
+/// Returns the ```index```th entry of the directory at ```path```, or None past the last entry.
+pub fn read_dir_entry(path: &str, index: usize) -> Result<Option<DirEntry>, Errno> {
+    let fs = filesystem()?.lock();
+    let root = fs.root_dir();
+    let dir = match relative(path).trim_end_matches('/') {
+        "" => root,
+        path => root.open_dir(path).map_err(map_error)?,
+    };
+
+    let entry = match dir.iter().nth(index) {
+        Some(entry) => entry.map_err(map_error)?,
+        None => return Ok(None),
+    };
+    // Long file names need fatfs' alloc feature, so only the 8.3 name is available
+    Ok(Some(DirEntry {
+        name: String::from_utf8_lossy(entry.short_file_name_as_bytes()).into_owned(),
+        is_dir: entry.is_dir(),
+        size: entry.len(),
+    }))
+}
 
 #[derive(Clone, Copy)]
 pub struct Fat32FileSystem {
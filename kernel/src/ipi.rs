@@ -0,0 +1,270 @@
+//! Inter-processor interrupts
+//!
+//! Cores signal each other with a small set of messages. On the Raspberry Pi 3 every message is a
+//! bit in mailbox 0 of the target core, while the Raspberry Pi 4 uses one GIC software generated
+//! interrupt per message. Either way, a message that is sent again before the target handled it
+//! is only delivered once, so message handlers look at shared state to find out what to do.
+//!
+//! The same controllers deliver the interrupt of each core's timer, which is passed on to
+//! ```timer```.
+
+use core::{
+    hint,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use generic_once_cell::OnceCell;
+use raspi::{
+    concurrency::mutex::{IrqMutex, Mutex, RawMutex},
+    cpu::{core_id, disable_interrupts, enable_interrupts, restore_interrupts, NUM_CORES},
+    exception::Cpu_Context,
+    peripherals::{
+        core_mailbox::CoreMailboxes, get_board, gic::Gic, Board, ConstantsRaspi3, ConstantsRaspi4,
+    },
+};
+
+use crate::{memory::vmalloc::ioremap, page_size, panic, timer};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpiMessage {
+    /// Makes the target core reconsider what it is running
+    Reschedule = 0,
+    /// Makes the target core run the function posted with ```run_on_cores```
+    FunctionCall = 1,
+    /// Stops the target core for good
+    Halt = 2,
+}
+
+impl IpiMessage {
+    const ALL: [IpiMessage; 3] = [
+        IpiMessage::Reschedule,
+        IpiMessage::FunctionCall,
+        IpiMessage::Halt,
+    ];
+}
+
+enum Controller {
+    Mailboxes(CoreMailboxes),
+    Gic(Gic),
+}
+
+static CONTROLLER: OnceCell<RawMutex, Controller> = OnceCell::new();
+// Bitmask of the cores that are able to receive IPIs
+static ONLINE_CORES: AtomicUsize = AtomicUsize::new(0);
+
+// Only a single function call is in flight at a time. The function is published in CALL, and
+// CALL_PENDING holds a bit for every core that has not finished running it yet.
+static CALL_LOCK: Mutex<()> = Mutex::new(());
+static CALL: IrqMutex<Option<&'static (dyn Fn() + Sync)>> = IrqMutex::new(None);
+static CALL_PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// Maps the interrupt controller of the board that is used to send IPIs.
+///
+/// Must be called once, on the boot core, after vmalloc is usable.
+pub fn init() -> Result<(), ()> {
+    let controller = match get_board() {
+        Board::RPI3 => {
+            let base = ioremap(
+                ConstantsRaspi3::LOCAL_PERIPHERALS_PHYS_BASE,
+                page_size() as usize,
+            )
+            .ok_or(())?;
+            // SAFETY: The local peripherals were just mapped at this address
+            Controller::Mailboxes(unsafe { CoreMailboxes::new(base.as_ptr() as u64) })
+        }
+        Board::RPI4 => {
+            let dist =
+                ioremap(ConstantsRaspi4::GIC_DIST_PHYS_BASE, page_size() as usize).ok_or(())?;
+            // The CPU interface is two pages long
+            let cpu =
+                ioremap(ConstantsRaspi4::GIC_CPU_PHYS_BASE, 2 * page_size() as usize).ok_or(())?;
+            // SAFETY: The distributor and CPU interface were just mapped at these addresses
+            let gic = unsafe { Gic::new(dist.as_ptr() as u64, cpu.as_ptr() as u64) };
+            gic.init_distributor();
+            Controller::Gic(gic)
+        }
+        Board::UNSUPPORTED => return Err(()),
+    };
+
+    CONTROLLER.set(controller).map_err(|_| ())
+}
+
+/// Lets the calling core receive IPIs and its timer interrupt, and unmasks IRQs on it.
+///
+/// Must be called on every core, after ```init``` and after the IRQ handler has been registered.
+pub fn init_core() {
+    let core = core_id();
+    match controller() {
+        Controller::Mailboxes(mailboxes) => {
+            mailboxes.enable(core);
+            mailboxes.enable_timer(core);
+        }
+        Controller::Gic(gic) => {
+            gic.init_cpu_interface();
+            gic.enable_ppi(Gic::TIMER_PPI);
+        }
+    }
+    ONLINE_CORES.fetch_or(1 << core, Ordering::AcqRel);
+    // SAFETY: IPIs and the timer are the only interrupts enabled so far, and handle_irq acknowledges
+    // both
+    unsafe { enable_interrupts() };
+}
+
+/// Returns true once ```init``` has succeeded.
+pub fn is_initialized() -> bool {
+    CONTROLLER.get().is_some()
+}
+
+fn controller() -> &'static Controller {
+    CONTROLLER
+        .get()
+        .expect("Attempted to use IPIs before they were initialized")
+}
+
+/// Sends ```message``` to ```core```.
+pub fn send(core: usize, message: IpiMessage) {
+    match controller() {
+        Controller::Mailboxes(mailboxes) => mailboxes.send(core as u64, 1 << message as u32),
+        Controller::Gic(gic) => gic.send_sgi(core as u64, message as u32),
+    }
+}
+
+/// Sends ```message``` to every online core except the calling one.
+pub fn send_to_others(message: IpiMessage) {
+    let this_core = core_id() as usize;
+    let online = ONLINE_CORES.load(Ordering::Acquire);
+    for core in (0..NUM_CORES).filter(|core| *core != this_core && online & (1 << core) != 0) {
+        send(core, message);
+    }
+}
+
+/// Handles the IPIs and timer interrupt pending on the calling core. Must be called from the IRQ
+/// handler, with the state of the interrupted code.
+///
+/// Returns false if the interrupt was raised by neither.
+pub fn handle_irq(context: &Cpu_Context) -> bool {
+    let core = core_id();
+    match controller() {
+        Controller::Mailboxes(mailboxes) => {
+            let timer_pending = mailboxes.is_timer_pending(core);
+            if timer_pending {
+                timer::handle_irq();
+            }
+            if !mailboxes.is_pending(core) {
+                return timer_pending;
+            }
+            let messages = mailboxes.take(core);
+            for message in IpiMessage::ALL {
+                if messages & (1 << message as u32) != 0 {
+                    handle_message(message, context);
+                }
+            }
+            true
+        }
+        Controller::Gic(gic) => {
+            let iar = gic.acknowledge();
+            let id = iar & 0x3FF;
+            if id == Gic::SPURIOUS_INTERRUPT {
+                // The interrupt was withdrawn before we got to it
+                return true;
+            }
+            if id == Gic::TIMER_PPI {
+                // The timer has to be disarmed before signalling completion, or it fires again
+                timer::handle_irq();
+                gic.end_of_interrupt(iar);
+                return true;
+            }
+            // Signal completion first, since a halt never returns
+            gic.end_of_interrupt(iar);
+            match IpiMessage::ALL.get(id as usize) {
+                Some(message) => {
+                    handle_message(*message, context);
+                    true
+                }
+                None => false,
+            }
+        }
+    }
+}
+
+fn handle_message(message: IpiMessage, context: &Cpu_Context) {
+    match message {
+        // Leaving the interrupt runs the expired timeouts and switches away from the interrupted
+        // process if it stopped running, and idle cores poll the run queues. That is all it takes
+        IpiMessage::Reschedule => {}
+        IpiMessage::FunctionCall => run_pending_call(),
+        IpiMessage::Halt => panic::halt(Some(context)),
+    }
+}
+
+/// Stops the calling core for good, with interrupts masked. It no longer receives IPIs.
+pub fn halt() -> ! {
+    disable_interrupts();
+    ONLINE_CORES.fetch_and(!(1 << core_id()), Ordering::AcqRel);
+    loop {
+        aarch64_cpu::asm::wfe();
+    }
+}
+
+/// Runs ```f``` on every core in the bitmask ```cores``` that is online, including the calling core
+/// if it is part of the mask. Returns once all of them have finished running it.
+///
+/// On remote cores, ```f``` runs in interrupt context. Must be called with interrupts enabled, since
+/// the other cores may need this core to service their own IPIs in the meantime.
+pub fn run_on_cores(cores: usize, f: &(dyn Fn() + Sync)) {
+    // Another core may be waiting for us to run its function while we wait for the lock, so keep
+    // servicing calls to avoid a deadlock
+    let _guard = loop {
+        if let Some(guard) = CALL_LOCK.try_lock() {
+            break guard;
+        }
+        run_pending_call();
+        hint::spin_loop();
+    };
+
+    let this_core = core_id() as usize;
+    let targets = cores & ONLINE_CORES.load(Ordering::Acquire) & !(1 << this_core);
+    // SAFETY: The reference is only used until CALL_PENDING drops to zero, which happens before this
+    // function returns
+    *CALL.lock() =
+        Some(unsafe { core::mem::transmute::<&(dyn Fn() + Sync), &'static (dyn Fn() + Sync)>(f) });
+    CALL_PENDING.store(targets, Ordering::Release);
+
+    for core in (0..NUM_CORES).filter(|core| targets & (1 << core) != 0) {
+        send(core, IpiMessage::FunctionCall);
+    }
+    if cores & (1 << this_core) != 0 {
+        f();
+    }
+
+    while CALL_PENDING.load(Ordering::Acquire) != 0 {
+        hint::spin_loop();
+    }
+    *CALL.lock() = None;
+}
+
+/// Runs ```f``` on ```core```, returning once it finished. See ```run_on_cores```.
+pub fn run_on_core(core: usize, f: &(dyn Fn() + Sync)) {
+    run_on_cores(1 << core, f);
+}
+
+/// Runs ```f``` on every online core, returning once all of them finished. See ```run_on_cores```.
+pub fn run_on_all_cores(f: &(dyn Fn() + Sync)) {
+    run_on_cores(usize::MAX, f);
+}
+
+fn run_pending_call() {
+    // The IPI could otherwise interrupt run_on_cores between the check and clearing the bit, and run
+    // the call a second time
+    let daif = disable_interrupts();
+    let bit = 1 << core_id();
+    if CALL_PENDING.load(Ordering::Acquire) & bit != 0 {
+        let call = *CALL.lock();
+        if let Some(f) = call {
+            f();
+        }
+        CALL_PENDING.fetch_and(!bit, Ordering::AcqRel);
+    }
+    // SAFETY: daif was read on this core above
+    unsafe { restore_interrupts(daif) };
+}
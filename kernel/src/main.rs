@@ -4,9 +4,17 @@
 #![feature(allocator_api)]
 #![feature(int_roundings)]
 
+pub mod device_tree;
+pub mod exception;
 pub mod fs;
+pub mod ipi;
 pub mod memory;
+pub mod panic;
+pub mod percpu;
 pub mod peripherals;
+pub mod process;
+pub mod syscall;
+pub mod timer;
 pub mod util;
 
 extern crate alloc;
@@ -14,6 +22,7 @@ extern crate alloc;
 extern "C" {
     static __PG_SIZE: u8;
     static __KERNEL_VIRT_END: u8;
+    static __STACK_SIZE: u8;
 }
 pub fn page_size() -> u64 {
     unsafe { (&__PG_SIZE as *const u8) as u64 }
@@ -23,65 +32,101 @@ pub fn kernel_virt_end() -> u64 {
     unsafe { (&__KERNEL_VIRT_END as *const u8) as u64 }
 }
 
-use core::time::Duration;
+pub fn stack_size() -> u64 {
+    unsafe { (&__STACK_SIZE as *const u8) as u64 }
+}
+
+use core::hint;
 
 use crate::{
-    fs::Fat32FileSystem,
-    memory::GLOBAL_ALLOCATOR,
-    peripherals::{EMMC2, MAILBOX, UART},
-    util::clear_tlb,
+    memory::{
+        accounting::{FrameCategory, ACCOUNTING},
+        address_space,
+        frame_cache::FRAME_CACHE,
+        frame_ref,
+        heap::KernelHeap,
+        memory_stats, reclaim_boot_frames, set_linear_map_start,
+        stack::{init_exception_stack, register_stack, retire_boot_stack, switch_to_new_stack},
+        tlb, FRAME_ALLOCATOR, GLOBAL_ALLOCATOR, KERNEL_PAGE_TABLE,
+    },
+    peripherals::{EMMC2, MAILBOX, POWER, UART},
+    process::scheduler,
 };
 use aarch64_cpu::registers;
-use alloc::string::String;
-use allocators::allocators::linked_list_allocator::LinkedListAlloc;
-use fatfs::{FileSystem, FsOptions, Read, Write};
-use generic_once_cell::Lazy;
-use memory::frame_allocator::FrameAlloc;
 use raspi::{
-    concurrency::{
-        barrier::Barrier,
-        mutex::{Mutex, RawMutex},
+    concurrency::{barrier::Barrier, mutex::Mutex},
+    cpu::{enable_pan, NUM_CORES},
+    exception::{
+        install_exception_handlers, set_irq_handler, set_synchronous_handler, set_syscall_handler,
     },
-    exception::install_exception_handlers,
     memory::{
         memory_map::{EntryType, MemoryMap},
-        page_table::{Lvl0TableDescriptor, MemoryType, PageAlloc, PageTable, VirtualAddr},
+        page_table::{Lvl0TableDescriptor, PageTable},
     },
     peripherals::{
         emmc::{EMMCController, SdResult},
         get_emmc_offset_from_mmio_base, get_mmio_offset_from_peripheral_base,
-        timer::wait_for,
     },
 };
 
-static FRAME_ALLOCATOR: Lazy<RawMutex, Mutex<FrameAlloc>> =
-    Lazy::new(|| Mutex::new(FrameAlloc::new()));
-
-static BARRIER: Lazy<RawMutex, Barrier> = Lazy::new(|| Barrier::new(4));
+static BARRIER: Barrier = Barrier::new(NUM_CORES as u32);
 
 // Safety: At this point, assume the TTBR0 table has been totally wiped out
 #[no_mangle]
-pub extern "C" fn secondary_core_kmain(core_num: u64) -> ! {
+pub extern "C" fn secondary_core_kmain(core_num: u64, stack_top: u64) -> ! {
+    // VBAR_EL1 is banked per core, so every core has to install the vectors itself
+    install_exception_handlers();
+    register_stack(stack_top, stack_size(), core_num, "idle")
+        .expect("Failed to register kernel stack guard");
     // Safe to unwrap here because we know the barrier won't be "consumed" until after
     // the barrier synchronizes
     BARRIER.wait();
-    kprints!(core_num, "Hello from secondary core!");
-    loop {}
+    // The heap is only guaranteed to be ready once the boot core reaches the barrier
+    init_exception_stack(core_num).expect("Failed to allocate exception stack");
+    enable_pan();
+    scheduler::init_core().expect("Failed to allocate scheduler idle stack");
+    // Leave the identity mapping of the bootloader like the boot core did. The boot core initialized
+    // the scheduler before the barrier, so the idle address space exists by now
+    address_space::switch_to(scheduler::idle_space());
+    tlb::flush_local();
+    ipi::init_core();
+    // Leave the stack the bootloader set up, so that its frames can be reclaimed
+    switch_to_new_stack("idle", secondary_core_idle, stack_top);
+}
+
+extern "C" fn secondary_core_idle(boot_stack_top: u64) -> ! {
+    retire_boot_stack(boot_stack_top);
+    kprints!(percpu!(core).get(), "Hello from secondary core!");
+    loop {
+        timer::run_expired();
+        scheduler::run_next();
+        panic::halt_if_panicking();
+        hint::spin_loop();
+    }
 }
 
 #[no_mangle]
 pub extern "C" fn kernel_early_init(
     core_num: u64,
     memory_linear_map_start: u64,
-    kernel_end: u64,
+    _kernel_end: u64,
     mem_map: *mut MemoryMap,
+    stack_top: u64,
+    dtb_addr: u64,
 ) -> ! {
+    percpu::init(core_num);
+
     // Fork off the secondary cores
     if core_num != 0 {
-        secondary_core_kmain(core_num);
+        secondary_core_kmain(core_num, stack_top);
     }
 
     let addr = install_exception_handlers();
+    set_synchronous_handler(exception::handle_synchronous);
+    set_syscall_handler(syscall::handle_syscall);
+    #[cfg(feature = "lockdep")]
+    raspi::concurrency::lockdep::set_report_handler(util::report_lock_violation);
+    set_linear_map_start(memory_linear_map_start);
 
     // Copy over the old memory map data before we reclaim the bootloader memory
     let mem_map_old: &MemoryMap = unsafe { &mut *mem_map };
@@ -100,6 +145,9 @@ pub extern "C" fn kernel_early_init(
     MAILBOX.lock().update_mmio_base(
         memory_linear_map_start + peripheral_start_addr + get_mmio_offset_from_peripheral_base(),
     );
+    POWER.lock().update_mmio_base(
+        memory_linear_map_start + peripheral_start_addr + get_mmio_offset_from_peripheral_base(),
+    );
     let _ = EMMC2.set(Mutex::new(unsafe {
         EMMCController::new(
             (memory_linear_map_start
@@ -118,9 +166,19 @@ pub extern "C" fn kernel_early_init(
             EntryType::Bootloader | EntryType::Free => {
                 for addr in (entry.base_addr..entry.end_addr).step_by(page_size() as usize) {
                     // If we fail to add a page to the free list, just silently ignore
-                    let _ = FRAME_ALLOCATOR.lock().deallocate_frame(addr as *mut u8);
+                    let _ = FRAME_ALLOCATOR.lock().add_frame(addr);
                 }
             }
+            // Account for the memory the bootloader set up for us
+            EntryType::Kernel | EntryType::Stack | EntryType::BLReserved => {
+                let category = match entry.entry_type {
+                    EntryType::Kernel => FrameCategory::KernelImage,
+                    EntryType::Stack => FrameCategory::Stack,
+                    // Everything the bootloader allocated dynamically was a page table
+                    _ => FrameCategory::PageTable,
+                };
+                ACCOUNTING.charge(category, entry.size.to_bytes() / page_size());
+            }
             _ => (),
         }
     }
@@ -129,44 +187,54 @@ pub extern "C" fn kernel_early_init(
         FRAME_ALLOCATOR.lock().num_free_frames()
     );
 
-    // TODO: Might want to consider lazy loading of memory into the kernel heap,
-    // via some kind of kmmap call. This would also allow resizable kernel heap.
-    let mut ttbr1 = unsafe {
-        PageTable::from_raw_ptr(
+    let ttbr1 = unsafe {
+        PageTable::from_raw_ptr_with_offset(
             registers::TTBR1_EL1.get_baddr() as *const Lvl0TableDescriptor,
-            &FRAME_ALLOCATOR,
+            &FRAME_CACHE,
+            memory_linear_map_start,
         )
     };
+    KERNEL_PAGE_TABLE
+        .set(Mutex::new(ttbr1))
+        .map_err(|_| ())
+        .expect("Failed to initialize kernel page table!");
 
     // Initialize kernel heap:
-    let kernel_heap_start = kernel_end;
-    let kernel_heap_end = kernel_heap_start + 0x200000;
-    for virt_page in (kernel_heap_start..kernel_heap_end).step_by(page_size() as usize) {
-        let phys_page = FRAME_ALLOCATOR
-            .lock()
-            .allocate_frame()
-            .expect("Failed to allocate memory for kernel heap") as u64;
-        ttbr1
-            .map_page(
-                phys_page,
-                VirtualAddr(virt_page),
-                MemoryType::NORMAL_CACHEABLE,
-            )
-            .expect("Failed to map memory for kernel heap");
-    }
+    let heap = KernelHeap::new().expect("Failed to map memory for kernel heap");
     GLOBAL_ALLOCATOR
         .0
-        .set(unsafe {
-            LinkedListAlloc::<RawMutex>::new(
-                kernel_heap_start as *mut u8,
-                kernel_heap_end as *mut u8,
-            )
-        })
+        .set(Mutex::new(heap))
+        .map_err(|_| ())
         .expect("Failed to initialize heap allocator!");
+    let heap_stats = GLOBAL_ALLOCATOR.stats().unwrap();
     kprintln!(
         "Initialized kernel heap at address range {:#x} - {:#x}",
-        kernel_heap_start,
-        kernel_heap_end
+        heap_stats.start,
+        heap_stats.end
+    );
+
+    // Now that vmalloc is usable, move exception handling off of the boot stack
+    register_stack(stack_top, stack_size(), core_num, "kernel main")
+        .expect("Failed to register kernel stack guard");
+    init_exception_stack(core_num).expect("Failed to allocate exception stack");
+    kprintln!("Switched to dedicated exception stack");
+    if enable_pan() {
+        kprintln!("Enabled Privileged Access Never");
+    }
+    scheduler::init_core().expect("Failed to allocate scheduler idle stack");
+
+    set_irq_handler(exception::handle_irq);
+    ipi::init().expect("Failed to initialize inter-processor interrupts");
+    ipi::init_core();
+    kprintln!("Enabled inter-processor interrupts");
+
+    frame_ref::init(map.get_total_mem().to_bytes())
+        .expect("Failed to allocate frame reference counts");
+
+    device_tree::init(dtb_addr).expect("Failed to copy the device tree");
+    kprintln!(
+        "Copied device tree blob of size {:#x} bytes",
+        device_tree::blob().unwrap().len()
     );
 
     let init_res = EMMC2.get().unwrap().lock().emmc_init_card();
@@ -176,39 +244,41 @@ pub extern "C" fn kernel_early_init(
         kprintln!("Initialized EMMC2 driver. Storage medium ready to receive block requests.");
     }
 
-    let fs = Fat32FileSystem::new();
-    let blah = FileSystem::new(fs, FsOptions::new()).unwrap();
+    fs::init().expect("Failed to read FAT filesystem from SD card");
     kprintln!("Successfully read FAT filesystem from SDCard");
 
-    // Wipe the identity-mapped page table
-    let ttbr0 = PageTable::new(&FRAME_ALLOCATOR).unwrap();
-    registers::TTBR0_EL1.set_baddr(ttbr0.as_raw_ptr() as u64);
-    clear_tlb();
-    kprintln!("Kernel initialization complete");
+    syscall::init().expect("Failed to register system calls");
+    kprintln!("Registered system calls");
 
-    kprintln!("Writing 'Hello, world!' to file 'hello.txt' at root dir");
-    let mut file = blah.root_dir().create_file("hello.txt").unwrap();
-    file.write("Hello, world!".as_bytes()).unwrap();
-    file.flush().unwrap();
+    // Wipe the identity-mapped page table by switching to the empty address space idle cores use
+    // The identity mapping was global, so it has to be flushed out of the TLB by hand
+    scheduler::init().expect("Failed to initialize the scheduler");
+    address_space::switch_to(scheduler::idle_space());
+    tlb::flush_local();
+    kprintln!("Kernel initialization complete");
 
-    kprintln!("Waiting 5 seconds...");
-    wait_for(Duration::new(5, 0));
+    BARRIER.wait();
+    // All cores are in the kernel now, so nothing is parked on the first page anymore
+    let reclaimed = reclaim_boot_frames(&map);
+    kprintln!("Reclaimed {} frames of boot-only memory", reclaimed);
+    kprint!("Physical memory usage:\n{}", memory_stats());
 
-    kprintln!("Opening file 'hello.txt' and reading file contents");
-    let mut file = blah.root_dir().open_file("hello.txt").unwrap();
-    let mut buf = [0u8; 13];
-    let bytes_read = file.read(&mut buf).unwrap();
-    kprintln!(
-        "Read {:?} bytes from file. File contents: {}",
-        bytes_read,
-        String::from_utf8_lossy(&buf)
-    );
+    // Whichever core is idle first picks it up
+    match process::spawn(process::INIT_PATH, &[process::INIT_PATH], None) {
+        Ok(pid) => kprintln!("Started {} as pid {}", process::INIT_PATH, pid),
+        Err(errno) => kprintln!("Failed to start {}: {}", process::INIT_PATH, errno),
+    }
 
-    BARRIER.wait();
-    kmain(ttbr0);
+    switch_to_new_stack("kernel main", kmain, stack_top);
 }
 
-fn kmain(_ttbr0: PageTable<RawMutex, FrameAlloc>) -> ! {
+extern "C" fn kmain(boot_stack_top: u64) -> ! {
+    retire_boot_stack(boot_stack_top);
     // Never return from this diverging fn
-    loop {}
+    loop {
+        timer::run_expired();
+        scheduler::run_next();
+        panic::halt_if_panicking();
+        hint::spin_loop();
+    }
 }
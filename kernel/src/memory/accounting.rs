@@ -0,0 +1,173 @@
+//! Physical memory accounting
+//!
+//! Every frame handed out by the frame allocator is charged to the category of its owner, and
+//! uncharged again when it is returned. Frames that were reserved before the kernel took over, such
+//! as the kernel image, are charged once at boot. This provides live totals and high-water marks for
+//! each category, which can be printed in a ```/proc/meminfo``` like format.
+
+use core::{
+    fmt::Display,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use raspi::memory::mem_size::MemSize;
+
+use crate::page_size;
+
+/// The owner of an allocated frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameCategory {
+    KernelImage,
+    PageTable,
+    Heap,
+    Slab,
+    Stack,
+    User,
+    Dma,
+    FileCache,
+}
+
+impl FrameCategory {
+    pub const ALL: [FrameCategory; NUM_CATEGORIES] = [
+        FrameCategory::KernelImage,
+        FrameCategory::PageTable,
+        FrameCategory::Heap,
+        FrameCategory::Slab,
+        FrameCategory::Stack,
+        FrameCategory::User,
+        FrameCategory::Dma,
+        FrameCategory::FileCache,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            FrameCategory::KernelImage => "KernelImage",
+            FrameCategory::PageTable => "PageTables",
+            FrameCategory::Heap => "Heap",
+            FrameCategory::Slab => "Slab",
+            FrameCategory::Stack => "KernelStack",
+            FrameCategory::User => "User",
+            FrameCategory::Dma => "Dma",
+            FrameCategory::FileCache => "FileCache",
+        }
+    }
+}
+
+const NUM_CATEGORIES: usize = 8;
+
+/// Frame counts per category.
+///
+/// The counters are atomics rather than living under the ```FRAME_ALLOCATOR``` lock, so that the
+/// per-core frame caches can charge frames without touching any shared lock.
+pub struct FrameAccounting {
+    current: [AtomicU64; NUM_CATEGORIES],
+    peak: [AtomicU64; NUM_CATEGORIES],
+    total: AtomicU64,
+    peak_total: AtomicU64,
+}
+
+impl FrameAccounting {
+    pub const fn new() -> Self {
+        const ZERO: AtomicU64 = AtomicU64::new(0);
+        FrameAccounting {
+            current: [ZERO; NUM_CATEGORIES],
+            peak: [ZERO; NUM_CATEGORIES],
+            total: ZERO,
+            peak_total: ZERO,
+        }
+    }
+
+    pub fn charge(&self, category: FrameCategory, frames: u64) {
+        let idx = category as usize;
+        let current = self.current[idx].fetch_add(frames, Ordering::Relaxed) + frames;
+        self.peak[idx].fetch_max(current, Ordering::Relaxed);
+        let total = self.total.fetch_add(frames, Ordering::Relaxed) + frames;
+        self.peak_total.fetch_max(total, Ordering::Relaxed);
+    }
+
+    pub fn uncharge(&self, category: FrameCategory, frames: u64) {
+        let previous = self.current[category as usize].fetch_sub(frames, Ordering::Relaxed);
+        // An underflow means a frame was returned under a different category than it was taken
+        // out with
+        debug_assert!(previous >= frames);
+        self.total.fetch_sub(frames, Ordering::Relaxed);
+    }
+
+    /// Returns a snapshot of the counters. The snapshot is not atomic as a whole, so concurrent
+    /// allocations may make the categories disagree slightly with the totals.
+    pub fn stats(&self, free_frames: u64) -> MemoryStats {
+        let mut categories = [CategoryStats::default(); NUM_CATEGORIES];
+        for (idx, stats) in categories.iter_mut().enumerate() {
+            stats.current = self.current[idx].load(Ordering::Relaxed);
+            stats.peak = self.peak[idx].load(Ordering::Relaxed);
+        }
+
+        MemoryStats {
+            free_frames,
+            used_frames: self.total.load(Ordering::Relaxed),
+            peak_used_frames: self.peak_total.load(Ordering::Relaxed),
+            categories,
+        }
+    }
+}
+
+/// The kernel's frame accounting, shared by all cores.
+pub static ACCOUNTING: FrameAccounting = FrameAccounting::new();
+
+#[derive(Default, Clone, Copy, Debug)]
+pub struct CategoryStats {
+    pub current: u64,
+    pub peak: u64,
+}
+
+/// A snapshot of physical memory usage. All values are in frames.
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryStats {
+    pub free_frames: u64,
+    pub used_frames: u64,
+    pub peak_used_frames: u64,
+    categories: [CategoryStats; NUM_CATEGORIES],
+}
+
+impl MemoryStats {
+    pub fn category(&self, category: FrameCategory) -> CategoryStats {
+        self.categories[category as usize]
+    }
+
+    pub fn total_frames(&self) -> u64 {
+        self.free_frames + self.used_frames
+    }
+}
+
+fn frames_to_size(frames: u64) -> MemSize {
+    MemSize {
+        bytes: frames * page_size(),
+    }
+}
+
+impl Display for MemoryStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "MemTotal:       {}", frames_to_size(self.total_frames()))?;
+        writeln!(f, "MemFree:        {}", frames_to_size(self.free_frames))?;
+        writeln!(f, "MemUsed:        {}", frames_to_size(self.used_frames))?;
+        writeln!(
+            f,
+            "MemUsedPeak:    {}",
+            frames_to_size(self.peak_used_frames)
+        )?;
+        for category in FrameCategory::ALL {
+            let stats = self.category(category);
+            let name = category.name();
+            writeln!(
+                f,
+                "{}:{:pad$}{} (peak {})",
+                name,
+                "",
+                frames_to_size(stats.current),
+                frames_to_size(stats.peak),
+                pad = 15 - name.len()
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,606 @@
+use core::{
+    ptr::copy_nonoverlapping,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use aarch64_cpu::{asm::barrier, registers::TTBR0_EL1};
+use alloc::{sync::Arc, vec::Vec};
+use raspi::{
+    concurrency::{dummylock::RawDummylock, mutex::Mutex},
+    cpu::{core_id, NUM_CORES},
+    exception::esr::AccessType,
+    memory::page_table::{MemoryType, PageTable, Protection, VirtualAddr},
+};
+use tock_registers::interfaces::Writeable;
+
+use super::{
+    accounting::FrameCategory,
+    asid::{self, ASID_ALLOCATOR},
+    cache,
+    frame_cache::{self, FrameCache, FRAME_CACHE},
+    frame_ref, linear_map_start, phys_to_virt, tlb,
+    vma::{Vma, VmaKind, VmaList},
+};
+use crate::{fs, page_size};
+
+// Software use bit marking a read-only page whose area is writable, and that has to be copied before
+// it can be written to
+const COW_BIT: u8 = 0b0001;
+
+// The heap is private to the process, and never executable
+const HEAP_PROTECTION: Protection = Protection {
+    writable: true,
+    executable: false,
+    user: true,
+};
+
+/// End of the lower half. TTBR0 translates 48 bit addresses, so every user address is below this.
+pub const USER_SPACE_END: u64 = 1 << 48;
+
+/// Limits on the memory a single address space may use.
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryLimits {
+    /// Most bytes that may be covered by areas
+    pub max_virtual: u64,
+    /// Most pages that may be backed by frames at once
+    pub max_resident: u64,
+}
+
+impl MemoryLimits {
+    pub const DEFAULT: MemoryLimits = MemoryLimits {
+        max_virtual: 1 << 30,
+        max_resident: 0x8000,
+    };
+}
+
+/// A page table whose frames are allocated from the kernel's per-core frame caches.
+pub type KernelPageTable = PageTable<'static, RawDummylock, FrameCache>;
+
+// Context ID of the address space currently installed in each core's TTBR0
+static ACTIVE_CONTEXT: [AtomicU64; NUM_CORES] = [NO_CONTEXT; NUM_CORES];
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_CONTEXT: AtomicU64 = AtomicU64::new(0);
+
+// The address space each core is currently running in, as installed by switch_to
+static CURRENT_SPACE: [Mutex<Option<Arc<Mutex<AddressSpace>>>>; NUM_CORES] = [NO_SPACE; NUM_CORES];
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_SPACE: Mutex<Option<Arc<Mutex<AddressSpace>>>> = Mutex::new(None);
+
+/// Activates ```space``` on the calling core, and records it as the core's current address space.
+pub fn switch_to(space: Arc<Mutex<AddressSpace>>) {
+    space.lock().activate();
+    *CURRENT_SPACE[core_id() as usize].lock() = Some(space);
+}
+
+/// Returns the address space the calling core is currently running in.
+pub fn current() -> Option<Arc<Mutex<AddressSpace>>> {
+    CURRENT_SPACE[core_id() as usize].lock().clone()
+}
+
+/// A lower half (TTBR0) address space, tagged with an ASID.
+///
+/// The ASID is allocated lazily the first time the address space is activated, and is refreshed
+/// whenever the ASID allocator has moved on to a new generation.
+pub struct AddressSpace {
+    page_table: KernelPageTable,
+    context: AtomicU64,
+    vmas: VmaList,
+    limits: MemoryLimits,
+    // Number of pages currently backed by frames, counting shared pages in every sharer
+    resident_pages: u64,
+    // The heap grown by brk starts at brk_start and ends at brk, which need not be page aligned
+    brk_start: u64,
+    brk: u64,
+}
+
+impl AddressSpace {
+    /// Constructs a new, empty address space.
+    pub fn new() -> Result<Self, ()> {
+        let mut page_table = PageTable::new_with_offset(&FRAME_CACHE, linear_map_start())?;
+        page_table.set_non_global(true);
+
+        Ok(AddressSpace {
+            page_table,
+            context: AtomicU64::new(0),
+            vmas: VmaList::new(),
+            limits: MemoryLimits::DEFAULT,
+            resident_pages: 0,
+            brk_start: 0,
+            brk: 0,
+        })
+    }
+
+    pub fn vmas(&self) -> &VmaList {
+        &self.vmas
+    }
+
+    pub fn limits(&self) -> MemoryLimits {
+        self.limits
+    }
+
+    /// Replaces the limits. Memory already in use beyond the new limits is not taken away, but no
+    /// more is handed out until usage drops below them.
+    pub fn set_limits(&mut self, limits: MemoryLimits) {
+        self.limits = limits;
+    }
+
+    /// Returns the number of pages currently backed by frames.
+    pub fn resident_pages(&self) -> u64 {
+        self.resident_pages
+    }
+
+    /// Reserves ```start..start + size``` as anonymous memory with the given protection.
+    ///
+    /// No frames are allocated up front. Each page is backed by a zeroed frame the first time it is
+    /// accessed. Returns Err if the range is not page aligned, overlaps an existing area, or would
+    /// exceed the virtual memory limit.
+    pub fn map_anonymous(
+        &mut self,
+        start: u64,
+        size: u64,
+        protection: Protection,
+    ) -> Result<(), ()> {
+        self.map(start, size, protection, VmaKind::Anonymous)
+    }
+
+    /// Reserves ```start..start + size``` as backed by ```kind```, with the given protection.
+    ///
+    /// See ```map_anonymous```.
+    pub fn map(
+        &mut self,
+        start: u64,
+        size: u64,
+        protection: Protection,
+        kind: VmaKind,
+    ) -> Result<(), ()> {
+        let end = start.checked_add(size).ok_or(())?;
+        if end > USER_SPACE_END || self.vmas.total_size() + size > self.limits.max_virtual {
+            return Err(());
+        }
+        self.vmas.insert(Vma {
+            start,
+            end,
+            protection,
+            kind,
+        })
+    }
+
+    /// Like ```map```, but first removes anything mapped inside the range, as ```unmap``` does.
+    ///
+    /// Returns Err without changing anything if the new area would cover the null page, not fit the
+    /// user range or exceed the virtual memory limit.
+    pub fn map_replacing(
+        &mut self,
+        start: u64,
+        size: u64,
+        protection: Protection,
+        kind: VmaKind,
+    ) -> Result<(), ()> {
+        let end = start.checked_add(size).ok_or(())?;
+        let replaced = self.vmas.size_in(start, end);
+        if start < page_size()
+            || end > USER_SPACE_END
+            || self.vmas.total_size() - replaced + size > self.limits.max_virtual
+        {
+            return Err(());
+        }
+        self.unmap(start, end)?;
+        self.map(start, size, protection, kind)
+    }
+
+    /// Returns the highest page aligned address below ```high``` and at or above ```low``` where
+    /// ```size``` bytes can be mapped.
+    pub fn find_free(&self, size: u64, low: u64, high: u64) -> Option<u64> {
+        self.vmas.find_gap(size, low, high)
+    }
+
+    /// Removes every area inside ```start..end```, freeing the frames backing them. Areas straddling
+    /// either end are split. Parts of the range that are not mapped are ignored.
+    pub fn unmap(&mut self, start: u64, end: u64) -> Result<(), ()> {
+        if start % page_size() != 0 || end % page_size() != 0 || start > end {
+            return Err(());
+        }
+        for vma in self.vmas.remove_range(start, end) {
+            self.release_pages(vma.start, vma.end);
+        }
+        self.flush_range(start, end);
+        Ok(())
+    }
+
+    /// Changes the protection of every area inside ```start..end```, along with the pages that are
+    /// already present. Copy-on-write pages stay read-only until they are written to.
+    ///
+    /// Returns Err without changing anything if part of the range is not covered by an area.
+    pub fn protect(&mut self, start: u64, end: u64, protection: Protection) -> Result<(), ()> {
+        if start % page_size() != 0 || end % page_size() != 0 || start > end {
+            return Err(());
+        }
+        self.vmas.protect_range(start, end, protection)?;
+        for page in (start..end).step_by(page_size() as usize) {
+            let entry = match self.page_table.page_entry(VirtualAddr(page)) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let mut page_protection = protection;
+            if entry.software_bits & COW_BIT != 0 {
+                page_protection.writable = false;
+            }
+            // Only pages that are present are walked, so this cannot fail
+            self.page_table
+                .protect_page(VirtualAddr(page), page_protection)?;
+        }
+        self.flush_range(start, end);
+        Ok(())
+    }
+
+    /// Places the start of the heap at ```addr```, which is where the heap begins growing from.
+    /// Meant to be called once, right after loading a program.
+    pub fn set_brk_base(&mut self, addr: u64) {
+        self.brk_start = addr;
+        self.brk = addr;
+    }
+
+    /// Returns the current end of the heap.
+    pub fn brk(&self) -> u64 {
+        self.brk
+    }
+
+    /// Moves the end of the heap to ```brk```, mapping or unmapping the pages in between.
+    ///
+    /// Returns Err if the heap would shrink below its start, collide with another area or exceed
+    /// the virtual memory limit, in which case the heap is left as it was.
+    pub fn set_brk(&mut self, brk: u64) -> Result<(), ()> {
+        if brk < self.brk_start || brk > USER_SPACE_END {
+            return Err(());
+        }
+        let old_end = self.brk.next_multiple_of(page_size());
+        let new_end = brk.next_multiple_of(page_size());
+
+        if new_end > old_end {
+            let size = new_end - old_end;
+            if self.vmas.total_size() + size > self.limits.max_virtual {
+                return Err(());
+            }
+            if old_end == self.brk_start {
+                self.map_anonymous(old_end, size, HEAP_PROTECTION)?;
+            } else {
+                self.vmas.resize(self.brk_start, new_end)?;
+            }
+        } else if new_end < old_end {
+            self.unmap(new_end, old_end)?;
+        }
+
+        self.brk = brk;
+        Ok(())
+    }
+
+    /// Attempts to resolve a page fault on ```addr``` caused by an access of type ```access```.
+    ///
+    /// Returns Err if ```addr``` is not covered by an area that permits the access, in which case the
+    /// access is genuinely invalid.
+    pub fn handle_fault(&mut self, addr: u64, access: AccessType) -> Result<(), ()> {
+        let vma = self.vmas.find(addr).ok_or(())?.clone();
+        if !vma.protection.user {
+            return Err(());
+        }
+        let allowed = match access {
+            AccessType::Read => true,
+            AccessType::Write => vma.protection.writable,
+            AccessType::Execute => vma.protection.executable,
+        };
+        if !allowed {
+            return Err(());
+        }
+
+        let page = addr - (addr % page_size());
+        if let Some(entry) = self.page_table.page_entry(VirtualAddr(page)) {
+            // The page is present and its area permits the access, so the only thing left that can
+            // be resolved is a write to a copy-on-write page
+            if access == AccessType::Write && entry.software_bits & COW_BIT != 0 {
+                return self.break_cow(page, entry.phys_addr, vma.protection);
+            }
+            return Err(());
+        }
+
+        self.populate_page(page, &vma)
+    }
+
+    // Backs the missing page at ```page``` according to its area
+    fn populate_page(&mut self, page: u64, vma: &Vma) -> Result<(), ()> {
+        // The limit is checked before anything is taken from the frame allocator
+        if self.resident_pages >= self.limits.max_resident {
+            return Err(());
+        }
+
+        // Frames are always zeroed by the allocator
+        let frame = frame_cache::allocate_frame(FrameCategory::User)?;
+        let filled = match &vma.kind {
+            VmaKind::Anonymous => Ok(()),
+            VmaKind::File { path, offset } => {
+                // SAFETY: The frame was just allocated, and is mapped in the linear map
+                let contents = unsafe {
+                    core::slice::from_raw_parts_mut(
+                        phys_to_virt(frame) as *mut u8,
+                        page_size() as usize,
+                    )
+                };
+                fs::read_at(path, offset + (page - vma.start), contents).map(|_| ())
+            }
+        };
+        if vma.protection.executable {
+            cache::sync_instructions(phys_to_virt(frame), page_size());
+        }
+        let mapped = filled.map_err(|_| ()).and_then(|_| {
+            self.page_table.map_page_with_protection(
+                frame,
+                VirtualAddr(page),
+                MemoryType::NORMAL_CACHEABLE,
+                vma.protection,
+            )
+        });
+        if mapped.is_err() {
+            frame_cache::deallocate_frame(frame, FrameCategory::User);
+            return Err(());
+        }
+        frame_ref::acquire(frame);
+        self.resident_pages += 1;
+
+        // An invalid descriptor is never cached in the TLB, so no invalidation is needed
+        Ok(())
+    }
+
+    // Unmaps the present pages in start..end and releases their frames. The TLB is not flushed
+    fn release_pages(&mut self, start: u64, end: u64) {
+        for page in (start..end).step_by(page_size() as usize) {
+            if let Ok(frame) = self.page_table.unmap_page(VirtualAddr(page)) {
+                frame_ref::release(frame);
+                self.resident_pages -= 1;
+            }
+        }
+    }
+
+    /// Returns the physical address ```addr``` is mapped to, or None if its page is not resident.
+    pub fn translate(&self, addr: u64) -> Option<u64> {
+        let page = addr - (addr % page_size());
+        let entry = self.page_table.page_entry(VirtualAddr(page))?;
+        Some(entry.phys_addr + addr % page_size())
+    }
+
+    /// Copies ```data``` to ```addr``` in this address space, regardless of whether the address space
+    /// is active and of the protection of the pages. Missing pages are populated first.
+    ///
+    /// This is meant for the kernel setting up an address space, such as when loading a program.
+    /// Code written to executable areas is made visible to instruction fetches. Returns Err if the range is not covered by areas, or if populating a page failed.
+    pub fn write(&mut self, addr: u64, data: &[u8]) -> Result<(), ()> {
+        let end = addr.checked_add(data.len() as u64).ok_or(())?;
+        let mut current = addr;
+        while current < end {
+            let page = current - (current % page_size());
+            let chunk_end = end.min(page + page_size());
+
+            let vma = self.vmas.find(page).ok_or(())?.clone();
+            let frame = match self.page_table.page_entry(VirtualAddr(page)) {
+                // A shared page must not be written in place
+                Some(entry) if entry.software_bits & COW_BIT != 0 => {
+                    self.break_cow(page, entry.phys_addr, vma.protection)?;
+                    self.page_table.virt_to_phys(VirtualAddr(page))?
+                }
+                Some(entry) => entry.phys_addr,
+                None => {
+                    self.populate_page(page, &vma)?;
+                    self.page_table.virt_to_phys(VirtualAddr(page))?
+                }
+            };
+
+            let offset = (current - addr) as usize;
+            let len = (chunk_end - current) as usize;
+            let target = phys_to_virt(frame) + current - page;
+            // The page may have been written through its cacheable mapping already
+            cache::clean_invalidate(target, len as u64);
+            // SAFETY: The frame is mapped in the linear map, and owned by this address space
+            unsafe {
+                copy_nonoverlapping(data[offset..].as_ptr(), target as *mut u8, len);
+            }
+            if vma.protection.executable {
+                cache::sync_instructions(target, len as u64);
+            }
+            current = chunk_end;
+        }
+
+        Ok(())
+    }
+
+    /// Creates a copy of this address space that shares all of its present pages.
+    ///
+    /// Writable pages are made read-only and copy-on-write in both address spaces, so that they
+    /// are only copied once either side writes to them.
+    pub fn fork(&mut self) -> Result<AddressSpace, ()> {
+        let mut child = AddressSpace::new()?;
+        child.limits = self.limits;
+        child.brk_start = self.brk_start;
+        child.brk = self.brk;
+        let shared = self.share_pages(&mut child);
+        // Pages that were made copy-on-write may still be cached as writable, even if sharing failed
+        // part of the way through
+        if let Some(asid) = self.asid() {
+            tlb::flush_asid(asid);
+        }
+
+        shared.map(|_| child)
+    }
+
+    fn share_pages(&mut self, child: &mut AddressSpace) -> Result<(), ()> {
+        for vma in self.vmas.iter() {
+            child.vmas.insert(vma.clone())?;
+            for page in (vma.start..vma.end).step_by(page_size() as usize) {
+                let entry = match self.page_table.page_entry(VirtualAddr(page)) {
+                    Some(entry) => entry,
+                    None => continue,
+                };
+
+                let mut protection = entry.protection;
+                let mut software_bits = entry.software_bits;
+                if protection.writable {
+                    protection.writable = false;
+                    software_bits |= COW_BIT;
+                    self.page_table
+                        .protect_page(VirtualAddr(page), protection)?;
+                    self.page_table
+                        .set_software_bits(VirtualAddr(page), software_bits)?;
+                }
+
+                // Take the reference first, so that if mapping fails the child can never release
+                // a reference it does not hold
+                frame_ref::acquire(entry.phys_addr);
+                let mapped = child.page_table.map_page_with_protection(
+                    entry.phys_addr,
+                    VirtualAddr(page),
+                    MemoryType::NORMAL_CACHEABLE,
+                    protection,
+                );
+                if mapped.is_err() {
+                    frame_ref::release(entry.phys_addr);
+                    return Err(());
+                }
+                child.resident_pages += 1;
+                child
+                    .page_table
+                    .set_software_bits(VirtualAddr(page), software_bits)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Gives this address space its own, writable copy of a copy-on-write page
+    fn break_cow(&mut self, page: u64, frame: u64, protection: Protection) -> Result<(), ()> {
+        if frame_ref::count(frame) == 1 {
+            // Every other sharer has already made its own copy, so the frame can be reused in place
+            self.page_table
+                .protect_page(VirtualAddr(page), protection)?;
+            self.page_table.set_software_bits(VirtualAddr(page), 0)?;
+            self.flush_page(page);
+            return Ok(());
+        }
+
+        let copy = frame_cache::allocate_frame(FrameCategory::User)?;
+        // The newest contents of the shared frame may still be in the cache
+        cache::clean_invalidate(phys_to_virt(frame), page_size());
+        unsafe {
+            copy_nonoverlapping(
+                phys_to_virt(frame) as *const u8,
+                phys_to_virt(copy) as *mut u8,
+                page_size() as usize,
+            );
+        }
+        if protection.executable {
+            cache::sync_instructions(phys_to_virt(copy), page_size());
+        }
+        frame_ref::acquire(copy);
+
+        // Replacing a descriptor that was just walked cannot fail, since all of its tables exist
+        self.page_table
+            .unmap_page(VirtualAddr(page))
+            .expect("Failed to unmap copy-on-write page");
+        self.page_table
+            .map_page_with_protection(
+                copy,
+                VirtualAddr(page),
+                MemoryType::NORMAL_CACHEABLE,
+                protection,
+            )
+            .expect("Failed to map copied page");
+        self.flush_page(page);
+
+        // Only release the shared frame once the old translation can no longer be used
+        frame_ref::release(frame);
+        Ok(())
+    }
+
+    fn flush_page(&self, page: u64) {
+        // An address space without an ASID has never been activated, so there are no TLB entries
+        // to invalidate
+        if let Some(asid) = self.asid() {
+            tlb::flush_page_asid(page, asid);
+        }
+    }
+
+    fn flush_range(&self, start: u64, end: u64) {
+        // See flush_page
+        if let Some(asid) = self.asid() {
+            tlb::flush_range(start, end, Some(asid));
+        }
+    }
+
+    pub fn page_table(&self) -> &KernelPageTable {
+        &self.page_table
+    }
+
+    pub fn page_table_mut(&mut self) -> &mut KernelPageTable {
+        &mut self.page_table
+    }
+
+    /// Returns the ASID this address space last ran with, if it was ever activated.
+    ///
+    /// The ASID may belong to a generation that has since been rolled over, but cores that have
+    /// not switched address spaces since can still hold TLB entries tagged with it. Invalidating
+    /// by a stale ASID at worst removes entries of another address space, which are reloaded on
+    /// demand.
+    pub fn asid(&self) -> Option<u16> {
+        let context = self.context.load(Ordering::Acquire);
+        match context {
+            0 => None,
+            _ => Some(asid::asid_of(context)),
+        }
+    }
+
+    /// Installs this address space in TTBR0 of the calling core.
+    ///
+    /// No TLB maintenance is performed unless the ASID allocator started a new generation since
+    /// this core last switched address spaces.
+    pub fn activate(&self) {
+        let mut context = self.context.load(Ordering::Acquire);
+        if !asid::is_current(context) {
+            context = ASID_ALLOCATOR.lock().refresh(context);
+            self.context.store(context, Ordering::Release);
+        }
+
+        let active = &ACTIVE_CONTEXT[core_id() as usize];
+        if active.load(Ordering::Relaxed) != context {
+            TTBR0_EL1.write(
+                TTBR0_EL1::ASID.val(asid::asid_of(context) as u64)
+                    + TTBR0_EL1::BADDR.val(self.page_table.as_raw_ptr() as u64 >> 1),
+            );
+            barrier::isb(barrier::SY);
+            active.store(context, Ordering::Relaxed);
+        }
+        asid::flush_if_pending();
+    }
+}
+
+impl Drop for AddressSpace {
+    /// Releases the ASID of this address space, invalidating any TLB entries tagged with it so
+    /// the ASID can safely be handed out again.
+    ///
+    /// The address space must not be active on any core when it is dropped.
+    fn drop(&mut self) {
+        let context = self.context.load(Ordering::Acquire);
+        if let Some(asid) = self.asid() {
+            tlb::flush_asid(asid);
+        }
+        // Make sure a future address space that is given the same context ID is never mistaken
+        // for the one that is already installed
+        for active in &ACTIVE_CONTEXT {
+            let _ = active.compare_exchange(context, 0, Ordering::Relaxed, Ordering::Relaxed);
+        }
+        ASID_ALLOCATOR.lock().free(context);
+
+        // The page table only frees its own tables, so release the frames backing each area
+        let areas: Vec<(u64, u64)> = self.vmas.iter().map(|vma| (vma.start, vma.end)).collect();
+        for (start, end) in areas {
+            self.release_pages(start, end);
+        }
+    }
+}
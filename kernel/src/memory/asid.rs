@@ -0,0 +1,138 @@
+//! Allocation of Address Space IDentifiers (ASIDs)
+//!
+//! Every user address space is tagged with an ASID, so that switching TTBR0 does not require
+//! invalidating the TLB. ASIDs are handed out from a bitmap until it runs dry. At that point the
+//! allocator starts a new generation: the bitmap is wiped, and every core is told to invalidate its
+//! local TLB the next time it switches address spaces. Address spaces remember the generation
+//! their ASID was allocated in, and lazily request a fresh one once that generation is stale.
+//!
+//! An address space may keep running with an ASID from a stale generation until its core switches
+//! away from it, so TLB maintenance always targets the ASID a space last ran with.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use aarch64_cpu::registers::ID_AA64MMFR0_EL1;
+use alloc::{vec, vec::Vec};
+use generic_once_cell::Lazy;
+use raspi::{
+    concurrency::mutex::{Mutex, RawMutex},
+    cpu::{core_id, NUM_CORES},
+};
+use tock_registers::interfaces::Readable;
+
+use super::tlb;
+
+// A context ID packs the generation of an ASID above the ASID itself. Context ID 0 is never
+// valid, as generations start at 1.
+const ASID_BITS: u64 = 16;
+const ASID_MASK: u64 = (1 << ASID_BITS) - 1;
+
+pub static ASID_ALLOCATOR: Lazy<RawMutex, Mutex<AsidAllocator>> =
+    Lazy::new(|| Mutex::new(AsidAllocator::new()));
+
+// Mirror of the allocator's generation, so the common case of switching to an address space with a
+// valid ASID never needs to take the allocator lock
+static CURRENT_GENERATION: AtomicU64 = AtomicU64::new(1);
+
+// Set for every core when a new generation starts. The core must invalidate its local TLB before
+// running with an ASID from the new generation.
+static FLUSH_PENDING: [AtomicBool; NUM_CORES] = [INIT; NUM_CORES];
+
+#[allow(clippy::declare_interior_mutable_const)]
+const INIT: AtomicBool = AtomicBool::new(false);
+
+pub struct AsidAllocator {
+    generation: u64,
+    num_asids: u64,
+    next: u64,
+    bitmap: Vec<u64>,
+}
+
+impl AsidAllocator {
+    fn new() -> Self {
+        let num_asids = match ID_AA64MMFR0_EL1.read(ID_AA64MMFR0_EL1::ASIDBits) {
+            0b0010 => 1 << 16,
+            _ => 1 << 8,
+        };
+        let mut allocator = AsidAllocator {
+            generation: 1,
+            num_asids,
+            next: 1,
+            bitmap: vec![0; (num_asids / 64) as usize],
+        };
+        // ASID 0 is reserved for the kernel's own empty TTBR0 table
+        allocator.bitmap[0] = 1;
+        allocator
+    }
+
+    /// Returns a context ID that is valid for the current generation.
+    ///
+    /// If ```context``` already belongs to the current generation it is returned unchanged,
+    /// otherwise a new ASID is allocated, starting a new generation if necessary.
+    pub fn refresh(&mut self, context: u64) -> u64 {
+        if context != 0 && generation_of(context) == self.generation {
+            return context;
+        }
+
+        let asid = match self.find_free() {
+            Some(asid) => asid,
+            None => {
+                self.rollover();
+                self.find_free()
+                    .expect("Failed to allocate an ASID after starting a new generation")
+            }
+        };
+        self.bitmap[(asid / 64) as usize] |= 1 << (asid % 64);
+        self.next = asid + 1;
+
+        (self.generation << ASID_BITS) | asid
+    }
+
+    /// Returns the ASID of ```context``` to the allocator. Context IDs from a previous generation
+    /// are ignored, since their ASIDs were already reclaimed by the rollover.
+    pub fn free(&mut self, context: u64) {
+        if context != 0 && generation_of(context) == self.generation {
+            let asid = context & ASID_MASK;
+            self.bitmap[(asid / 64) as usize] &= !(1 << (asid % 64));
+        }
+    }
+
+    fn find_free(&self) -> Option<u64> {
+        (self.next..self.num_asids)
+            .chain(1..self.next)
+            .find(|asid| self.bitmap[(asid / 64) as usize] & (1 << (asid % 64)) == 0)
+    }
+
+    fn rollover(&mut self) {
+        self.generation += 1;
+        self.bitmap.fill(0);
+        self.bitmap[0] = 1;
+        self.next = 1;
+
+        for flag in &FLUSH_PENDING {
+            flag.store(true, Ordering::Release);
+        }
+        CURRENT_GENERATION.store(self.generation, Ordering::Release);
+    }
+}
+
+fn generation_of(context: u64) -> u64 {
+    context >> ASID_BITS
+}
+
+/// Extracts the ASID that should be written to TTBR0_EL1 from a context ID.
+pub fn asid_of(context: u64) -> u16 {
+    (context & ASID_MASK) as u16
+}
+
+/// Returns true if ```context``` holds an ASID from the current generation.
+pub fn is_current(context: u64) -> bool {
+    context != 0 && generation_of(context) == CURRENT_GENERATION.load(Ordering::Acquire)
+}
+
+/// Invalidates the local TLB if a generation rollover happened since this core last did so.
+pub fn flush_if_pending() {
+    if FLUSH_PENDING[core_id() as usize].swap(false, Ordering::AcqRel) {
+        tlb::flush_local();
+    }
+}
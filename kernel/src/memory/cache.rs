@@ -0,0 +1,41 @@
+//! Data and instruction cache maintenance
+//!
+//! The bootloader maps physical memory into the linear map as Device memory, so the kernel reads and
+//! writes frames around the data cache, while processes access the same frames through cacheable
+//! mappings. Lines that a cacheable mapping left in the data cache would hide what the kernel wrote,
+//! or overwrite it once they are evicted, so they have to be written back and dropped before the
+//! kernel accesses a frame. Instruction fetches do not look at the data cache at all, so frames that
+//! code was written to also need the instruction caches invalidated before the code runs.
+
+use core::arch::asm;
+
+// Smallest data cache line in the system, from CTR_EL0.DminLine, which counts 4 byte words as log2
+fn dcache_line_size() -> u64 {
+    let ctr: u64;
+    unsafe { asm!("mrs {ctr}, ctr_el0", ctr = out(reg) ctr, options(nomem, nostack)) };
+    4 << ((ctr >> 16) & 0xF)
+}
+
+/// Writes back and invalidates the data cache lines covering ```virt..virt + len``` up to the point
+/// of coherency, so that the memory behind them can be accessed through the linear map.
+pub fn clean_invalidate(virt: u64, len: u64) {
+    let line = dcache_line_size();
+    let start = virt & !(line - 1);
+    for addr in (start..virt + len).step_by(line as usize) {
+        unsafe { asm!("DC CIVAC, {addr}", addr = in(reg) addr, options(nostack)) };
+    }
+    unsafe { asm!("DSB SY", options(nostack)) };
+}
+
+/// Makes code written to ```virt..virt + len``` visible to instruction fetches on every core.
+///
+/// The data cache is cleaned to the point of unification and the instruction caches of all cores
+/// are invalidated, since a process may run on any of them.
+pub fn sync_instructions(virt: u64, len: u64) {
+    let line = dcache_line_size();
+    let start = virt & !(line - 1);
+    for addr in (start..virt + len).step_by(line as usize) {
+        unsafe { asm!("DC CVAU, {addr}", addr = in(reg) addr, options(nostack)) };
+    }
+    unsafe { asm!("DSB ISH", "IC IALLUIS", "DSB ISH", "ISB", options(nostack)) };
+}
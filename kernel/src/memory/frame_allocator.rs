@@ -1,8 +1,16 @@
-use raspi::memory::{page_frame_allocator::PageFrameAllocator, page_table::PageAlloc};
+use raspi::memory::page_frame_allocator::PageFrameAllocator;
 
+use super::{cache, phys_to_virt, virt_to_phys};
 use crate::page_size;
-use core::ptr::write_bytes;
 
+/// The kernel's global pool of free physical frames.
+///
+/// Frames are handed out and accepted as physical addresses, but the underlying freelist is
+/// accessed through the linear mapping of physical memory, so the allocator keeps working after
+/// the identity mapping in TTBR0 has been removed.
+///
+/// The pool is only touched in batches by the per-core caches in ```frame_cache```, which are what
+/// the rest of the kernel allocates frames from.
 pub struct FrameAlloc(PageFrameAllocator);
 impl FrameAlloc {
     pub fn new() -> Self {
@@ -11,22 +19,19 @@ impl FrameAlloc {
     pub fn num_free_frames(&self) -> u64 {
         self.0.num_free_frames()
     }
-}
-impl PageAlloc for FrameAlloc {
-    fn allocate_frame(&mut self) -> Result<*mut u8, ()> {
-        let frame = self
-            .0
-            .alloc_frame()
-            .expect("Bootloader ran out of physical frames to allocate!")
-            as *mut u8;
 
-        unsafe {
-            write_bytes(frame as *mut u8, 0, page_size() as usize);
-        }
-        Ok(frame)
+    /// Adds a frame to the pool of free frames.
+    pub fn add_frame(&mut self, frame: u64) -> Result<(), ()> {
+        // Lines the frame left in the cache must not overwrite the freelist link once evicted
+        cache::clean_invalidate(phys_to_virt(frame), page_size());
+        self.0.free_frame(phys_to_virt(frame) as *mut u64)
     }
 
-    fn deallocate_frame(&mut self, frame: *mut u8) {
-        let _ = self.0.free_frame(frame as *mut u64);
+    /// Removes a frame from the pool. The contents of the frame are undefined.
+    pub fn take_frame(&mut self) -> Result<u64, ()> {
+        // Running out of frames is not fatal for the kernel, callers such as the heap can
+        // recover from it
+        let frame = self.0.alloc_frame()?;
+        Ok(virt_to_phys(frame as u64))
     }
 }
@@ -0,0 +1,165 @@
+//! Per-core caches of free frames
+//!
+//! Every core keeps a small magazine of free frames that only it ever touches, so the common case
+//! of allocating or freeing a frame takes no lock at all. When a magazine runs empty it is refilled
+//! with a batch of frames from the ```FRAME_ALLOCATOR```, and when it fills up half of it is drained
+//! back, so the global lock is only taken once per batch.
+
+use core::{
+    cell::UnsafeCell,
+    ptr::write_bytes,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use raspi::{
+    concurrency::dummylock::Dummylock,
+    cpu::{core_id, NUM_CORES},
+    memory::page_table::PageAlloc,
+};
+
+use super::{
+    accounting::{FrameCategory, ACCOUNTING},
+    cache, phys_to_virt, FRAME_ALLOCATOR,
+};
+use crate::page_size;
+
+const MAGAZINE_SIZE: usize = 64;
+// Number of frames moved between a magazine and the global allocator at once
+const BATCH_SIZE: usize = MAGAZINE_SIZE / 2;
+
+struct Magazine {
+    frames: UnsafeCell<[u64; MAGAZINE_SIZE]>,
+    // Only written by the owning core, but read by any core gathering statistics
+    count: AtomicUsize,
+}
+
+// SAFETY: The frames of a magazine are only ever accessed by the core that owns it
+unsafe impl Sync for Magazine {}
+
+impl Magazine {
+    const fn new() -> Self {
+        Magazine {
+            frames: UnsafeCell::new([0; MAGAZINE_SIZE]),
+            count: AtomicUsize::new(0),
+        }
+    }
+
+    // SAFETY: Must only be called on the calling core's own magazine, and not be reentered from an
+    // exception handler on the same core
+    unsafe fn pop(&self) -> Option<u64> {
+        let count = self.count.load(Ordering::Relaxed);
+        if count == 0 {
+            return None;
+        }
+        let frame = (*self.frames.get())[count - 1];
+        self.count.store(count - 1, Ordering::Relaxed);
+        Some(frame)
+    }
+
+    // SAFETY: See pop
+    unsafe fn push(&self, frame: u64) -> Result<(), ()> {
+        let count = self.count.load(Ordering::Relaxed);
+        if count == MAGAZINE_SIZE {
+            return Err(());
+        }
+        (*self.frames.get())[count] = frame;
+        self.count.store(count + 1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // SAFETY: See pop
+    unsafe fn refill(&self) {
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        for _ in 0..BATCH_SIZE {
+            match allocator.take_frame() {
+                Ok(frame) => self.push(frame).expect("Refilled a full frame magazine"),
+                Err(_) => break,
+            }
+        }
+    }
+
+    // SAFETY: See pop
+    unsafe fn drain(&self, num_frames: usize) {
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        for _ in 0..num_frames {
+            match self.pop() {
+                Some(frame) => {
+                    let _ = allocator.add_frame(frame);
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: Magazine = Magazine::new();
+static MAGAZINES: [Magazine; NUM_CORES] = [EMPTY; NUM_CORES];
+
+/// Returns a new, zero-initialized frame, charged to ```category```.
+///
+/// Frames are allocated from the calling core's cache, which is refilled from the
+/// ```FRAME_ALLOCATOR``` when it runs empty. Returns Err if physical memory is exhausted.
+pub fn allocate_frame(category: FrameCategory) -> Result<u64, ()> {
+    let magazine = &MAGAZINES[core_id() as usize];
+    // SAFETY: The magazine belongs to this core. The only exception handler that allocates frames
+    // is the page fault handler, and magazine operations never fault
+    let frame = unsafe {
+        match magazine.pop() {
+            Some(frame) => frame,
+            None => {
+                magazine.refill();
+                magazine.pop().ok_or(())?
+            }
+        }
+    };
+
+    // The previous owner of the frame may have left lines in the cache through a cacheable mapping
+    cache::clean_invalidate(phys_to_virt(frame), page_size());
+    unsafe {
+        write_bytes(phys_to_virt(frame) as *mut u8, 0, page_size() as usize);
+    }
+    ACCOUNTING.charge(category, 1);
+    Ok(frame)
+}
+
+/// Frees a frame that was allocated with ```allocate_frame``` using the same ```category```.
+pub fn deallocate_frame(frame: u64, category: FrameCategory) {
+    ACCOUNTING.uncharge(category, 1);
+    let magazine = &MAGAZINES[core_id() as usize];
+    // SAFETY: See allocate_frame
+    unsafe {
+        if magazine.push(frame).is_err() {
+            magazine.drain(BATCH_SIZE);
+            magazine
+                .push(frame)
+                .expect("Frame magazine is still full after draining");
+        }
+    }
+}
+
+/// Returns the number of free frames currently held in the per-core caches.
+pub fn num_cached_frames() -> u64 {
+    MAGAZINES
+        .iter()
+        .map(|magazine| magazine.count.load(Ordering::Relaxed) as u64)
+        .sum()
+}
+
+/// Frame source for kernel page tables, backed by the per-core caches.
+///
+/// ```PageTable``` expects its allocator behind a lock, but the caches need no locking, so the
+/// handle is wrapped in a ```Dummylock```. Frames allocated through it are charged as page tables.
+pub struct FrameCache;
+
+impl PageAlloc for FrameCache {
+    fn allocate_frame(&mut self) -> Result<*mut u8, ()> {
+        allocate_frame(FrameCategory::PageTable).map(|frame| frame as *mut u8)
+    }
+
+    fn deallocate_frame(&mut self, frame: *mut u8) {
+        deallocate_frame(frame as u64, FrameCategory::PageTable);
+    }
+}
+
+pub static FRAME_CACHE: Dummylock<FrameCache> = Dummylock::new(FrameCache);
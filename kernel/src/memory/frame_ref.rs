@@ -0,0 +1,69 @@
+//! Reference counts for frames shared between address spaces
+//!
+//! The ```FRAME_ALLOCATOR``` only knows whether a frame is free or not. Frames that are mapped into
+//! lower half address spaces can be shared, for example by copy-on-write mappings after a fork, so
+//! each of those frames carries a count of the mappings referring to it. The frame is only returned
+//! to the allocator once the last mapping is released.
+//!
+//! Frames used only by the kernel itself are not reference counted, and their count stays at zero.
+
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use generic_once_cell::OnceCell;
+use raspi::concurrency::mutex::RawMutex;
+
+use super::{accounting::FrameCategory, frame_cache, vmalloc::vmalloc};
+use crate::page_size;
+
+static FRAME_REFS: OnceCell<RawMutex, &'static [AtomicU16]> = OnceCell::new();
+
+/// Allocates a reference count for every frame of physical memory below ```mem_size```.
+///
+/// Must be called exactly once, after the kernel heap has been initialized.
+pub fn init(mem_size: u64) -> Result<(), ()> {
+    let num_frames = (mem_size / page_size()) as usize;
+    let counts = vmalloc(num_frames * core::mem::size_of::<AtomicU16>()).ok_or(())?;
+    // SAFETY: vmalloc returns zeroed memory, which is a valid AtomicU16 of value zero, and the
+    // allocation is never freed
+    let counts =
+        unsafe { core::slice::from_raw_parts(counts.as_ptr() as *const AtomicU16, num_frames) };
+    FRAME_REFS.set(counts).map_err(|_| ())
+}
+
+fn count_of(frame: u64) -> &'static AtomicU16 {
+    let counts = FRAME_REFS
+        .get()
+        .expect("Attempted to reference count a frame before frame_ref was initialized");
+    &counts[(frame / page_size()) as usize]
+}
+
+/// Records a new mapping of ```frame```, and returns the new number of references.
+pub fn acquire(frame: u64) -> u16 {
+    let previous = count_of(frame).fetch_add(1, Ordering::Relaxed);
+    assert!(
+        previous != u16::MAX,
+        "Frame {:#x} has too many references",
+        frame
+    );
+    previous + 1
+}
+
+/// Drops a mapping of ```frame```, returning it to the frame allocator if it was the last one.
+///
+/// Returns true if the frame was freed.
+pub fn release(frame: u64) -> bool {
+    let previous = count_of(frame).fetch_sub(1, Ordering::Release);
+    assert!(previous != 0, "Released unreferenced frame {:#x}", frame);
+    if previous == 1 {
+        // Make sure every other owner is done with the frame before it is handed out again
+        core::sync::atomic::fence(Ordering::Acquire);
+        frame_cache::deallocate_frame(frame, FrameCategory::User);
+        return true;
+    }
+    false
+}
+
+/// Returns the number of mappings currently referring to ```frame```.
+pub fn count(frame: u64) -> u16 {
+    count_of(frame).load(Ordering::Acquire)
+}
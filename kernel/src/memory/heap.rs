@@ -0,0 +1,220 @@
+//! The kernel heap
+//!
+//! The heap lives in its own reserved region of the higher half, and is backed by frames that are
+//! mapped on demand. It is made up of a stack of arenas, each of which is a contiguous run of mapped
+//! pages managed by its own linked list allocator. When no arena can satisfy an allocation, a new
+//! arena is mapped directly above the current top of the heap. When enough empty arenas pile up at
+//! the top of the heap, they are unmapped and their frames are returned to the frame allocator.
+
+use core::{
+    alloc::{Allocator, Layout},
+    fmt::Display,
+    ptr::NonNull,
+};
+
+use allocators::allocators::linked_list_allocator::LinkedListAlloc;
+use arrayvec::ArrayVec;
+use raspi::{concurrency::dummylock::RawDummylock, memory::mem_size::MemSize};
+
+use super::{accounting::FrameCategory, map_kernel_range, unmap_kernel_range, KERNEL_PAGE_TABLE};
+use crate::page_size;
+
+/// Start of the virtual region reserved for the kernel heap, the second lvl0 entry of TTBR1.
+pub const KERNEL_HEAP_START: u64 = 0xFFFF008000000000;
+/// The kernel heap will never grow beyond this many bytes.
+pub const KERNEL_HEAP_MAX_SIZE: u64 = 0x40000000;
+
+const INITIAL_SIZE: u64 = 0x200000;
+// Minimum size of a new arena
+const GROWTH_SIZE: u64 = 0x40000;
+// Empty arenas at the top of the heap are only unmapped once they add up to at least this many
+// bytes, to avoid repeatedly mapping and unmapping memory at the boundary
+const SHRINK_THRESHOLD: u64 = 0x100000;
+const MAX_ARENAS: usize = 64;
+
+struct Arena {
+    start: u64,
+    end: u64,
+    allocator: LinkedListAlloc<RawDummylock>,
+    allocated: u64,
+}
+
+impl Arena {
+    fn contains(&self, addr: u64) -> bool {
+        (self.start..self.end).contains(&addr)
+    }
+
+    fn try_allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let ptr = self.allocator.allocate(layout).ok()?;
+        self.allocated += layout.size() as u64;
+        Some(ptr.cast())
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct HeapStats {
+    pub start: u64,
+    pub end: u64,
+    pub allocated: MemSize,
+    pub peak_allocated: MemSize,
+    pub num_arenas: usize,
+    pub num_grows: u64,
+    pub num_shrinks: u64,
+}
+
+impl Display for HeapStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "Heap range:      {:#x} - {:#x}", self.start, self.end)?;
+        writeln!(
+            f,
+            "Mapped:          {}",
+            MemSize {
+                bytes: self.end - self.start
+            }
+        )?;
+        writeln!(f, "Allocated:       {}", self.allocated)?;
+        writeln!(f, "Peak allocated:  {}", self.peak_allocated)?;
+        writeln!(f, "Arenas:          {}", self.num_arenas)?;
+        write!(
+            f,
+            "Grown {} times, shrunk {} times",
+            self.num_grows, self.num_shrinks
+        )
+    }
+}
+
+pub struct KernelHeap {
+    arenas: ArrayVec<Arena, MAX_ARENAS>,
+    stats: HeapStats,
+}
+
+// Safety: The arenas own the memory they point to, and the heap is only ever accessed through the
+// mutex inside the GlobalAllocator
+unsafe impl Send for KernelHeap {}
+
+impl KernelHeap {
+    /// Constructs the kernel heap, mapping its initial arena.
+    ///
+    /// The kernel page table must already be available in ```KERNEL_PAGE_TABLE```.
+    pub fn new() -> Result<Self, ()> {
+        let mut heap = KernelHeap {
+            arenas: ArrayVec::new(),
+            stats: HeapStats {
+                start: KERNEL_HEAP_START,
+                end: KERNEL_HEAP_START,
+                ..Default::default()
+            },
+        };
+        heap.grow(INITIAL_SIZE)?;
+        Ok(heap)
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        self.stats
+    }
+
+    pub fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        // Try the newest arenas first, the older ones are more likely to be fragmented
+        let ptr = match self
+            .arenas
+            .iter_mut()
+            .rev()
+            .find_map(|arena| arena.try_allocate(layout))
+        {
+            Some(ptr) => ptr,
+            None => {
+                // Leave a page of slack for the allocator's own bookkeeping
+                let size = (layout.size() + layout.align()) as u64 + page_size();
+                self.grow(size.next_multiple_of(page_size()).max(GROWTH_SIZE))
+                    .ok()?;
+                self.arenas.last_mut()?.try_allocate(layout)?
+            }
+        };
+
+        self.stats.allocated.bytes += layout.size() as u64;
+        if self.stats.allocated.bytes > self.stats.peak_allocated.bytes {
+            self.stats.peak_allocated = self.stats.allocated;
+        }
+        Some(ptr)
+    }
+
+    pub fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let arena = self
+            .arenas
+            .iter_mut()
+            .find(|arena| arena.contains(ptr.as_ptr() as u64))
+            .expect("Attempted to free memory that does not belong to the kernel heap");
+
+        // Safety: The pointer was handed out by this arena with the same layout
+        unsafe { arena.allocator.deallocate(ptr, layout) };
+        arena.allocated -= layout.size() as u64;
+        self.stats.allocated.bytes -= layout.size() as u64;
+
+        self.shrink();
+    }
+
+    /// Maps a new arena of ```size``` bytes at the top of the heap.
+    fn grow(&mut self, size: u64) -> Result<(), ()> {
+        let start = self.stats.end;
+        let end = start + size;
+        if self.arenas.is_full() || end > KERNEL_HEAP_START + KERNEL_HEAP_MAX_SIZE {
+            return Err(());
+        }
+
+        map_kernel_range(
+            &mut KERNEL_PAGE_TABLE.get().ok_or(())?.lock(),
+            start,
+            end,
+            FrameCategory::Heap,
+        )?;
+
+        // Safety: The range was just mapped, and belongs exclusively to this arena
+        let allocator = unsafe { LinkedListAlloc::new(start as *mut u8, end as *mut u8) };
+        self.arenas
+            .try_push(Arena {
+                start,
+                end,
+                allocator,
+                allocated: 0,
+            })
+            .map_err(|_| ())?;
+
+        self.stats.end = end;
+        self.stats.num_arenas = self.arenas.len();
+        self.stats.num_grows += 1;
+        Ok(())
+    }
+
+    /// Unmaps the empty arenas at the top of the heap, once there are enough of them.
+    ///
+    /// The initial arena is never released.
+    fn shrink(&mut self) {
+        let trailing_free: u64 = self
+            .arenas
+            .iter()
+            .skip(1)
+            .rev()
+            .take_while(|arena| arena.allocated == 0)
+            .map(|arena| arena.end - arena.start)
+            .sum();
+        if trailing_free < SHRINK_THRESHOLD {
+            return;
+        }
+
+        let mut page_table = KERNEL_PAGE_TABLE
+            .get()
+            .expect("Kernel heap exists without a kernel page table")
+            .lock();
+        while self.arenas.len() > 1 && self.arenas.last().is_some_and(|x| x.allocated == 0) {
+            let arena = self.arenas.pop().unwrap();
+            let (start, end) = (arena.start, arena.end);
+            drop(arena);
+
+            unmap_kernel_range(&mut page_table, start, end, FrameCategory::Heap);
+            self.stats.end = start;
+        }
+
+        self.stats.num_arenas = self.arenas.len();
+        self.stats.num_shrinks += 1;
+    }
+}
@@ -1,32 +1,183 @@
 use core::{
-    alloc::{Allocator, GlobalAlloc},
+    alloc::GlobalAlloc,
     ptr::{null_mut, NonNull},
 };
 
-use allocators::allocators::linked_list_allocator::LinkedListAlloc;
-use generic_once_cell::OnceCell;
-use raspi::concurrency::mutex::RawMutex;
+use generic_once_cell::{Lazy, OnceCell};
+use raspi::{
+    concurrency::mutex::{Mutex, RawMutex},
+    memory::{
+        memory_map::{EntryType, MemoryMap},
+        page_table::{MemoryType, VirtualAddr},
+    },
+};
+
+use crate::page_size;
+
+use self::{
+    accounting::{FrameCategory, MemoryStats, ACCOUNTING},
+    address_space::KernelPageTable,
+    frame_allocator::FrameAlloc,
+    heap::{HeapStats, KernelHeap},
+};
 
+pub mod accounting;
+pub mod address_space;
+pub mod asid;
+pub mod cache;
 pub mod frame_allocator;
+pub mod frame_cache;
+pub mod frame_ref;
+pub mod heap;
+pub mod stack;
+pub mod tlb;
+pub mod user_access;
+pub mod vma;
+pub mod vmalloc;
 
-pub struct GlobalAllocator(pub OnceCell<RawMutex, LinkedListAlloc<RawMutex>>);
+pub struct GlobalAllocator(pub OnceCell<RawMutex, Mutex<KernelHeap>>);
+
+impl GlobalAllocator {
+    /// Returns a snapshot of the kernel heap statistics, or None if the heap is not initialized yet.
+    pub fn stats(&self) -> Option<HeapStats> {
+        self.0.get().map(|heap| heap.lock().stats())
+    }
+}
 
 #[global_allocator]
 pub static GLOBAL_ALLOCATOR: GlobalAllocator = GlobalAllocator(OnceCell::new());
 
+pub static FRAME_ALLOCATOR: Lazy<RawMutex, Mutex<FrameAlloc>> =
+    Lazy::new(|| Mutex::new(FrameAlloc::new()));
+
+/// The kernel's higher half (TTBR1) page table, shared by all cores.
+pub static KERNEL_PAGE_TABLE: OnceCell<RawMutex, Mutex<KernelPageTable>> = OnceCell::new();
+
+// Virtual address at which the bootloader linearly mapped all of physical memory
+static LINEAR_MAP_START: OnceCell<RawMutex, u64> = OnceCell::new();
+
+/// Records the start of the linear mapping of physical memory. Must be called exactly once, before
+/// any frames are handed to the ```FRAME_ALLOCATOR```.
+pub fn set_linear_map_start(addr: u64) {
+    LINEAR_MAP_START
+        .set(addr)
+        .expect("Linear map start was already initialized");
+}
+
+pub fn linear_map_start() -> u64 {
+    *LINEAR_MAP_START
+        .get()
+        .expect("Attempted to access the linear map before it was initialized")
+}
+
+/// Translates a physical address into its alias inside the linear mapping of physical memory.
+pub fn phys_to_virt(phys_addr: u64) -> u64 {
+    phys_addr + linear_map_start()
+}
+
+/// Translates an address inside the linear mapping of physical memory back into a physical address.
+pub fn virt_to_phys(virt_addr: u64) -> u64 {
+    virt_addr - linear_map_start()
+}
+
+/// Backs every page in ```start..end``` of the kernel page table with a newly allocated frame,
+/// charged to ```category```.
+///
+/// If the range cannot be fully mapped, the pages that were already mapped are released again.
+pub fn map_kernel_range(
+    page_table: &mut KernelPageTable,
+    start: u64,
+    end: u64,
+    category: FrameCategory,
+) -> Result<(), ()> {
+    for virt_page in (start..end).step_by(page_size() as usize) {
+        let frame = frame_cache::allocate_frame(category);
+        let mapped = match frame {
+            Ok(phys_page) => page_table
+                .map_page(
+                    phys_page,
+                    VirtualAddr(virt_page),
+                    MemoryType::NORMAL_CACHEABLE,
+                )
+                .map_err(|_| frame_cache::deallocate_frame(phys_page, category)),
+            Err(_) => Err(()),
+        };
+
+        if mapped.is_err() {
+            // Roll back the pages we did manage to map
+            unmap_kernel_range(page_table, start, virt_page, category);
+            return Err(());
+        }
+    }
+
+    Ok(())
+}
+
+/// Unmaps every page in ```start..end``` from the kernel page table, returning the frames to the
+/// frame allocator once no core can still be using them. The frames must have been charged
+/// to ```category```.
+pub fn unmap_kernel_range(
+    page_table: &mut KernelPageTable,
+    start: u64,
+    end: u64,
+    category: FrameCategory,
+) {
+    for virt_page in (start..end).step_by(page_size() as usize) {
+        if let Ok(phys_page) = page_table.unmap_page(VirtualAddr(virt_page)) {
+            tlb::flush_page(virt_page);
+            frame_cache::deallocate_frame(phys_page, category);
+        }
+    }
+}
+
+/// Returns a snapshot of physical memory usage, broken down by ```FrameCategory```.
+pub fn memory_stats() -> MemoryStats {
+    let free_frames = FRAME_ALLOCATOR.lock().num_free_frames() + frame_cache::num_cached_frames();
+    ACCOUNTING.stats(free_frames)
+}
+
+/// Returns the frames that are only needed while booting to the ```FRAME_ALLOCATOR```, and returns
+/// the number of frames reclaimed.
+///
+/// This covers the device tree blob, which must already have been copied with
+/// ```device_tree::init```, and the first page of physical memory, where the firmware parked the
+/// secondary cores. All cores must have entered the kernel. The boot stacks are reclaimed
+/// separately by each core, with ```stack::retire_boot_stack```.
+pub fn reclaim_boot_frames(map: &MemoryMap) -> u64 {
+    let mut reclaimed = 0;
+    for entry in map.get_entries() {
+        let frames = match entry.entry_type {
+            EntryType::DtReserved => entry.base_addr..entry.end_addr,
+            // Only the first page of firmware memory is boot only, as it holds the spin tables
+            // the secondary cores were parked on
+            EntryType::Firmware if entry.base_addr == 0 => 0..page_size(),
+            _ => continue,
+        };
+        for frame in frames.step_by(page_size() as usize) {
+            if FRAME_ALLOCATOR.lock().add_frame(frame).is_ok() {
+                reclaimed += 1;
+            }
+        }
+    }
+
+    reclaimed
+}
+
 unsafe impl GlobalAlloc for GlobalAllocator {
     unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
         self.0
             .get()
             .expect("Attempted an allocation without an initialized global allocator")
+            .lock()
             .allocate(layout)
-            .map_or(null_mut(), |x| x.as_ptr() as *mut u8)
+            .map_or(null_mut(), |x| x.as_ptr())
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
         self.0
             .get()
             .expect("Attempted a deallocation without an initialized global allocator")
+            .lock()
             .deallocate(
                 NonNull::new(ptr).expect("Passed null ptr to global allocator"),
                 layout,
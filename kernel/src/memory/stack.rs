@@ -0,0 +1,127 @@
+//! Kernel stacks and the guard pages protecting them
+//!
+//! Every kernel stack has an unmapped guard page directly below it. The guard pages are recorded
+//! here, so that when a core faults on one the exception handler can report the overflow along with
+//! the core and thread that owned the stack, rather than a generic uncaught exception.
+//!
+//! Regular kernel code runs on SP_EL0, while every core takes its exceptions on a separate stack in
+//! SP_EL1. This way the handler still has a valid stack when the regular one has overflowed.
+
+use core::arch::asm;
+
+use arrayvec::ArrayVec;
+use raspi::{concurrency::rwlock::RwLock, cpu::core_id, exception::use_exception_stack};
+
+use super::{
+    accounting::FrameCategory, unmap_kernel_range, vmalloc::vmalloc_for, KERNEL_PAGE_TABLE,
+};
+use crate::{page_size, percpu, stack_size};
+
+/// Size of the per-core stack that exceptions are handled on. System calls run on it as well,
+/// including any filesystem access they make.
+pub const EXCEPTION_STACK_SIZE: u64 = 0x8000;
+
+const MAX_STACK_GUARDS: usize = 32;
+
+/// An unmapped page directly below a kernel stack.
+#[derive(Clone, Copy, Debug)]
+pub struct StackGuard {
+    pub start: u64,
+    pub end: u64,
+    pub core: u64,
+    pub owner: &'static str,
+}
+
+// Only changes when a stack is created or retired, but is searched on every kernel fault
+static STACK_GUARDS: RwLock<ArrayVec<StackGuard, MAX_STACK_GUARDS>> =
+    RwLock::new(ArrayVec::new_const());
+
+/// Records the guard page below the stack of ```size``` bytes ending at ```stack_top```.
+///
+/// The stack must already have an unmapped page below it. Fails if too many stacks are registered.
+pub fn register_stack(stack_top: u64, size: u64, core: u64, owner: &'static str) -> Result<(), ()> {
+    let end = stack_top - size;
+    STACK_GUARDS
+        .write()
+        .try_push(StackGuard {
+            start: end - page_size(),
+            end,
+            core,
+            owner,
+        })
+        .map_err(|_| ())
+}
+
+/// Forgets the guard page below the stack of ```size``` bytes ending at ```stack_top```.
+pub fn unregister_stack(stack_top: u64, size: u64) {
+    STACK_GUARDS
+        .write()
+        .retain(|guard| guard.end != stack_top - size);
+}
+
+/// Returns the guard page containing ```addr```, if there is one.
+///
+/// This is called from the exception handler, which may have interrupted a core that is updating the
+/// registry. In that case the lookup gives up rather than deadlocking.
+pub fn find_guard(addr: u64) -> Option<StackGuard> {
+    let guards = STACK_GUARDS.try_read()?;
+    guards
+        .iter()
+        .find(|guard| addr >= guard.start && addr < guard.end)
+        .copied()
+}
+
+/// Allocates an exception stack for the calling core and switches exceptions over to it.
+///
+/// Must be called exactly once on each core, after the kernel heap has been initialized.
+pub fn init_exception_stack(core: u64) -> Result<(), ()> {
+    let stack = vmalloc_for(EXCEPTION_STACK_SIZE as usize, FrameCategory::Stack).ok_or(())?;
+    let stack_top = stack.as_ptr() as u64 + EXCEPTION_STACK_SIZE;
+    // vmalloc always leaves the page below an allocation unmapped
+    register_stack(stack_top, EXCEPTION_STACK_SIZE, core, "exception")?;
+    // SAFETY: The allocation is page aligned, mapped, and owned exclusively by this core
+    unsafe { use_exception_stack(stack_top) };
+    percpu!(exception_stack_top).set(stack_top);
+    Ok(())
+}
+
+/// Allocates a new kernel stack and moves the calling core onto it, continuing execution in
+/// ```entry``` with ```arg``` as its only argument.
+///
+/// Nothing that lives on the current stack survives the switch, so all state that ```entry```
+/// needs must be passed through ```arg``` or statics.
+pub fn switch_to_new_stack(owner: &'static str, entry: extern "C" fn(u64) -> !, arg: u64) -> ! {
+    let stack = vmalloc_for(stack_size() as usize, FrameCategory::Stack)
+        .expect("Failed to allocate kernel stack");
+    let stack_top = stack.as_ptr() as u64 + stack_size();
+    register_stack(stack_top, stack_size(), core_id(), owner)
+        .expect("Failed to register kernel stack guard");
+
+    unsafe {
+        asm!(
+            "mov sp, {top}",
+            "br x1",
+            top = in(reg) stack_top,
+            in("x0") arg,
+            in("x1") entry as usize,
+            options(noreturn)
+        );
+    }
+}
+
+/// Unmaps a stack set up by the bootloader once no core is running on it anymore, returning its
+/// frames to the frame allocator.
+pub fn retire_boot_stack(stack_top: u64) {
+    unregister_stack(stack_top, stack_size());
+    let mut page_table = KERNEL_PAGE_TABLE
+        .get()
+        .expect("Kernel page table is not initialized")
+        .lock();
+    // The boot stacks were charged as reserved stack memory when the allocator was initialized
+    unmap_kernel_range(
+        &mut page_table,
+        stack_top - stack_size(),
+        stack_top,
+        FrameCategory::Stack,
+    );
+}
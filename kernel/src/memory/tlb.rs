@@ -0,0 +1,152 @@
+//! TLB maintenance and cross-core shootdowns
+//!
+//! Whenever a mapping is changed or removed, every core that may have cached the old translation
+//! must drop it. This uses the inner-shareable broadcast form of TLBI, which the hardware delivers
+//! to every core in the inner shareable domain. The trailing ```DSB ISH``` does not complete until
+//! all cores have performed the invalidation, which doubles as the acknowledgement.
+//!
+//! All cores of the supported boards are brought into the inner shareable domain by the firmware
+//! before the kernel starts, so no software fallback is needed.
+
+use core::arch::asm;
+
+use crate::page_size;
+
+// Flushing a large range one page at a time is slower than dropping the whole ASID (or the whole
+// TLB for global mappings), so ranges of more pages than this are escalated.
+const MAX_RANGE_PAGES: u64 = 64;
+
+/// A single TLB invalidation request.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Invalidation {
+    /// A single page of a global (kernel) mapping, in every ASID
+    Page(u64),
+    /// A single page of a non-global mapping tagged with the given ASID
+    PageAsid(u64, u16),
+    /// Every entry tagged with the given ASID
+    Asid(u16),
+    /// Every entry
+    All,
+}
+
+impl Invalidation {
+    /// Performs the invalidation on the local core only.
+    fn perform_local(&self) {
+        unsafe {
+            match *self {
+                Invalidation::Page(virt) => asm!(
+                    "DSB NSHST",
+                    "TLBI VAAE1, {arg}",
+                    "DSB NSH",
+                    "ISB",
+                    arg = in(reg) tlbi_va_operand(virt, 0)
+                ),
+                Invalidation::PageAsid(virt, asid) => asm!(
+                    "DSB NSHST",
+                    "TLBI VAE1, {arg}",
+                    "DSB NSH",
+                    "ISB",
+                    arg = in(reg) tlbi_va_operand(virt, asid)
+                ),
+                Invalidation::Asid(asid) => asm!(
+                    "DSB NSHST",
+                    "TLBI ASIDE1, {arg}",
+                    "DSB NSH",
+                    "ISB",
+                    arg = in(reg) (asid as u64) << 48
+                ),
+                Invalidation::All => asm!("DSB NSHST", "TLBI VMALLE1", "DSB NSH", "ISB"),
+            }
+        }
+    }
+
+    /// Performs the invalidation on every core in the inner shareable domain.
+    fn perform_broadcast(&self) {
+        unsafe {
+            match *self {
+                Invalidation::Page(virt) => asm!(
+                    "DSB ISHST",
+                    "TLBI VAAE1IS, {arg}",
+                    "DSB ISH",
+                    "ISB",
+                    arg = in(reg) tlbi_va_operand(virt, 0)
+                ),
+                Invalidation::PageAsid(virt, asid) => asm!(
+                    "DSB ISHST",
+                    "TLBI VAE1IS, {arg}",
+                    "DSB ISH",
+                    "ISB",
+                    arg = in(reg) tlbi_va_operand(virt, asid)
+                ),
+                Invalidation::Asid(asid) => asm!(
+                    "DSB ISHST",
+                    "TLBI ASIDE1IS, {arg}",
+                    "DSB ISH",
+                    "ISB",
+                    arg = in(reg) (asid as u64) << 48
+                ),
+                Invalidation::All => asm!("DSB ISHST", "TLBI VMALLE1IS", "DSB ISH", "ISB"),
+            }
+        }
+    }
+}
+
+// TLBI by VA takes VA[55:12] in bits [43:0] and the ASID in bits [63:48]
+fn tlbi_va_operand(virt: u64, asid: u16) -> u64 {
+    ((virt >> 12) & ((1 << 44) - 1)) | ((asid as u64) << 48)
+}
+
+/// Invalidates the entire TLB of the local core only.
+pub fn flush_local() {
+    Invalidation::All.perform_local();
+}
+
+/// Invalidates a single page of a global kernel mapping on every core.
+pub fn flush_page(virt: u64) {
+    shootdown(Invalidation::Page(virt));
+}
+
+/// Invalidates a single page of a user mapping tagged with ```asid``` on every core.
+pub fn flush_page_asid(virt: u64, asid: u16) {
+    shootdown(Invalidation::PageAsid(virt, asid));
+}
+
+/// Invalidates every entry tagged with ```asid``` on every core.
+pub fn flush_asid(asid: u16) {
+    shootdown(Invalidation::Asid(asid));
+}
+
+/// Invalidates the entire TLB of every core.
+pub fn flush_all() {
+    shootdown(Invalidation::All);
+}
+
+/// Invalidates the pages covering ```start..end``` on every core.
+///
+/// If ```asid``` is None the range is treated as a global kernel mapping. Large ranges are
+/// escalated to flushing the whole ASID, or the entire TLB for global mappings.
+pub fn flush_range(start: u64, end: u64, asid: Option<u16>) {
+    let page_size = page_size();
+    let first_page = start & !(page_size - 1);
+    let num_pages = (end - first_page).div_ceil(page_size);
+
+    if num_pages > MAX_RANGE_PAGES {
+        match asid {
+            Some(asid) => flush_asid(asid),
+            None => flush_all(),
+        }
+        return;
+    }
+
+    for page in (first_page..end).step_by(page_size as usize) {
+        match asid {
+            Some(asid) => flush_page_asid(page, asid),
+            None => flush_page(page),
+        }
+    }
+}
+
+/// Performs ```invalidation``` on every core, returning once all of them have completed it.
+pub fn shootdown(invalidation: Invalidation) {
+    invalidation.perform_broadcast();
+}
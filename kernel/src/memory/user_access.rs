@@ -0,0 +1,232 @@
+//! Access to user memory
+//!
+//! System calls receive raw pointers into the address space of the calling process, which may point
+//! anywhere. The kernel only ever touches user memory through the routines in this module, which
+//! use the unprivileged ```LDTR```/```STTR``` instructions. These are checked against the EL0
+//! permissions of the page, so a user pointer can never be used to read or write memory that the
+//! process could not access itself. They are also exempt from PAN, which is enabled on cores that
+//! implement it so that any other kernel access to user memory faults.
+//!
+//! Missing pages are populated by the page fault handler as usual. Every instruction that touches
+//! user memory is listed in the exception table along with a fixup address, so an access the fault
+//! handler cannot resolve makes the routine return early instead of bringing the kernel down.
+//!
+//! Since resolving a fault needs the lock of the current address space, user memory must not be
+//! accessed while holding it.
+
+use core::{arch::global_asm, mem::size_of};
+
+use alloc::{string::String, vec::Vec};
+
+use raspi::exception::Cpu_Context;
+
+use super::address_space::USER_SPACE_END;
+use crate::{
+    page_size,
+    syscall::{Errno, UserPtr},
+};
+
+global_asm!(
+    r#"
+.text
+// x0 = kernel destination, x1 = user source, x2 = length. Returns the number of bytes not copied
+.globl __copy_from_user
+.type __copy_from_user, @function
+__copy_from_user:
+.Lfrom_words:
+    cmp x2, #8
+    b.lo .Lfrom_bytes
+.Lfrom_load_word:
+    ldtr x3, [x1]
+    str x3, [x0], #8
+    add x1, x1, #8
+    sub x2, x2, #8
+    b .Lfrom_words
+.Lfrom_bytes:
+    cbz x2, .Lfrom_done
+.Lfrom_load_byte:
+    ldtrb w3, [x1]
+    strb w3, [x0], #1
+    add x1, x1, #1
+    sub x2, x2, #1
+    b .Lfrom_bytes
+.Lfrom_done:
+    mov x0, x2
+    ret
+
+// x0 = user destination, x1 = kernel source, x2 = length. Returns the number of bytes not copied
+.globl __copy_to_user
+.type __copy_to_user, @function
+__copy_to_user:
+.Lto_words:
+    cmp x2, #8
+    b.lo .Lto_bytes
+    ldr x3, [x1], #8
+.Lto_store_word:
+    sttr x3, [x0]
+    add x0, x0, #8
+    sub x2, x2, #8
+    b .Lto_words
+.Lto_bytes:
+    cbz x2, .Lto_done
+    ldrb w3, [x1], #1
+.Lto_store_byte:
+    sttrb w3, [x0]
+    add x0, x0, #1
+    sub x2, x2, #1
+    b .Lto_bytes
+.Lto_done:
+    mov x0, x2
+    ret
+
+// Pairs of (faulting instruction, fixup) addresses
+.pushsection __ex_table, "a"
+.balign 8
+    .quad .Lfrom_load_word, .Lfrom_done
+    .quad .Lfrom_load_byte, .Lfrom_done
+    .quad .Lto_store_word, .Lto_done
+    .quad .Lto_store_byte, .Lto_done
+.popsection
+"#
+);
+
+extern "C" {
+    fn __copy_from_user(dst: *mut u8, src: u64, len: usize) -> usize;
+    fn __copy_to_user(dst: u64, src: *const u8, len: usize) -> usize;
+
+    static __EX_TABLE_START: ExTableEntry;
+    static __EX_TABLE_END: ExTableEntry;
+}
+
+#[repr(C)]
+struct ExTableEntry {
+    insn: u64,
+    fixup: u64,
+}
+
+/// Plain data types, for which any bit pattern is a valid value.
+///
+/// # Safety
+/// Implementors must not contain padding, pointers or any other field with invalid bit patterns.
+pub unsafe trait UserCopy: Copy {}
+
+unsafe impl UserCopy for u8 {}
+unsafe impl UserCopy for u16 {}
+unsafe impl UserCopy for u32 {}
+unsafe impl UserCopy for u64 {}
+unsafe impl UserCopy for i32 {}
+unsafe impl UserCopy for i64 {}
+unsafe impl UserCopy for usize {}
+
+fn find_entry(insn: u64) -> Option<&'static ExTableEntry> {
+    // SAFETY: The linker script places the exception table between these two symbols
+    let table = unsafe {
+        let start = &__EX_TABLE_START as *const ExTableEntry;
+        let end = &__EX_TABLE_END as *const ExTableEntry;
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    };
+    table.iter().find(|entry| entry.insn == insn)
+}
+
+/// Returns whether the instruction at ```pc``` is one of the user accesses of this module.
+pub fn is_user_access(pc: u64) -> bool {
+    find_entry(pc).is_some()
+}
+
+/// Redirects a kernel fault in one of the user access routines to its fixup.
+///
+/// Returns false if the fault happened anywhere else, in which case it is a genuine kernel bug.
+pub fn fixup_exception(context: &mut Cpu_Context) -> bool {
+    match find_entry(context.elr_el1) {
+        Some(entry) => {
+            context.elr_el1 = entry.fixup;
+            true
+        }
+        None => false,
+    }
+}
+
+// Checks that len bytes starting at addr all lie in the lower half
+fn check_range(addr: u64, len: usize) -> Result<(), Errno> {
+    match addr.checked_add(len as u64) {
+        Some(end) if end <= USER_SPACE_END => Ok(()),
+        _ => Err(Errno::EFAULT),
+    }
+}
+
+/// Fills ```dst``` from user memory starting at ```src```.
+///
+/// Fails with EFAULT if any byte could not be read. ```dst``` may be partially filled then.
+pub fn copy_from_user(dst: &mut [u8], src: UserPtr<u8>) -> Result<(), Errno> {
+    check_range(src.addr(), dst.len())?;
+    // SAFETY: dst is a valid kernel buffer of the given length, and the source has been checked to
+    // be a user address. Faults are caught by the exception table
+    match unsafe { __copy_from_user(dst.as_mut_ptr(), src.addr(), dst.len()) } {
+        0 => Ok(()),
+        _ => Err(Errno::EFAULT),
+    }
+}
+
+/// Copies ```src``` to user memory starting at ```dst```.
+///
+/// Fails with EFAULT if any byte could not be written. Part of the data may have been written then.
+pub fn copy_to_user(dst: UserPtr<u8>, src: &[u8]) -> Result<(), Errno> {
+    check_range(dst.addr(), src.len())?;
+    // SAFETY: See copy_from_user
+    match unsafe { __copy_to_user(dst.addr(), src.as_ptr(), src.len()) } {
+        0 => Ok(()),
+        _ => Err(Errno::EFAULT),
+    }
+}
+
+/// Reads a single value from user memory.
+pub fn read_user<T: UserCopy>(src: UserPtr<T>) -> Result<T, Errno> {
+    let mut value = core::mem::MaybeUninit::<T>::uninit();
+    // SAFETY: The buffer covers exactly the value
+    let bytes =
+        unsafe { core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>()) };
+    copy_from_user(bytes, src.cast())?;
+    // SAFETY: Every byte was written, and any bit pattern is a valid T
+    Ok(unsafe { value.assume_init() })
+}
+
+/// Writes a single value to user memory.
+pub fn write_user<T: UserCopy>(dst: UserPtr<T>, value: &T) -> Result<(), Errno> {
+    // SAFETY: T has no padding, so all of its bytes are initialized
+    let bytes =
+        unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+    copy_to_user(dst.cast(), bytes)
+}
+
+/// Reads a null terminated string of at most ```max_len``` bytes, excluding the terminator, from
+/// user memory.
+///
+/// Fails with ENAMETOOLONG if no terminator is found in time, and EINVAL if the string is not
+/// valid UTF-8.
+pub fn read_user_string(src: UserPtr<u8>, max_len: usize) -> Result<String, Errno> {
+    let mut bytes = Vec::new();
+    let mut addr = src.addr();
+    let mut chunk = [0u8; 64];
+    loop {
+        // Never read past the end of the page the terminator might be in, since the next one may
+        // legitimately be unmapped
+        let to_page_end = (page_size() - addr % page_size()) as usize;
+        let len = chunk.len().min(to_page_end);
+        copy_from_user(&mut chunk[..len], UserPtr::new(addr).ok_or(Errno::EFAULT)?)?;
+
+        if let Some(end) = chunk[..len].iter().position(|byte| *byte == 0) {
+            bytes.extend_from_slice(&chunk[..end]);
+            break;
+        }
+        bytes.extend_from_slice(&chunk[..len]);
+        if bytes.len() > max_len {
+            return Err(Errno::ENAMETOOLONG);
+        }
+        addr += len as u64;
+    }
+
+    if bytes.len() > max_len {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    String::from_utf8(bytes).map_err(|_| Errno::EINVAL)
+}
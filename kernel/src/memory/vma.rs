@@ -0,0 +1,230 @@
+//! Virtual memory areas
+//!
+//! A virtual memory area describes a range of an address space that is allowed to be accessed,
+//! and how. Pages inside an area are not necessarily mapped; the page fault handler consults the
+//! areas of the faulting address space to decide whether a missing page should be populated or the
+//! access is invalid.
+//!
+//! Areas that are not user accessible reserve their range without permitting any access at all,
+//! which is how ```PROT_NONE``` mappings are represented.
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use raspi::memory::page_table::Protection;
+
+use crate::page_size;
+
+/// Describes what backs the pages of a virtual memory area.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VmaKind {
+    /// Pages are backed by zeroed frames, allocated the first time they are touched
+    Anonymous,
+    /// Pages are private copies of a file on the SD card, read the first time they are touched.
+    /// ```offset``` is the position in the file that the start of the area maps. Whatever lies
+    /// beyond the end of the file reads as zeroes
+    File { path: Arc<str>, offset: u64 },
+}
+
+#[derive(Clone, Debug)]
+pub struct Vma {
+    pub start: u64,
+    pub end: u64,
+    pub protection: Protection,
+    pub kind: VmaKind,
+}
+
+impl Vma {
+    pub fn contains(&self, addr: u64) -> bool {
+        addr >= self.start && addr < self.end
+    }
+
+    pub fn size(&self) -> u64 {
+        self.end - self.start
+    }
+
+    // Cuts the area in two at addr, which must lie strictly inside it. Returns the upper half
+    fn split_off(&mut self, addr: u64) -> Vma {
+        let kind = match &self.kind {
+            VmaKind::Anonymous => VmaKind::Anonymous,
+            VmaKind::File { path, offset } => VmaKind::File {
+                path: path.clone(),
+                offset: offset + (addr - self.start),
+            },
+        };
+        let upper = Vma {
+            start: addr,
+            end: self.end,
+            protection: self.protection,
+            kind,
+        };
+        self.end = addr;
+        upper
+    }
+}
+
+/// The non-overlapping virtual memory areas of a single address space, ordered by start address.
+pub struct VmaList {
+    areas: BTreeMap<u64, Vma>,
+}
+
+impl VmaList {
+    pub fn new() -> Self {
+        VmaList {
+            areas: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a new area to the list.
+    ///
+    /// Returns Err if the area is empty, not page aligned, covers the null page, or overlaps an
+    /// existing area.
+    pub fn insert(&mut self, vma: Vma) -> Result<(), ()> {
+        if vma.start >= vma.end || vma.start % page_size() != 0 || vma.end % page_size() != 0 {
+            return Err(());
+        }
+        // The null page is never mapped, so that null pointer dereferences always fault
+        if vma.start < page_size() {
+            return Err(());
+        }
+        if self.overlaps(vma.start, vma.end) {
+            return Err(());
+        }
+
+        self.areas.insert(vma.start, vma);
+        Ok(())
+    }
+
+    /// Removes the area starting at ```start```.
+    pub fn remove(&mut self, start: u64) -> Option<Vma> {
+        self.areas.remove(&start)
+    }
+
+    /// Removes every part of every area inside ```start..end```, splitting the areas that straddle
+    /// either end. Returns the removed parts.
+    pub fn remove_range(&mut self, start: u64, end: u64) -> Vec<Vma> {
+        self.split_at(start);
+        self.split_at(end);
+        let starts: Vec<u64> = self
+            .areas
+            .range(start..end)
+            .map(|(start, _)| *start)
+            .collect();
+        starts
+            .into_iter()
+            .filter_map(|start| self.areas.remove(&start))
+            .collect()
+    }
+
+    /// Changes the protection of every area inside ```start..end```, splitting the areas that
+    /// straddle either end.
+    ///
+    /// Returns Err without changing anything if part of the range is not covered by an area.
+    pub fn protect_range(
+        &mut self,
+        start: u64,
+        end: u64,
+        protection: Protection,
+    ) -> Result<(), ()> {
+        if !self.covers(start, end) {
+            return Err(());
+        }
+        self.split_at(start);
+        self.split_at(end);
+        for (_, vma) in self.areas.range_mut(start..end) {
+            vma.protection = protection;
+        }
+        Ok(())
+    }
+
+    /// Moves the end of the area starting at ```start``` to ```end```.
+    ///
+    /// Returns Err if there is no such area, or if the new end would make it empty or overlap the
+    /// next area.
+    pub fn resize(&mut self, start: u64, end: u64) -> Result<(), ()> {
+        if end <= start || end % page_size() != 0 {
+            return Err(());
+        }
+        let old_end = self.areas.get(&start).ok_or(())?.end;
+        if end > old_end && self.overlaps(old_end, end) {
+            return Err(());
+        }
+        self.areas.get_mut(&start).ok_or(())?.end = end;
+        Ok(())
+    }
+
+    /// Returns the area containing ```addr```, if there is one.
+    pub fn find(&self, addr: u64) -> Option<&Vma> {
+        self.areas
+            .range(..=addr)
+            .next_back()
+            .map(|(_, vma)| vma)
+            .filter(|vma| vma.contains(addr))
+    }
+
+    /// Returns true if any area intersects ```start..end```.
+    pub fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.areas
+            .range(..end)
+            .next_back()
+            .map_or(false, |(_, vma)| vma.end > start)
+    }
+
+    /// Returns true if every address in ```start..end``` lies in some area.
+    pub fn covers(&self, start: u64, end: u64) -> bool {
+        let mut current = start;
+        while current < end {
+            match self.find(current) {
+                Some(vma) => current = vma.end,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Returns the highest address at which ```size``` bytes fit between ```low``` and ```high```
+    /// without overlapping any area, if there is room.
+    pub fn find_gap(&self, size: u64, low: u64, high: u64) -> Option<u64> {
+        let mut gap_end = high;
+        for (_, vma) in self.areas.range(..high).rev() {
+            if vma.end <= gap_end && gap_end - vma.end >= size {
+                break;
+            }
+            gap_end = gap_end.min(vma.start);
+        }
+        let start = gap_end.checked_sub(size)?;
+        match start >= low {
+            true => Some(start),
+            false => None,
+        }
+    }
+
+    /// Returns the combined size of all areas.
+    pub fn total_size(&self) -> u64 {
+        self.areas.values().map(Vma::size).sum()
+    }
+
+    /// Returns the number of bytes of ```start..end``` that lie in some area.
+    pub fn size_in(&self, start: u64, end: u64) -> u64 {
+        self.areas
+            .range(..end)
+            .map(|(_, vma)| vma.end.min(end).saturating_sub(vma.start.max(start)))
+            .sum()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Vma> {
+        self.areas.values()
+    }
+
+    // Splits the area containing addr in two at addr, unless addr is already one of its bounds
+    fn split_at(&mut self, addr: u64) {
+        let start = match self.find(addr) {
+            Some(vma) if vma.start != addr => vma.start,
+            _ => return,
+        };
+        let upper = self
+            .areas
+            .get_mut(&start)
+            .expect("Area disappeared while splitting")
+            .split_off(addr);
+        self.areas.insert(addr, upper);
+    }
+}
@@ -0,0 +1,198 @@
+//! Virtually contiguous kernel allocations
+//!
+//! ```vmalloc``` hands out buffers that are contiguous in the kernel's virtual address space, but
+//! that are backed by individual, possibly scattered, physical frames. This makes it suitable for
+//! large buffers that would otherwise require a large physically contiguous allocation. Every
+//! allocation is followed by an unmapped guard page, so overrunning a buffer faults instead of
+//! silently corrupting its neighbour.
+//!
+//! The same region also holds ```ioremap``` mappings of device registers that are not covered by the
+//! linear mapping of physical memory.
+
+use core::ptr::NonNull;
+
+use alloc::collections::BTreeMap;
+use generic_once_cell::Lazy;
+use raspi::{
+    concurrency::mutex::{Mutex, RawMutex},
+    memory::page_table::{MemoryType, VirtualAddr},
+};
+
+use super::{
+    accounting::FrameCategory, address_space::KernelPageTable, map_kernel_range, tlb,
+    unmap_kernel_range, KERNEL_PAGE_TABLE,
+};
+use crate::page_size;
+
+/// Start of the virtual region reserved for vmalloc, the third lvl0 entry of TTBR1.
+pub const VMALLOC_START: u64 = 0xFFFF010000000000;
+/// Size of the virtual region reserved for vmalloc.
+pub const VMALLOC_SIZE: u64 = 0x8000000000;
+
+static VMALLOC: Lazy<RawMutex, Mutex<VmallocSpace>> = Lazy::new(|| Mutex::new(VmallocSpace::new()));
+
+enum Backing {
+    // Frames owned by the allocation, charged to the category
+    Frames(FrameCategory),
+    // Device registers, which are not owned by the allocation and only get unmapped
+    Device,
+}
+
+struct VmallocArea {
+    // Size in bytes, excluding the guard page
+    size: u64,
+    backing: Backing,
+}
+
+struct VmallocSpace {
+    // Maps the start address of each allocation to its area
+    areas: BTreeMap<u64, VmallocArea>,
+}
+
+impl VmallocSpace {
+    fn new() -> Self {
+        VmallocSpace {
+            areas: BTreeMap::new(),
+        }
+    }
+
+    /// Finds the lowest free range that fits ```size``` bytes plus a trailing guard page.
+    fn find_free(&self, size: u64) -> Option<u64> {
+        let guard = page_size();
+        // The very first page of the region is left unmapped, so that every allocation also has
+        // a guard page below it
+        let mut candidate = VMALLOC_START + guard;
+        for (start, area) in &self.areas {
+            if candidate + size + guard <= *start {
+                break;
+            }
+            candidate = start + area.size + guard;
+        }
+
+        match candidate + size + guard <= VMALLOC_START + VMALLOC_SIZE {
+            true => Some(candidate),
+            false => None,
+        }
+    }
+}
+
+/// Allocates ```size``` bytes of virtually contiguous, zero-initialized kernel memory.
+///
+/// The size is rounded up to a multiple of the page size, and the returned pointer is page
+/// aligned. Returns None if either virtual address space or physical frames are exhausted.
+pub fn vmalloc(size: usize) -> Option<NonNull<u8>> {
+    vmalloc_for(size, FrameCategory::Heap)
+}
+
+/// Behaves like ```vmalloc```, but charges the backing frames to ```category```.
+pub fn vmalloc_for(size: usize, category: FrameCategory) -> Option<NonNull<u8>> {
+    if size == 0 {
+        return None;
+    }
+
+    let size = (size as u64).next_multiple_of(page_size());
+    let mut space = VMALLOC.lock();
+    let start = space.find_free(size)?;
+
+    map_kernel_range(
+        &mut KERNEL_PAGE_TABLE.get()?.lock(),
+        start,
+        start + size,
+        category,
+    )
+    .ok()?;
+    space.areas.insert(
+        start,
+        VmallocArea {
+            size,
+            backing: Backing::Frames(category),
+        },
+    );
+
+    NonNull::new(start as *mut u8)
+}
+
+/// Maps ```size``` bytes of device registers starting at the physical address ```phys_addr``` into
+/// the kernel's address space, as device memory.
+///
+/// ```phys_addr``` must be page aligned. The mapping is removed again with ```vfree```. Returns None
+/// if the virtual address space is exhausted.
+pub fn ioremap(phys_addr: u64, size: usize) -> Option<NonNull<u8>> {
+    if size == 0 || phys_addr % page_size() != 0 {
+        return None;
+    }
+
+    let size = (size as u64).next_multiple_of(page_size());
+    let mut space = VMALLOC.lock();
+    let start = space.find_free(size)?;
+
+    let mut page_table = KERNEL_PAGE_TABLE.get()?.lock();
+    for offset in (0..size).step_by(page_size() as usize) {
+        if page_table
+            .map_page(
+                phys_addr + offset,
+                VirtualAddr(start + offset),
+                MemoryType::DEVICE,
+            )
+            .is_err()
+        {
+            unmap_device_range(&mut page_table, start, start + offset);
+            return None;
+        }
+    }
+    space.areas.insert(
+        start,
+        VmallocArea {
+            size,
+            backing: Backing::Device,
+        },
+    );
+
+    NonNull::new(start as *mut u8)
+}
+
+fn unmap_device_range(page_table: &mut KernelPageTable, start: u64, end: u64) {
+    for virt_page in (start..end).step_by(page_size() as usize) {
+        if page_table.unmap_page(VirtualAddr(virt_page)).is_ok() {
+            tlb::flush_page(virt_page);
+        }
+    }
+}
+
+/// Returns the size in bytes of the vmalloc allocation starting at ```ptr```.
+pub fn vmalloc_size(ptr: NonNull<u8>) -> Option<usize> {
+    VMALLOC
+        .lock()
+        .areas
+        .get(&(ptr.as_ptr() as u64))
+        .map(|area| area.size as usize)
+}
+
+/// Frees an allocation made by ```vmalloc```, unmapping it and returning its frames. Mappings made by
+/// ```ioremap``` are only unmapped.
+///
+/// # Safety
+/// ```ptr``` must have been returned by ```vmalloc```, and the memory must not be accessed after
+/// this call.
+///
+/// # Panics
+/// Panics if ```ptr``` does not point to the start of a live vmalloc allocation.
+pub unsafe fn vfree(ptr: NonNull<u8>) {
+    let start = ptr.as_ptr() as u64;
+    let mut space = VMALLOC.lock();
+    let area = space
+        .areas
+        .remove(&start)
+        .expect("Attempted to vfree memory that was not allocated with vmalloc");
+
+    let mut page_table = KERNEL_PAGE_TABLE
+        .get()
+        .expect("vmalloc allocation exists without a kernel page table")
+        .lock();
+    match area.backing {
+        Backing::Frames(category) => {
+            unmap_kernel_range(&mut page_table, start, start + area.size, category)
+        }
+        Backing::Device => unmap_device_range(&mut page_table, start, start + area.size),
+    }
+}
@@ -0,0 +1,176 @@
+//! Kernel panics
+//!
+//! The first core to panic stops every other core before printing anything, so that they can no
+//! longer write to the UART or to disk. Cores are stopped with a halt IPI, which also captures the
+//! state they were interrupted in. Cores that cannot receive IPIs yet notice the panic in their idle
+//! loops instead.
+//!
+//! Once the other cores are stopped, the panicking core owns the UART outright, and writes to it
+//! without taking its lock, which a stopped core or the panicking code itself may still be holding.
+
+use core::{
+    cell::UnsafeCell,
+    fmt::Write,
+    hint,
+    mem::MaybeUninit,
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use raspi::{
+    cpu::{core_id, disable_interrupts, NUM_CORES},
+    exception::Cpu_Context,
+    peripherals::{
+        timer::{duration_to_cycles, timer_cycle_count},
+        uart::Uart,
+    },
+};
+
+use crate::{
+    ipi::{self, IpiMessage},
+    peripherals::UART,
+};
+
+// How long the panicking core waits for the others to stop before printing anyway
+const HALT_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_PANICS: AtomicU32 = AtomicU32::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const NOT_SET: AtomicBool = AtomicBool::new(false);
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_CONTEXT: UnsafeCell<MaybeUninit<Cpu_Context>> = UnsafeCell::new(MaybeUninit::uninit());
+
+// 0 while no core has panicked, otherwise the number of the first panicking core plus one
+static PANICKING_CORE: AtomicU64 = AtomicU64::new(0);
+// Number of panics currently being handled on each core
+static PANIC_DEPTH: [AtomicU32; NUM_CORES] = [NO_PANICS; NUM_CORES];
+static HALTED: [AtomicBool; NUM_CORES] = [NOT_SET; NUM_CORES];
+static DUMP_HALTED_CORES: AtomicBool = AtomicBool::new(true);
+
+struct HaltedContexts {
+    contexts: [UnsafeCell<MaybeUninit<Cpu_Context>>; NUM_CORES],
+    captured: [AtomicBool; NUM_CORES],
+}
+
+// SAFETY: Each core only writes its own context, once, before publishing it through captured
+unsafe impl Sync for HaltedContexts {}
+
+static HALTED_CONTEXTS: HaltedContexts = HaltedContexts {
+    contexts: [NO_CONTEXT; NUM_CORES],
+    captured: [NOT_SET; NUM_CORES],
+};
+
+/// Controls whether a panic prints the state the other cores were stopped in. Enabled by default.
+pub fn set_dump_halted_cores(dump: bool) {
+    DUMP_HALTED_CORES.store(dump, Ordering::Relaxed);
+}
+
+/// Returns true once any core has panicked.
+pub fn is_panicking() -> bool {
+    PANICKING_CORE.load(Ordering::Acquire) != 0
+}
+
+/// Stops the calling core if another core has panicked. Meant to be polled in idle loops.
+pub fn halt_if_panicking() {
+    if is_panicking() {
+        halt(None);
+    }
+}
+
+/// Stops the calling core for good, recording ```context``` as the state it was stopped in.
+pub fn halt(context: Option<&Cpu_Context>) -> ! {
+    disable_interrupts();
+    let core = core_id() as usize;
+    if let Some(context) = context {
+        // SAFETY: Only this core writes its slot, and only once since it never returns
+        unsafe { (*HALTED_CONTEXTS.contexts[core].get()).write(*context) };
+        HALTED_CONTEXTS.captured[core].store(true, Ordering::Release);
+    }
+    HALTED[core].store(true, Ordering::Release);
+    ipi::halt();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    disable_interrupts();
+    let core = core_id();
+    let depth = PANIC_DEPTH[core as usize].fetch_add(1, Ordering::AcqRel);
+    // SAFETY: Either the other cores are stopped, or we give up on them below. The panicking core
+    // may be holding the lock itself, so it is not taken
+    let uart = unsafe { &mut *UART.data_ptr() };
+
+    match depth {
+        0 => {}
+        // Panicked while printing a panic. Report it, but don't try to print the original again
+        1 => {
+            let _ = writeln!(uart, "\nNESTED KERNEL PANIC on core {}!", core);
+            if let Some(location) = info.location() {
+                let _ = writeln!(uart, "Location: {}", location);
+            }
+            halt(None);
+        }
+        // Even printing the nested panic failed, so there is nothing left to do safely
+        _ => halt(None),
+    }
+
+    if PANICKING_CORE
+        .compare_exchange(0, core + 1, Ordering::AcqRel, Ordering::Acquire)
+        .is_err()
+    {
+        // Another core panicked first and is stopping everyone, so leave the output to it
+        halt(None);
+    }
+
+    let stopped_all = stop_other_cores();
+    print_panic(uart, core, info, stopped_all);
+
+    HALTED[core as usize].store(true, Ordering::Release);
+    ipi::halt();
+}
+
+// Returns true if every other core confirmed it stopped in time
+fn stop_other_cores() -> bool {
+    // Without IPIs, the other cores only stop once they poll the panic flag
+    if ipi::is_initialized() {
+        ipi::send_to_others(IpiMessage::Halt);
+    }
+
+    let this_core = core_id() as usize;
+    let others = || (0..NUM_CORES).filter(move |core| *core != this_core);
+    let deadline = timer_cycle_count() + duration_to_cycles(HALT_TIMEOUT);
+    while timer_cycle_count() < deadline {
+        if others().all(|core| HALTED[core].load(Ordering::Acquire)) {
+            return true;
+        }
+        hint::spin_loop();
+    }
+    false
+}
+
+fn print_panic(uart: &mut Uart, core: u64, info: &PanicInfo, stopped_all: bool) {
+    let _ = writeln!(uart, "\nKERNEL PANIC on core {}!", core);
+    if let Some(location) = info.location() {
+        let _ = writeln!(uart, "Location: {}", location);
+    }
+    if let Some(message) = info.message() {
+        let _ = writeln!(uart, "Reason: \n{}", message);
+    }
+    if !stopped_all {
+        let _ = writeln!(uart, "Not all cores stopped, output may be garbled");
+    }
+
+    if !DUMP_HALTED_CORES.load(Ordering::Relaxed) {
+        return;
+    }
+    for other in (0..NUM_CORES).filter(|other| *other as u64 != core) {
+        if HALTED_CONTEXTS.captured[other].load(Ordering::Acquire) {
+            // SAFETY: The context was fully written before captured was set
+            let context = unsafe { (*HALTED_CONTEXTS.contexts[other].get()).assume_init_ref() };
+            let _ = writeln!(uart, "\nCore {} was stopped in:\n{}", other, context);
+        } else if HALTED[other].load(Ordering::Acquire) {
+            let _ = writeln!(uart, "\nCore {} was stopped while idle", other);
+        }
+    }
+}
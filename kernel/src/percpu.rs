@@ -0,0 +1,115 @@
+//! Per-CPU data
+//!
+//! Every core owns a ```PerCpu``` block, and keeps its address in TPIDR_EL1 so it can be found with a
+//! single register read. Only the owning core ever touches its block, so the fields need no locking
+//! and are plain ```Cell```s. A field of the calling core's block is accessed with the ```percpu!```
+//! macro, e.g. ```percpu!(irq_depth).get()```.
+//!
+//! The run queue is the one exception. Idle cores take processes from the run queues of other cores,
+//! which they find with ```cpu```, so it is locked.
+//!
+//! A reference to a per-CPU field must never be held across a point where the code could be moved to
+//! another core.
+
+use core::cell::{Cell, RefCell};
+
+use alloc::{collections::VecDeque, sync::Arc};
+use raspi::{
+    concurrency::mutex::Mutex,
+    cpu::{percpu_base, set_percpu_base, NUM_CORES},
+};
+
+use crate::process::Process;
+
+/// Event counters of a single core.
+pub struct CpuStats {
+    pub synchronous_exceptions: Cell<u64>,
+    pub page_faults: Cell<u64>,
+    pub irqs: Cell<u64>,
+    pub syscalls: Cell<u64>,
+}
+
+impl CpuStats {
+    const fn new() -> Self {
+        CpuStats {
+            synchronous_exceptions: Cell::new(0),
+            page_faults: Cell::new(0),
+            irqs: Cell::new(0),
+            syscalls: Cell::new(0),
+        }
+    }
+}
+
+/// The data block owned by a single core.
+pub struct PerCpu {
+    pub core: Cell<u64>,
+    /// Number of nested interrupt handlers currently running on this core
+    pub irq_depth: Cell<u32>,
+    /// Top of the stack this core takes its exceptions on, once it has one
+    pub exception_stack_top: Cell<u64>,
+    /// Top of the stack this core idles on while it has no process to run
+    pub idle_stack_top: Cell<u64>,
+    /// The process this core is running, if any
+    pub current_process: RefCell<Option<Arc<Mutex<Process>>>>,
+    /// Processes waiting for their turn on this core
+    pub run_queue: Mutex<VecDeque<Arc<Mutex<Process>>>>,
+    pub stats: CpuStats,
+}
+
+// SAFETY: A block is only ever accessed by the core it belongs to, except for the run queue, which is
+// locked
+unsafe impl Sync for PerCpu {}
+
+impl PerCpu {
+    const fn new() -> Self {
+        PerCpu {
+            core: Cell::new(0),
+            irq_depth: Cell::new(0),
+            exception_stack_top: Cell::new(0),
+            idle_stack_top: Cell::new(0),
+            current_process: RefCell::new(None),
+            run_queue: Mutex::new(VecDeque::new()),
+            stats: CpuStats::new(),
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: PerCpu = PerCpu::new();
+static PER_CPU: [PerCpu; NUM_CORES] = [EMPTY; NUM_CORES];
+
+/// Points TPIDR_EL1 of the calling core at its data block.
+///
+/// Must be the first thing each core does in the kernel, since exception handlers rely on it.
+pub fn init(core: u64) {
+    let block = &PER_CPU[core as usize];
+    block.core.set(core);
+    // SAFETY: The block belongs to the calling core
+    unsafe { set_percpu_base(block as *const PerCpu as u64) };
+}
+
+/// Returns the data block of the calling core.
+#[inline(always)]
+pub fn this_cpu() -> &'static PerCpu {
+    // SAFETY: init has stored the address of this core's block, which lives forever
+    unsafe { &*(percpu_base() as *const PerCpu) }
+}
+
+/// Returns the data block of any core. Only its run queue may be touched from another core.
+pub fn cpu(core: usize) -> &'static PerCpu {
+    &PER_CPU[core]
+}
+
+/// Increments a per-CPU counter.
+#[inline(always)]
+pub fn inc(counter: &Cell<u64>) {
+    counter.set(counter.get() + 1);
+}
+
+/// Returns a reference to a field of the calling core's ```PerCpu``` block.
+#[macro_export]
+macro_rules! percpu {
+    ($($field:ident).+) => {
+        &$crate::percpu::this_cpu().$($field).+
+    };
+}
@@ -1,9 +1,12 @@
 use generic_once_cell::{Lazy, OnceCell};
 use raspi::{
-    concurrency::mutex::{Mutex, RawMutex},
-    peripherals::{emmc::EMMCController, mailbox::Mailbox, uart::Uart},
+    concurrency::mutex::{IrqMutex, Mutex, RawMutex},
+    peripherals::{emmc::EMMCController, mailbox::Mailbox, power::PowerManagement, uart::Uart},
 };
 
-pub static UART: Lazy<RawMutex, Mutex<Uart>> = Lazy::new(|| Mutex::new(Uart::new()));
+// Printing is allowed from interrupt handlers, so the UART must not be locked with interrupts enabled
+pub static UART: Lazy<RawMutex, IrqMutex<Uart>> = Lazy::new(|| IrqMutex::new(Uart::new()));
 pub static MAILBOX: Lazy<RawMutex, Mutex<Mailbox>> = Lazy::new(|| Mutex::new(Mailbox::new()));
+pub static POWER: Lazy<RawMutex, Mutex<PowerManagement>> =
+    Lazy::new(|| Mutex::new(PowerManagement::new()));
 pub static EMMC2: OnceCell<RawMutex, Mutex<EMMCController>> = OnceCell::new();
@@ -0,0 +1,76 @@
+//! Open files of a process
+//!
+//! A file descriptor is an index into the file table of a process. Files on the SD card are
+//! reopened by path for every access, since the filesystem can only lend out files while it is
+//! locked, so an open file only remembers its path and position.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use raspi::concurrency::mutex::Mutex;
+
+use crate::syscall::Errno;
+
+/// Most files a single process can have open at once.
+pub const MAX_FILES: usize = 32;
+
+pub enum File {
+    /// The UART, which is the only terminal
+    Console,
+    /// A regular file on the SD card, opened for reading
+    Disk { path: String, offset: u64 },
+}
+
+pub struct FileTable {
+    files: Vec<Option<Arc<Mutex<File>>>>,
+}
+
+impl FileTable {
+    pub fn new() -> Self {
+        FileTable { files: Vec::new() }
+    }
+
+    /// Returns a table with standard input, output and error all connected to the console.
+    pub fn with_console() -> Self {
+        let console = Arc::new(Mutex::new(File::Console));
+        FileTable {
+            files: alloc::vec![Some(console.clone()), Some(console.clone()), Some(console)],
+        }
+    }
+
+    /// Adds ```file``` to the table, returning the lowest free file descriptor.
+    pub fn insert(&mut self, file: File) -> Result<usize, Errno> {
+        let file = Some(Arc::new(Mutex::new(file)));
+        match self.files.iter().position(|slot| slot.is_none()) {
+            Some(fd) => {
+                self.files[fd] = file;
+                Ok(fd)
+            }
+            None if self.files.len() < MAX_FILES => {
+                self.files.push(file);
+                Ok(self.files.len() - 1)
+            }
+            None => Err(Errno::EMFILE),
+        }
+    }
+
+    /// Returns the file behind ```fd```.
+    pub fn get(&self, fd: usize) -> Result<Arc<Mutex<File>>, Errno> {
+        self.files
+            .get(fd)
+            .and_then(|slot| slot.clone())
+            .ok_or(Errno::EBADF)
+    }
+
+    /// Closes ```fd```.
+    pub fn remove(&mut self, fd: usize) -> Result<(), Errno> {
+        self.files
+            .get_mut(fd)
+            .and_then(|slot| slot.take())
+            .map(|_| ())
+            .ok_or(Errno::EBADF)
+    }
+
+    /// Closes every file.
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+}
@@ -0,0 +1,173 @@
+//! Futexes
+//!
+//! A futex lets user code sleep until a 32 bit word in its memory changes, without the kernel
+//! knowing what the word means. User code only asks to wait once it found the word in a state that
+//! requires waiting, such as a locked mutex. The kernel checks the word again with the futex queues
+//! locked, and wakers take the same lock, so a wakeup that happens in between is never lost.
+//!
+//! Futexes are identified by the physical address of the word, so processes sharing memory meet at
+//! the same futex through whatever virtual addresses they mapped it at. Pages that are shared
+//! copy-on-write also share their futexes until one of them is written, so waiters must expect
+//! spurious wakeups and check the word again.
+//!
+//! A waiting process sleeps like in any other blocking system call, and the system call is made
+//! again once it runs. How the wait ended is recorded in the process in the meantime, so that the
+//! restarted call can report it.
+
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+};
+use raspi::concurrency::mutex::Mutex;
+
+use super::{current, scheduler, Process, ProcessState};
+use crate::{
+    memory::{address_space, user_access::read_user},
+    syscall::{Errno, UserPtr},
+    timer::{self, TimeoutHandle},
+};
+
+/// Where a process is in waiting on a futex.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FutexState {
+    /// Not waiting on a futex
+    Idle,
+    /// In the queue of a futex
+    Queued,
+    /// Taken off the queue by ```wake```, but has not noticed yet
+    Woken,
+    /// Taken off the queue once its timeout expired, but has not noticed yet
+    TimedOut,
+}
+
+struct Waiter {
+    process: Arc<Mutex<Process>>,
+    // Tells apart successive waits of the same process, so a late timeout leaves a newer one alone
+    token: u64,
+    timeout: Option<TimeoutHandle>,
+}
+
+// The processes waiting on each futex in the order they started waiting, by physical address
+static FUTEXES: Mutex<BTreeMap<u64, VecDeque<Waiter>>> = Mutex::new(BTreeMap::new());
+
+static NEXT_TOKEN: AtomicU64 = AtomicU64::new(0);
+
+// Returns the physical address of the word at uaddr in the current address space
+fn key(uaddr: UserPtr<u32>) -> Result<u64, Errno> {
+    if uaddr.addr() % 4 != 0 {
+        return Err(Errno::EINVAL);
+    }
+    // Reading the word makes sure the page is resident, and that the process may access it at all
+    read_user(uaddr)?;
+    let space = address_space::current().ok_or(Errno::EFAULT)?;
+    let phys_addr = space.lock().translate(uaddr.addr());
+    phys_addr.ok_or(Errno::EFAULT)
+}
+
+// Takes a waiter that has been removed from its queue out of its wait
+fn finish(waiter: Waiter, outcome: FutexState) {
+    waiter.process.lock().futex = outcome;
+    scheduler::wake(&waiter.process);
+}
+
+// Runs once the timeout of a waiter expired, unless it was woken up first
+fn time_out(key: u64, token: u64) {
+    let mut futexes = FUTEXES.lock();
+    let queue = match futexes.get_mut(&key) {
+        Some(queue) => queue,
+        None => return,
+    };
+    let index = match queue.iter().position(|waiter| waiter.token == token) {
+        Some(index) => index,
+        None => return,
+    };
+    let waiter = queue
+        .remove(index)
+        .expect("Futex waiter vanished from its queue");
+    if queue.is_empty() {
+        futexes.remove(&key);
+    }
+    finish(waiter, FutexState::TimedOut);
+}
+
+/// Waits on the futex at ```uaddr``` if the word there still holds ```expected```, for at most
+/// ```timeout``` if one is given.
+///
+/// Returns true once the current process has been woken up by ```wake```. Otherwise it is put to
+/// sleep and false is returned, in which case the caller must try again once it runs again. Fails
+/// with EAGAIN if the word holds another value, and with ETIMEDOUT once the timeout expired.
+pub fn wait(uaddr: UserPtr<u32>, expected: u32, timeout: Option<Duration>) -> Result<bool, Errno> {
+    let current = current().ok_or(Errno::ESRCH)?;
+    {
+        let mut process = current.lock();
+        match core::mem::replace(&mut process.futex, FutexState::Idle) {
+            FutexState::Idle => {}
+            FutexState::Woken => return Ok(true),
+            FutexState::TimedOut => return Err(Errno::ETIMEDOUT),
+            // Woken up by something other than the futex, so it goes back to sleep
+            FutexState::Queued => {
+                process.futex = FutexState::Queued;
+                process.state = ProcessState::Waiting;
+                return Ok(false);
+            }
+        }
+    }
+
+    let key = key(uaddr)?;
+    let mut futexes = FUTEXES.lock();
+    // The word is read through the process' own cacheable mapping, which is where user code
+    // changed it. A fault only waits for the address space lock, never for the futex queues
+    if read_user(uaddr)? != expected {
+        return Err(Errno::EAGAIN);
+    }
+
+    let token = NEXT_TOKEN.fetch_add(1, Ordering::Relaxed);
+    // The timeout can only run on this core once the system call is over, by which time the
+    // process is in the queue
+    let timeout = timeout.map(|after| timer::add(after, Box::new(move || time_out(key, token))));
+    futexes.entry(key).or_default().push_back(Waiter {
+        process: current.clone(),
+        token,
+        timeout,
+    });
+
+    let mut process = current.lock();
+    process.futex = FutexState::Queued;
+    process.state = ProcessState::Waiting;
+    Ok(false)
+}
+
+/// Wakes up at most ```count``` of the processes waiting on the futex at ```uaddr```, in the order
+/// they started waiting. Returns the number of processes woken up.
+pub fn wake(uaddr: UserPtr<u32>, count: u32) -> Result<u64, Errno> {
+    let key = key(uaddr)?;
+    let mut futexes = FUTEXES.lock();
+    let queue = match futexes.get_mut(&key) {
+        Some(queue) => queue,
+        None => return Ok(0),
+    };
+
+    let mut woken = 0;
+    while woken < count as u64 {
+        let waiter = match queue.pop_front() {
+            Some(waiter) => waiter,
+            None => break,
+        };
+        // A timeout that is already running finds the waiter gone
+        if let Some(timeout) = waiter.timeout {
+            timer::cancel(timeout);
+        }
+        finish(waiter, FutexState::Woken);
+        woken += 1;
+    }
+    if queue.is_empty() {
+        futexes.remove(&key);
+    }
+    Ok(woken)
+}
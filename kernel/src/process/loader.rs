@@ -0,0 +1,189 @@
+//! Loading user programs
+//!
+//! Programs are statically linked AArch64 ELF executables. Every loadable segment gets its own area
+//! in the new address space, with the permissions from its flags, and the file contents are copied
+//! in up front. Whatever the segment reserves beyond its file contents is left to be populated with
+//! zeroed pages on demand. The heap starts right after the highest segment.
+//!
+//! The initial stack follows the System V layout: the stack pointer points at ```argc```, followed by
+//! the ```argv``` and ```envp``` pointer arrays, each terminated by a null pointer, and the auxiliary
+//! vector. The strings they point to are stored above them, at the top of the stack. As a
+//! convenience, ```argc```, ```argv``` and ```envp``` are also passed in x0, x1 and x2.
+
+use alloc::vec::Vec;
+use elf_parse::{ElfFile, MachineType};
+use raspi::{exception::Cpu_Context, memory::page_table::Protection};
+
+use crate::{
+    memory::address_space::{AddressSpace, USER_SPACE_END},
+    page_size,
+    syscall::Errno,
+};
+
+/// The initial stack ends here, below a gap that is never mapped.
+pub const USER_STACK_TOP: u64 = USER_SPACE_END - 0x10000;
+/// Size of the area reserved for the stack. Only the pages that are touched are backed by frames.
+pub const USER_STACK_SIZE: u64 = 0x100000;
+// Limit on the size of the arguments and environment, including their pointer arrays
+const MAX_ARGS_SIZE: usize = 0x10000;
+
+const PT_LOAD: u32 = 1;
+const PT_PHDR: u32 = 6;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_ENTRY: u64 = 9;
+
+/// Loads the executable ```image``` into the empty address space ```space```, and sets up its
+/// stack with the given arguments and environment.
+///
+/// Returns the context the program starts executing in. Fails with ENOEXEC if the image is not a
+/// valid executable, E2BIG if the arguments do not fit on the stack, and ENOMEM if memory ran out.
+/// The address space may be partially populated on failure.
+pub fn load(
+    space: &mut AddressSpace,
+    image: &[u8],
+    argv: &[&str],
+    envp: &[&str],
+) -> Result<Cpu_Context, Errno> {
+    let elf = ElfFile::new(image).map_err(|_| Errno::ENOEXEC)?;
+    if elf.hdr.machine != MachineType::AARCH64 {
+        return Err(Errno::ENOEXEC);
+    }
+
+    let mut phdr_addr = None;
+    let mut loaded_any = false;
+    let mut brk_base = 0;
+    for segment in elf.program_headers().ok_or(Errno::ENOEXEC)? {
+        match segment.program_type {
+            PT_LOAD => {}
+            PT_PHDR => {
+                phdr_addr = Some(segment.virt_addr);
+                continue;
+            }
+            _ => continue,
+        }
+        if segment.memsz == 0 {
+            continue;
+        }
+
+        let file_end = segment
+            .offset
+            .checked_add(segment.filesz)
+            .ok_or(Errno::ENOEXEC)?;
+        let mem_end = segment
+            .virt_addr
+            .checked_add(segment.memsz)
+            .ok_or(Errno::ENOEXEC)?;
+        if segment.filesz > segment.memsz
+            || file_end > image.len() as u64
+            || mem_end > USER_STACK_TOP - USER_STACK_SIZE
+        {
+            return Err(Errno::ENOEXEC);
+        }
+
+        let start = segment.virt_addr - (segment.virt_addr % page_size());
+        let end = mem_end.next_multiple_of(page_size());
+        let protection = Protection {
+            writable: segment.flags & PF_W != 0,
+            executable: segment.flags & PF_X != 0,
+            user: true,
+        };
+        space
+            .map_anonymous(start, end - start, protection)
+            .map_err(|_| Errno::ENOEXEC)?;
+        space
+            .write(
+                segment.virt_addr,
+                &image[segment.offset as usize..file_end as usize],
+            )
+            .map_err(|_| Errno::ENOMEM)?;
+        loaded_any = true;
+        brk_base = brk_base.max(end);
+
+        // Without a PT_PHDR segment, the program headers can still be found if they were loaded
+        if phdr_addr.is_none() && (segment.offset..file_end).contains(&elf.hdr.ph_off) {
+            phdr_addr = Some(segment.virt_addr + elf.hdr.ph_off - segment.offset);
+        }
+    }
+    if !loaded_any {
+        return Err(Errno::ENOEXEC);
+    }
+    space.set_brk_base(brk_base);
+
+    let mut auxv = Vec::new();
+    if let Some(phdr_addr) = phdr_addr {
+        auxv.extend_from_slice(&[
+            (AT_PHDR, phdr_addr),
+            (AT_PHENT, elf.hdr.ph_entsize as u64),
+            (AT_PHNUM, elf.hdr.ph_num as u64),
+        ]);
+    }
+    auxv.extend_from_slice(&[(AT_PAGESZ, page_size()), (AT_ENTRY, elf.hdr.entry)]);
+
+    space
+        .map_anonymous(
+            USER_STACK_TOP - USER_STACK_SIZE,
+            USER_STACK_SIZE,
+            Protection {
+                writable: true,
+                executable: false,
+                user: true,
+            },
+        )
+        .map_err(|_| Errno::ENOEXEC)?;
+    setup_stack(space, elf.hdr.entry, argv, envp, &auxv)
+}
+
+fn setup_stack(
+    space: &mut AddressSpace,
+    entry: u64,
+    argv: &[&str],
+    envp: &[&str],
+    auxv: &[(u64, u64)],
+) -> Result<Cpu_Context, Errno> {
+    // Strings go at the very top, each terminated by a null byte
+    let strings_size: usize = argv.iter().chain(envp).map(|s| s.len() + 1).sum();
+    let num_words = 1 + (argv.len() + 1) + (envp.len() + 1) + 2 * (auxv.len() + 1);
+    if strings_size + 8 * num_words > MAX_ARGS_SIZE {
+        return Err(Errno::E2BIG);
+    }
+
+    let strings_start = USER_STACK_TOP - strings_size as u64;
+    // The stack pointer must be 16 byte aligned at all times
+    let sp = (strings_start - 8 * num_words as u64) & !0xF;
+
+    let mut strings = Vec::with_capacity(strings_size);
+    let mut words = Vec::with_capacity(num_words);
+    words.push(argv.len() as u64);
+    for list in [argv, envp] {
+        for s in list {
+            words.push(strings_start + strings.len() as u64);
+            strings.extend_from_slice(s.as_bytes());
+            strings.push(0);
+        }
+        words.push(0);
+    }
+    for (key, value) in auxv.iter().chain(&[(AT_NULL, 0)]) {
+        words.push(*key);
+        words.push(*value);
+    }
+
+    let words: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    space
+        .write(strings_start, &strings)
+        .and_then(|_| space.write(sp, &words))
+        .map_err(|_| Errno::ENOMEM)?;
+
+    let mut context = Cpu_Context::new_user(entry, sp);
+    let argv_addr = sp + 8;
+    context.gpr[0] = argv.len() as u64;
+    context.gpr[1] = argv_addr;
+    context.gpr[2] = argv_addr + 8 * (argv.len() as u64 + 1);
+    Ok(context)
+}
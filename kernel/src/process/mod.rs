@@ -0,0 +1,282 @@
+//! User processes
+//!
+//! A process is a program running at EL0 in its own lower half address space. While a process is
+//! running, its user state lives in the exception frame of whichever core runs it, and is saved back
+//! into the process whenever it stops running. See ```scheduler``` for how cores pick processes.
+//!
+//! Every process is listed in the process table until it has exited and its parent has waited for
+//! it. A process whose parent exits first is orphaned, and is removed from the table as soon as it
+//! exits itself.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use raspi::{concurrency::mutex::Mutex, exception::Cpu_Context};
+
+use self::{files::FileTable, futex::FutexState};
+use crate::{
+    fs,
+    memory::address_space::{self, AddressSpace},
+    syscall::Errno,
+};
+
+pub mod files;
+pub mod futex;
+pub mod loader;
+pub mod scheduler;
+
+pub use self::scheduler::current;
+
+static NEXT_PID: AtomicU64 = AtomicU64::new(1);
+
+// Every process that has not been reaped yet, by pid
+static PROCESSES: Mutex<BTreeMap<u64, Arc<Mutex<Process>>>> = Mutex::new(BTreeMap::new());
+
+/// The program the kernel starts once it has finished booting.
+pub const INIT_PATH: &str = "/init";
+
+/// Exit status of a process that was killed for an invalid memory access or instruction.
+pub const KILLED_STATUS: i32 = 139;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProcessState {
+    /// In the run queue, or about to be put there by the core it last ran on
+    Runnable,
+    /// Running on a core
+    Running,
+    /// Blocked in a system call until it is woken up
+    Waiting,
+    /// Exited with the given status, but not waited for yet
+    Zombie(i32),
+}
+
+pub struct Process {
+    pid: u64,
+    name: String,
+    parent: Option<u64>,
+    children: Vec<u64>,
+    state: ProcessState,
+    // Whether a core is still running the process or has not saved its state yet
+    on_cpu: bool,
+    futex: FutexState,
+    // Released once the process has exited and its core has switched away from it
+    space: Option<Arc<Mutex<AddressSpace>>>,
+    files: FileTable,
+    // The user state the process continues in the next time it runs
+    context: Cpu_Context,
+}
+
+impl Process {
+    /// Creates a process that runs the executable ```image```, in a new address space, with
+    /// standard input, output and error connected to the console.
+    ///
+    /// See ```loader::load``` for the errors.
+    pub fn from_elf(name: &str, image: &[u8], argv: &[&str], envp: &[&str]) -> Result<Self, Errno> {
+        let mut space = AddressSpace::new().map_err(|_| Errno::ENOMEM)?;
+        let context = loader::load(&mut space, image, argv, envp)?;
+
+        Ok(Process {
+            pid: NEXT_PID.fetch_add(1, Ordering::Relaxed),
+            name: String::from(name),
+            parent: None,
+            children: Vec::new(),
+            state: ProcessState::Runnable,
+            on_cpu: false,
+            futex: FutexState::Idle,
+            space: Some(Arc::new(Mutex::new(space))),
+            files: FileTable::with_console(),
+            context,
+        })
+    }
+
+    pub fn pid(&self) -> u64 {
+        self.pid
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn parent(&self) -> Option<u64> {
+        self.parent
+    }
+
+    pub fn state(&self) -> ProcessState {
+        self.state
+    }
+
+    /// Returns None once the process has exited.
+    pub fn address_space(&self) -> Option<&Arc<Mutex<AddressSpace>>> {
+        self.space.as_ref()
+    }
+
+    pub fn files(&self) -> &FileTable {
+        &self.files
+    }
+
+    pub fn files_mut(&mut self) -> &mut FileTable {
+        &mut self.files
+    }
+
+    /// The user state the process continues in the next time it runs.
+    pub fn context(&self) -> &Cpu_Context {
+        &self.context
+    }
+
+    pub fn context_mut(&mut self) -> &mut Cpu_Context {
+        &mut self.context
+    }
+}
+
+/// Returns the process with the given pid, if it has not been reaped yet.
+pub fn find(pid: u64) -> Option<Arc<Mutex<Process>>> {
+    PROCESSES.lock().get(&pid).cloned()
+}
+
+/// Returns the ```index```th process that has not been reaped yet, in order of pid.
+pub fn nth(index: usize) -> Option<Arc<Mutex<Process>>> {
+    PROCESSES.lock().values().nth(index).cloned()
+}
+
+// The last component of path, used as the name of the process running it
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Starts a new process running the executable at ```path``` on the SD card, returning its pid.
+///
+/// The process becomes a child of ```parent```, or has no parent if None.
+pub fn spawn(path: &str, argv: &[&str], parent: Option<u64>) -> Result<u64, Errno> {
+    let image = fs::read_file(path)?;
+    let mut process = Process::from_elf(file_name(path), &image, argv, &[])?;
+    process.parent = parent;
+    let pid = process.pid;
+    let process = Arc::new(Mutex::new(process));
+
+    {
+        let mut processes = PROCESSES.lock();
+        if let Some(parent) = parent {
+            let parent = processes.get(&parent).ok_or(Errno::ESRCH)?;
+            parent.lock().children.push(pid);
+        }
+        processes.insert(pid, process.clone());
+    }
+
+    scheduler::make_runnable(process);
+    Ok(pid)
+}
+
+/// Replaces the program the current process runs with the executable at ```path```.
+///
+/// On success, ```context``` is replaced by the entry state of the new program and the number of
+/// arguments is returned, which ends up in x0. On failure the old program keeps running.
+pub fn exec(context: &mut Cpu_Context, path: &str, argv: &[&str]) -> Result<u64, Errno> {
+    let current = current().ok_or(Errno::ESRCH)?;
+    let image = fs::read_file(path)?;
+    let mut space = AddressSpace::new().map_err(|_| Errno::ENOMEM)?;
+    *context = loader::load(&mut space, &image, argv, &[])?;
+
+    let space = Arc::new(Mutex::new(space));
+    // Leave the old address space before it is dropped
+    address_space::switch_to(space.clone());
+    let old_space = {
+        let mut process = current.lock();
+        process.name = String::from(file_name(path));
+        process.space.replace(space)
+    };
+    drop(old_space);
+    Ok(argv.len() as u64)
+}
+
+/// Terminates the current process with ```status```.
+///
+/// The process keeps running on its core until the next call to ```scheduler::reschedule```.
+pub fn exit_current(status: i32) {
+    let current = match current() {
+        Some(current) => current,
+        None => return,
+    };
+
+    let mut processes = PROCESSES.lock();
+    let (pid, parent, children) = {
+        let mut process = current.lock();
+        process.state = ProcessState::Zombie(status);
+        process.files.clear();
+        let children = core::mem::take(&mut process.children);
+        (process.pid, process.parent, children)
+    };
+
+    // Nobody is going to wait for the children anymore, so the ones that exited already are reaped
+    for child_pid in children {
+        if let Some(child) = processes.get(&child_pid).cloned() {
+            let mut child = child.lock();
+            child.parent = None;
+            if matches!(child.state, ProcessState::Zombie(_)) {
+                drop(child);
+                processes.remove(&child_pid);
+            }
+        }
+    }
+
+    match parent.and_then(|parent| processes.get(&parent).cloned()) {
+        Some(parent) => scheduler::wake(&parent),
+        None => {
+            processes.remove(&pid);
+        }
+    }
+}
+
+/// Waits for a child of the current process to exit, either the one with ```pid``` or any if None.
+///
+/// Returns the pid and exit status of the child, which stays a zombie until it is passed to
+/// ```reap```. If no matching child has exited yet, the current process is put to sleep until one
+/// does and None is returned, in which case the caller must try again once it runs again. Fails with
+/// ECHILD if there is no matching child.
+pub fn wait(pid: Option<u64>) -> Result<Option<(u64, i32)>, Errno> {
+    let current = current().ok_or(Errno::ESRCH)?;
+    let processes = PROCESSES.lock();
+    let mut process = current.lock();
+
+    let candidates: Vec<u64> = process
+        .children
+        .iter()
+        .copied()
+        .filter(|child_pid| pid.map_or(true, |pid| pid == *child_pid))
+        .collect();
+    if candidates.is_empty() {
+        return Err(Errno::ECHILD);
+    }
+
+    for child_pid in candidates {
+        let state = match processes.get(&child_pid) {
+            Some(child) => child.lock().state,
+            None => continue,
+        };
+        if let ProcessState::Zombie(status) = state {
+            return Ok(Some((child_pid, status)));
+        }
+    }
+    process.state = ProcessState::Waiting;
+    Ok(None)
+}
+
+/// Removes the exited child ```pid``` of the current process, once ```wait``` returned it.
+pub fn reap(pid: u64) {
+    let current = match current() {
+        Some(current) => current,
+        None => return,
+    };
+    let mut processes = PROCESSES.lock();
+    current
+        .lock()
+        .children
+        .retain(|child_pid| *child_pid != pid);
+    processes.remove(&pid);
+}
+
+/// Terminates the current process after it performed an invalid operation, and switches the core
+/// to another process. ```context``` is the exception frame of the offending process.
+pub fn kill_current(context: &mut Cpu_Context, status: i32) {
+    exit_current(status);
+    scheduler::reschedule(context);
+}
@@ -0,0 +1,267 @@
+//! Scheduling processes onto cores
+//!
+//! Processes are only switched while they are in the kernel. A process keeps its core until it
+//! blocks, yields or exits in a system call, or is killed. On the way out of the kernel, its user
+//! state is saved from the exception frame, and the state of the next process in the run queue is
+//! put there instead, so the exception returns into that process.
+//!
+//! Every core has its own run queue in its per-CPU block. Processes are queued on the core that
+//! starts or wakes them, and a core whose queue is empty takes the next process from the queue of
+//! another core, so that work spreads out over idle cores.
+//!
+//! When there is nothing to run, the exception returns into ```idle``` instead, a kernel loop
+//! running on a per-core stack that polls the run queues. Cores that have not run a process yet poll
+//! them from their own idle loops.
+//!
+//! A process that is woken up while its core has not saved its state yet is left for that core to
+//! queue, so that no other core can pick it up with stale state.
+//!
+//! The sleeping primitives of ```raspi::concurrency``` block on wait queues kept here, keyed by the
+//! address they wait on. Since the kernel cannot switch away from a process in the middle of a
+//! system call, a process blocked on a wait queue keeps its core, which polls timeouts until the
+//! process is woken up.
+
+use core::hint;
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+};
+use generic_once_cell::OnceCell;
+use raspi::{
+    concurrency::{
+        mutex::{Mutex, RawMutex},
+        wait::{set_wait_hooks, wait_for_event},
+    },
+    cpu::{core_id, NUM_CORES},
+    exception::{enter_context, Cpu_Context},
+};
+
+use super::{Process, ProcessState};
+use crate::{
+    memory::{
+        accounting::FrameCategory,
+        address_space::{self, AddressSpace},
+        stack::register_stack,
+        vmalloc::vmalloc_for,
+    },
+    panic, percpu, stack_size, timer,
+};
+
+// The empty address space installed on cores that are not running a process
+static IDLE_SPACE: OnceCell<RawMutex, Arc<Mutex<AddressSpace>>> = OnceCell::new();
+
+// Processes blocked on a sleeping primitive, keyed by the address the primitive waits on
+static WAIT_QUEUES: Mutex<BTreeMap<usize, VecDeque<Arc<Mutex<Process>>>>> =
+    Mutex::new(BTreeMap::new());
+
+/// Creates the address space used by idle cores, and makes the sleeping primitives block on the
+/// scheduler's wait queues. Must be called once, before any process runs.
+pub fn init() -> Result<(), ()> {
+    IDLE_SPACE
+        .set(Arc::new(Mutex::new(AddressSpace::new()?)))
+        .map_err(|_| ())?;
+    set_wait_hooks(block_on, wake_up)
+}
+
+/// Returns the empty address space that idle cores run in.
+pub fn idle_space() -> Arc<Mutex<AddressSpace>> {
+    IDLE_SPACE
+        .get()
+        .expect("Scheduler is not initialized")
+        .clone()
+}
+
+/// Allocates the stack the calling core idles on once it ran out of processes.
+///
+/// Must be called on every core, after the kernel heap has been initialized.
+pub fn init_core() -> Result<(), ()> {
+    let stack = vmalloc_for(stack_size() as usize, FrameCategory::Stack).ok_or(())?;
+    let stack_top = stack.as_ptr() as u64 + stack_size();
+    register_stack(stack_top, stack_size(), core_id(), "scheduler idle")?;
+    percpu!(idle_stack_top).set(stack_top);
+    Ok(())
+}
+
+/// Returns the process the calling core is currently running.
+pub fn current() -> Option<Arc<Mutex<Process>>> {
+    percpu!(current_process).borrow().clone()
+}
+
+/// Adds a process that is not running anywhere yet to the run queue of the calling core.
+pub(super) fn make_runnable(process: Arc<Mutex<Process>>) {
+    percpu!(run_queue).lock().push_back(process);
+}
+
+// Takes the next process from the calling core's run queue, or from that of another core
+fn pop_next() -> Option<Arc<Mutex<Process>>> {
+    let core = core_id() as usize;
+    if let Some(next) = percpu!(run_queue).lock().pop_front() {
+        return Some(next);
+    }
+    (1..NUM_CORES)
+        .map(|offset| (core + offset) % NUM_CORES)
+        .find_map(|other| percpu::cpu(other).run_queue.lock().pop_front())
+}
+
+/// Makes ```process``` runnable again if it is waiting.
+pub(super) fn wake(process: &Arc<Mutex<Process>>) {
+    let mut locked = process.lock();
+    if locked.state != ProcessState::Waiting {
+        return;
+    }
+    locked.state = ProcessState::Runnable;
+    // Otherwise its core queues it once it saved its state
+    if !locked.on_cpu {
+        percpu!(run_queue).lock().push_back(process.clone());
+    }
+}
+
+// Wait hook of the sleeping primitives. Blocks the current process on the wait queue of key until
+// wake_up is called for it, or sleeps in WFE when the core is not running a process
+fn block_on(key: usize, should_block: &dyn Fn() -> bool) {
+    let current = match current() {
+        Some(current) => current,
+        None => return wait_for_event(should_block),
+    };
+    {
+        let mut queues = WAIT_QUEUES.lock();
+        if !should_block() {
+            return;
+        }
+        current.lock().state = ProcessState::Waiting;
+        queues.entry(key).or_default().push_back(current.clone());
+    }
+
+    // The process keeps its core, so wake leaves it for us rather than queueing it
+    while current.lock().state == ProcessState::Waiting {
+        timer::run_expired();
+        panic::halt_if_panicking();
+        hint::spin_loop();
+    }
+    let mut current = current.lock();
+    if current.state == ProcessState::Runnable {
+        current.state = ProcessState::Running;
+    }
+}
+
+// Wake hook of the sleeping primitives. Wakes up the first or all processes blocked on key
+fn wake_up(key: usize, all: bool) {
+    let woken: VecDeque<_> = {
+        let mut queues = WAIT_QUEUES.lock();
+        let queue = match queues.get_mut(&key) {
+            Some(queue) => queue,
+            None => return,
+        };
+        let woken = match all {
+            true => core::mem::take(queue),
+            false => queue.pop_front().into_iter().collect(),
+        };
+        if queue.is_empty() {
+            queues.remove(&key);
+        }
+        woken
+    };
+    for process in &woken {
+        wake(process);
+    }
+}
+
+/// Lets the other runnable processes run before the current one continues.
+pub fn yield_current() {
+    if let Some(current) = current() {
+        let mut current = current.lock();
+        if current.state == ProcessState::Running {
+            current.state = ProcessState::Runnable;
+        }
+    }
+}
+
+/// Switches the calling core to another process if the current one stopped running.
+///
+/// Must be called on the way out of every exception taken from a process, with the exception's
+/// context, which is replaced by that of the process to continue.
+pub fn reschedule(context: &mut Cpu_Context) {
+    // Timeouts may wake up processes, which is best done before picking the next one
+    timer::run_expired();
+
+    let current = match current() {
+        Some(current) => current,
+        None => return,
+    };
+    {
+        let mut locked = current.lock();
+        if locked.state == ProcessState::Running {
+            return;
+        }
+        locked.context = *context;
+        locked.on_cpu = false;
+        if locked.state == ProcessState::Runnable {
+            percpu!(run_queue).lock().push_back(current.clone());
+        }
+    }
+
+    let next = pop_next();
+    *context = match next {
+        Some(next) => install(next),
+        None => go_idle(),
+    };
+
+    // Only now that the core switched away can the address space of an exited process be freed
+    let space = {
+        let mut locked = current.lock();
+        match locked.state {
+            ProcessState::Zombie(_) => locked.space.take(),
+            _ => None,
+        }
+    };
+    drop(space);
+}
+
+/// Starts running the next runnable process on the calling core, if there is one, in which case it
+/// never returns. Meant to be polled by idle loops.
+///
+/// Must be called on the regular stack, since that stack is abandoned. See ```enter_context```.
+pub fn run_next() {
+    let next = pop_next();
+    if let Some(next) = next {
+        let context = install(next);
+        let stack_top = percpu!(exception_stack_top).get();
+        // SAFETY: We are running on the regular stack, nothing on it needs to be dropped anymore, and
+        // stack_top belongs to this core
+        unsafe { enter_context(&context, stack_top) }
+    }
+}
+
+// Makes process the current process of the calling core, returning the context to continue it in
+fn install(process: Arc<Mutex<Process>>) -> Cpu_Context {
+    let (space, context) = {
+        let mut locked = process.lock();
+        locked.state = ProcessState::Running;
+        locked.on_cpu = true;
+        let space = locked
+            .space
+            .clone()
+            .expect("Runnable process has no address space");
+        (space, locked.context)
+    };
+    address_space::switch_to(space);
+    percpu!(current_process).replace(Some(process));
+    context
+}
+
+// Leaves the calling core without a process, returning the context of its idle loop
+fn go_idle() -> Cpu_Context {
+    address_space::switch_to(idle_space());
+    percpu!(current_process).replace(None);
+    Cpu_Context::new_kernel(idle as usize as u64, percpu!(idle_stack_top).get())
+}
+
+extern "C" fn idle() -> ! {
+    loop {
+        timer::run_expired();
+        run_next();
+        panic::halt_if_panicking();
+        hint::spin_loop();
+    }
+}
@@ -0,0 +1,160 @@
+use core::{fmt::Debug, marker::PhantomData};
+
+use raspi::exception::Cpu_Context;
+
+use super::errno::Errno;
+use crate::memory::address_space::USER_SPACE_END;
+
+/// The raw arguments of a system call, as passed in x0 to x5.
+#[derive(Clone, Copy, Debug)]
+pub struct SyscallArgs(pub [u64; 6]);
+
+impl SyscallArgs {
+    pub fn from_context(context: &Cpu_Context) -> Self {
+        let mut args = [0; 6];
+        args.copy_from_slice(&context.gpr[0..6]);
+        SyscallArgs(args)
+    }
+
+    /// Decodes argument ```index``` as a ```T```.
+    pub fn get<T: FromArg>(&self, index: usize) -> Result<T, Errno> {
+        T::from_arg(*self.0.get(index).ok_or(Errno::EINVAL)?)
+    }
+
+    /// Decodes the leading arguments as a tuple, e.g.
+    /// ```let (fd, buf, len): (u32, UserPtr<u8>, usize) = args.decode()?;```
+    pub fn decode<T: FromArgs>(&self) -> Result<T, Errno> {
+        T::from_args(&self.0)
+    }
+}
+
+/// A type that can be decoded from a single raw system call argument.
+pub trait FromArg: Sized {
+    fn from_arg(raw: u64) -> Result<Self, Errno>;
+}
+
+impl FromArg for u64 {
+    fn from_arg(raw: u64) -> Result<Self, Errno> {
+        Ok(raw)
+    }
+}
+
+impl FromArg for usize {
+    fn from_arg(raw: u64) -> Result<Self, Errno> {
+        Ok(raw as usize)
+    }
+}
+
+impl FromArg for i64 {
+    fn from_arg(raw: u64) -> Result<Self, Errno> {
+        Ok(raw as i64)
+    }
+}
+
+// The AAPCS64 leaves the upper half of a register holding a 32 bit value unspecified, so it is
+// ignored rather than rejected
+impl FromArg for u32 {
+    fn from_arg(raw: u64) -> Result<Self, Errno> {
+        Ok(raw as u32)
+    }
+}
+
+impl FromArg for i32 {
+    fn from_arg(raw: u64) -> Result<Self, Errno> {
+        Ok(raw as i32)
+    }
+}
+
+impl FromArg for bool {
+    fn from_arg(raw: u64) -> Result<Self, Errno> {
+        Ok(raw as u32 != 0)
+    }
+}
+
+impl<T> FromArg for UserPtr<T> {
+    fn from_arg(raw: u64) -> Result<Self, Errno> {
+        UserPtr::new(raw).ok_or(Errno::EFAULT)
+    }
+}
+
+/// A pointer into the address space of the calling process.
+///
+/// Decoding one only checks that it points into the lower half. It is never dereferenced directly,
+/// since the memory behind it may be unmapped or change at any time.
+pub struct UserPtr<T> {
+    addr: u64,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T> UserPtr<T> {
+    /// Returns None if ```addr``` is not a user address.
+    pub fn new(addr: u64) -> Option<Self> {
+        match addr < USER_SPACE_END {
+            true => Some(UserPtr {
+                addr,
+                _marker: PhantomData,
+            }),
+            false => None,
+        }
+    }
+
+    pub fn addr(&self) -> u64 {
+        self.addr
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.addr == 0
+    }
+
+    /// Reinterprets the pointer as pointing to a ```U```.
+    pub fn cast<U>(&self) -> UserPtr<U> {
+        UserPtr {
+            addr: self.addr,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns a pointer ```count``` elements further along, or None if that leaves the lower half.
+    pub fn add(&self, count: usize) -> Option<Self> {
+        let offset = (count as u64).checked_mul(core::mem::size_of::<T>() as u64)?;
+        UserPtr::new(self.addr.checked_add(offset)?)
+    }
+}
+
+impl<T> Clone for UserPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for UserPtr<T> {}
+
+impl<T> Debug for UserPtr<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "UserPtr({:#x})", self.addr)
+    }
+}
+
+/// A tuple of types that can be decoded from the leading raw system call arguments.
+pub trait FromArgs: Sized {
+    fn from_args(raw: &[u64; 6]) -> Result<Self, Errno>;
+}
+
+macro_rules! impl_from_args {
+    ($($ty:ident: $index:tt),*) => {
+        impl<$($ty: FromArg),*> FromArgs for ($($ty,)*) {
+            #[allow(unused_variables)]
+            fn from_args(raw: &[u64; 6]) -> Result<Self, Errno> {
+                Ok(($($ty::from_arg(raw[$index])?,)*))
+            }
+        }
+    };
+}
+
+impl_from_args!();
+impl_from_args!(A: 0);
+impl_from_args!(A: 0, B: 1);
+impl_from_args!(A: 0, B: 1, C: 2);
+impl_from_args!(A: 0, B: 1, C: 2, D: 3);
+impl_from_args!(A: 0, B: 1, C: 2, D: 3, E: 4);
+impl_from_args!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5);
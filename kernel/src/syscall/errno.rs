@@ -0,0 +1,117 @@
+use core::fmt::Display;
+
+/// The reason a system call failed.
+///
+/// The values match the ones used by Linux. A failed system call returns the negated value in x0,
+/// so any return value in ```-4095..0``` is an error.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u64)]
+pub enum Errno {
+    /// Operation not permitted
+    EPERM = 1,
+    /// No such file or directory
+    ENOENT = 2,
+    /// No such process
+    ESRCH = 3,
+    /// Interrupted system call
+    EINTR = 4,
+    /// I/O error
+    EIO = 5,
+    /// Argument list too long
+    E2BIG = 7,
+    /// Exec format error
+    ENOEXEC = 8,
+    /// Bad file descriptor
+    EBADF = 9,
+    /// No child processes
+    ECHILD = 10,
+    /// Try again
+    EAGAIN = 11,
+    /// Out of memory
+    ENOMEM = 12,
+    /// Permission denied
+    EACCES = 13,
+    /// Bad address
+    EFAULT = 14,
+    /// Device or resource busy
+    EBUSY = 16,
+    /// File exists
+    EEXIST = 17,
+    /// No such device
+    ENODEV = 19,
+    /// Not a directory
+    ENOTDIR = 20,
+    /// Is a directory
+    EISDIR = 21,
+    /// Invalid argument
+    EINVAL = 22,
+    /// Too many open files
+    EMFILE = 24,
+    /// No space left on device
+    ENOSPC = 28,
+    /// Illegal seek
+    ESPIPE = 29,
+    /// Read-only file system
+    EROFS = 30,
+    /// Result out of range
+    ERANGE = 34,
+    /// File name too long
+    ENAMETOOLONG = 36,
+    /// Function not implemented
+    ENOSYS = 38,
+    /// Timed out
+    ETIMEDOUT = 110,
+    /// Never returned to user code. The system call blocked, and is made again from the start once
+    /// the process runs again
+    ERESTARTSYS = 512,
+}
+
+impl Errno {
+    const ALL: [Errno; 27] = [
+        Errno::EPERM,
+        Errno::ENOENT,
+        Errno::ESRCH,
+        Errno::EINTR,
+        Errno::EIO,
+        Errno::E2BIG,
+        Errno::ENOEXEC,
+        Errno::EBADF,
+        Errno::ECHILD,
+        Errno::EAGAIN,
+        Errno::ENOMEM,
+        Errno::EACCES,
+        Errno::EFAULT,
+        Errno::EBUSY,
+        Errno::EEXIST,
+        Errno::ENODEV,
+        Errno::ENOTDIR,
+        Errno::EISDIR,
+        Errno::EINVAL,
+        Errno::EMFILE,
+        Errno::ENOSPC,
+        Errno::ESPIPE,
+        Errno::EROFS,
+        Errno::ERANGE,
+        Errno::ENAMETOOLONG,
+        Errno::ENOSYS,
+        Errno::ETIMEDOUT,
+    ];
+
+    /// Returns the value placed in x0 when a system call fails with this error.
+    pub fn to_return_value(self) -> u64 {
+        (self as u64).wrapping_neg()
+    }
+
+    /// Decodes the error a system call failed with from its return value, if it failed.
+    pub fn from_return_value(value: u64) -> Option<Errno> {
+        let code = value.wrapping_neg();
+        Errno::ALL.into_iter().find(|errno| *errno as u64 == code)
+    }
+}
+
+impl Display for Errno {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?} ({})", self, *self as u64)
+    }
+}
@@ -0,0 +1,162 @@
+//! System calls that operate on file descriptors
+
+use alloc::{sync::Arc, vec};
+use raspi::{concurrency::mutex::Mutex, exception::Cpu_Context};
+
+use super::{process::MAX_STRING_LEN, Errno, SyscallArgs, SyscallResult, UserPtr};
+use crate::{
+    fs,
+    memory::user_access::{copy_from_user, copy_to_user, read_user_string, write_user, UserCopy},
+    peripherals::UART,
+    process::{self, files::File, scheduler},
+};
+
+/// Flag of ```open``` that selects the access mode. Only reading is supported so far.
+pub const O_ACCMODE: u32 = 0b11;
+pub const O_RDONLY: u32 = 0;
+
+// Most bytes copied through the kernel at once
+const CHUNK_SIZE: usize = 4096;
+
+/// Longest name a ```Dirent``` can hold.
+pub const MAX_NAME_LEN: usize = 256;
+
+/// A directory entry, as filled in by ```readdir```.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Dirent {
+    pub size: u64,
+    /// 1 for directories, 0 for regular files
+    pub is_dir: u32,
+    pub name_len: u32,
+    /// Not null terminated
+    pub name: [u8; MAX_NAME_LEN],
+}
+
+// SAFETY: Plain integers without padding
+unsafe impl UserCopy for Dirent {}
+
+fn get_file(fd: u32) -> Result<Arc<Mutex<File>>, Errno> {
+    let current = process::current().ok_or(Errno::ESRCH)?;
+    let file = current.lock().files().get(fd as usize);
+    file
+}
+
+/// read(fd: u32, buf: *mut u8, len: usize) -> bytes read
+///
+/// Reads at most one chunk at a time, so a short read does not mean the end of the file. Reading
+/// the console blocks until at least one byte has been received.
+pub fn sys_read(_context: &mut Cpu_Context, args: SyscallArgs) -> SyscallResult {
+    let (fd, buf, len): (u32, UserPtr<u8>, usize) = args.decode()?;
+    let file = get_file(fd)?;
+    let mut file = file.lock();
+
+    match &mut *file {
+        File::Console => {
+            let mut chunk = [0u8; 256];
+            let mut count = 0;
+            {
+                let mut uart = UART.lock();
+                while count < len.min(chunk.len()) {
+                    match uart.try_receive_byte() {
+                        Some(byte) => chunk[count] = byte,
+                        None => break,
+                    }
+                    count += 1;
+                }
+            }
+            // Without receive interrupts, the only way to wait for input is to poll, letting the
+            // other processes run in between
+            if count == 0 && len != 0 {
+                scheduler::yield_current();
+                return Err(Errno::ERESTARTSYS);
+            }
+            copy_to_user(buf, &chunk[..count])?;
+            Ok(count as u64)
+        }
+        File::Disk { path, offset } => {
+            let mut chunk = vec![0u8; len.min(CHUNK_SIZE)];
+            let read = fs::read_at(path, *offset, &mut chunk)?;
+            copy_to_user(buf, &chunk[..read])?;
+            *offset += read as u64;
+            Ok(read as u64)
+        }
+    }
+}
+
+/// write(fd: u32, buf: *const u8, len: usize) -> bytes written
+pub fn sys_write(_context: &mut Cpu_Context, args: SyscallArgs) -> SyscallResult {
+    let (fd, buf, len): (u32, UserPtr<u8>, usize) = args.decode()?;
+    let file = get_file(fd)?;
+    let file = file.lock();
+
+    match &*file {
+        File::Console => {
+            let mut chunk = [0u8; 256];
+            let mut written = 0;
+            while written < len {
+                let size = (len - written).min(chunk.len());
+                let src = buf.add(written).ok_or(Errno::EFAULT)?;
+                copy_from_user(&mut chunk[..size], src)?;
+                // The UART is not locked while touching user memory, which may fault
+                let mut uart = UART.lock();
+                for byte in &chunk[..size] {
+                    uart.send_byte(*byte);
+                }
+                written += size;
+            }
+            Ok(written as u64)
+        }
+        // Files are only ever opened for reading
+        File::Disk { .. } => Err(Errno::EBADF),
+    }
+}
+
+/// open(path: *const u8, flags: u32) -> fd
+pub fn sys_open(_context: &mut Cpu_Context, args: SyscallArgs) -> SyscallResult {
+    let (path, flags): (UserPtr<u8>, u32) = args.decode()?;
+    let path = read_user_string(path, MAX_STRING_LEN)?;
+    if flags & O_ACCMODE != O_RDONLY {
+        return Err(Errno::EROFS);
+    }
+    fs::check_file(&path)?;
+
+    let current = process::current().ok_or(Errno::ESRCH)?;
+    let fd = current
+        .lock()
+        .files_mut()
+        .insert(File::Disk { path, offset: 0 })?;
+    Ok(fd as u64)
+}
+
+/// close(fd: u32) -> 0
+pub fn sys_close(_context: &mut Cpu_Context, args: SyscallArgs) -> SyscallResult {
+    let (fd,): (u32,) = args.decode()?;
+    let current = process::current().ok_or(Errno::ESRCH)?;
+    current.lock().files_mut().remove(fd as usize)?;
+    Ok(0)
+}
+
+/// readdir(path: *const u8, index: usize, entry: *mut Dirent) -> 1, or 0 past the last entry
+pub fn sys_readdir(_context: &mut Cpu_Context, args: SyscallArgs) -> SyscallResult {
+    let (path, index, entry): (UserPtr<u8>, usize, UserPtr<Dirent>) = args.decode()?;
+    let path = read_user_string(path, MAX_STRING_LEN)?;
+
+    let found = match fs::read_dir_entry(&path, index)? {
+        Some(found) => found,
+        None => return Ok(0),
+    };
+    let mut dirent = Dirent {
+        size: found.size,
+        is_dir: found.is_dir as u32,
+        name_len: 0,
+        name: [0; MAX_NAME_LEN],
+    };
+    let name = found.name.as_bytes();
+    let name_len = name.len().min(MAX_NAME_LEN);
+    dirent.name[..name_len].copy_from_slice(&name[..name_len]);
+    dirent.name_len = name_len as u32;
+
+    write_user(entry, &dirent)?;
+    Ok(1)
+}
@@ -0,0 +1,167 @@
+//! System calls that manage the address space of the calling process
+
+use alloc::sync::Arc;
+use raspi::{concurrency::mutex::Mutex, exception::Cpu_Context, memory::page_table::Protection};
+
+use super::{Errno, SyscallArgs, SyscallResult};
+use crate::{
+    memory::{
+        address_space::{AddressSpace, USER_SPACE_END},
+        vma::VmaKind,
+    },
+    page_size,
+    process::{
+        self,
+        files::File,
+        loader::{USER_STACK_SIZE, USER_STACK_TOP},
+    },
+};
+
+pub const PROT_NONE: u32 = 0;
+pub const PROT_READ: u32 = 1;
+pub const PROT_WRITE: u32 = 2;
+pub const PROT_EXEC: u32 = 4;
+
+pub const MAP_SHARED: u32 = 0x01;
+pub const MAP_PRIVATE: u32 = 0x02;
+pub const MAP_FIXED: u32 = 0x10;
+pub const MAP_ANONYMOUS: u32 = 0x20;
+
+// Mappings placed by the kernel are put as high as possible below this, leaving a guard page
+// between them and the stack
+const MMAP_TOP: u64 = USER_STACK_TOP - USER_STACK_SIZE - 0x1000;
+
+fn current_space() -> Result<Arc<Mutex<AddressSpace>>, Errno> {
+    let current = process::current().ok_or(Errno::ESRCH)?;
+    let space = current.lock().address_space().cloned();
+    space.ok_or(Errno::ESRCH)
+}
+
+// Mappings are always readable, so PROT_READ makes no difference
+fn decode_protection(prot: u32) -> Result<Protection, Errno> {
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(Errno::EINVAL);
+    }
+    Ok(Protection {
+        writable: prot & PROT_WRITE != 0,
+        executable: prot & PROT_EXEC != 0,
+        user: prot != PROT_NONE,
+    })
+}
+
+// Checks that addr is page aligned and rounds len up to whole pages, returning the end of the range
+fn page_range(addr: u64, len: u64) -> Result<u64, Errno> {
+    if addr % page_size() != 0 || len == 0 {
+        return Err(Errno::EINVAL);
+    }
+    addr.checked_add(len)
+        .and_then(|end| end.checked_next_multiple_of(page_size()))
+        .ok_or(Errno::ENOMEM)
+}
+
+/// mmap(addr: u64, len: usize, prot: u32, flags: u32, fd: u32, offset: u64) -> address
+///
+/// File mappings are always private, since files can only be read. ```addr``` is only a hint
+/// unless ```MAP_FIXED``` is given, in which case anything mapped there before is replaced.
+pub fn sys_mmap(_context: &mut Cpu_Context, args: SyscallArgs) -> SyscallResult {
+    let (addr, len, prot, flags, fd, offset): (u64, u64, u32, u32, u32, u64) = args.decode()?;
+    let protection = decode_protection(prot)?;
+    if len == 0 || offset % page_size() != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let size = len
+        .checked_next_multiple_of(page_size())
+        .ok_or(Errno::ENOMEM)?;
+
+    let shared = match flags & (MAP_SHARED | MAP_PRIVATE) {
+        MAP_SHARED => true,
+        MAP_PRIVATE => false,
+        _ => return Err(Errno::EINVAL),
+    };
+    let kind = match flags & MAP_ANONYMOUS != 0 {
+        true => VmaKind::Anonymous,
+        false => {
+            let current = process::current().ok_or(Errno::ESRCH)?;
+            let file = current.lock().files().get(fd as usize)?;
+            let file = file.lock();
+            match &*file {
+                // Writes to a shared mapping would have to reach the file
+                File::Disk { .. } if shared && protection.writable => return Err(Errno::EACCES),
+                File::Disk { path, .. } => VmaKind::File {
+                    path: Arc::from(path.as_str()),
+                    offset,
+                },
+                File::Console => return Err(Errno::ENODEV),
+            }
+        }
+    };
+
+    let space = current_space()?;
+    let mut space = space.lock();
+    if flags & MAP_FIXED != 0 {
+        // A range that wraps around is just as far out of the user range. The null page is never
+        // mapped
+        let end = page_range(addr, size).map_err(|_| Errno::EINVAL)?;
+        if addr < page_size() || end > USER_SPACE_END {
+            return Err(Errno::EINVAL);
+        }
+        // Fails before anything is unmapped, so the old mapping survives
+        space
+            .map_replacing(addr, size, protection, kind)
+            .map_err(|_| Errno::ENOMEM)?;
+        return Ok(addr);
+    }
+
+    let hint_free = addr % page_size() == 0
+        && addr >= page_size()
+        && addr.checked_add(size).map_or(false, |end| {
+            end <= MMAP_TOP && !space.vmas().overlaps(addr, end)
+        });
+    let start = match hint_free {
+        true => addr,
+        false => space
+            .find_free(size, page_size(), MMAP_TOP)
+            .ok_or(Errno::ENOMEM)?,
+    };
+
+    space
+        .map(start, size, protection, kind)
+        .map_err(|_| Errno::ENOMEM)?;
+    Ok(start)
+}
+
+/// munmap(addr: u64, len: usize) -> 0
+pub fn sys_munmap(_context: &mut Cpu_Context, args: SyscallArgs) -> SyscallResult {
+    let (addr, len): (u64, u64) = args.decode()?;
+    let end = page_range(addr, len)?;
+    let space = current_space()?;
+    let result = space.lock().unmap(addr, end);
+    result.map_err(|_| Errno::EINVAL)?;
+    Ok(0)
+}
+
+/// mprotect(addr: u64, len: usize, prot: u32) -> 0
+pub fn sys_mprotect(_context: &mut Cpu_Context, args: SyscallArgs) -> SyscallResult {
+    let (addr, len, prot): (u64, u64, u32) = args.decode()?;
+    let protection = decode_protection(prot)?;
+    let end = page_range(addr, len)?;
+    let space = current_space()?;
+    let result = space.lock().protect(addr, end, protection);
+    // Part of the range is not mapped
+    result.map_err(|_| Errno::ENOMEM)?;
+    Ok(0)
+}
+
+/// brk(addr: u64) -> new end of the heap
+///
+/// Moves the end of the heap to ```addr```. Never fails; if the heap cannot be moved, or ```addr```
+/// is 0, the current end is returned unchanged.
+pub fn sys_brk(_context: &mut Cpu_Context, args: SyscallArgs) -> SyscallResult {
+    let (addr,): (u64,) = args.decode()?;
+    let space = current_space()?;
+    let mut space = space.lock();
+    if addr != 0 {
+        let _ = space.set_brk(addr);
+    }
+    Ok(space.brk())
+}
@@ -0,0 +1,206 @@
+//! System calls
+//!
+//! User code makes a system call by executing ```SVC #0``` with the system call number in x8 and up
+//! to six arguments in x0 to x5. The result is returned in x0: a non-negative value on success, or a
+//! negated ```Errno``` on failure. All other registers are preserved.
+//!
+//! System calls are looked up in a table that is filled in with ```register```. A handler receives
+//! the raw arguments and decodes them into the types it expects, so an argument that cannot be
+//! decoded fails the call before the handler does anything. Any system call can be traced, which
+//! prints every call of it along with its arguments and result.
+//!
+//! A system call that has to block puts the calling process to sleep and fails with
+//! ```ERESTARTSYS```. The process is then switched out, and makes the same system call again once
+//! it is woken up.
+
+use core::{
+    fmt::Display,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use raspi::{concurrency::rwlock::RwLock, exception::Cpu_Context};
+
+use crate::{kprints, percpu, process::scheduler};
+
+pub mod args;
+pub mod errno;
+pub mod file;
+pub mod memory;
+pub mod process;
+pub mod system;
+
+pub use self::{
+    args::{FromArg, FromArgs, SyscallArgs, UserPtr},
+    errno::Errno,
+};
+
+/// Number of entries in the system call table. Valid system call numbers are below this.
+pub const MAX_SYSCALLS: usize = 64;
+
+pub const SYS_READ: usize = 0;
+pub const SYS_WRITE: usize = 1;
+pub const SYS_OPEN: usize = 2;
+pub const SYS_CLOSE: usize = 3;
+pub const SYS_EXIT: usize = 4;
+pub const SYS_GETPID: usize = 5;
+pub const SYS_GETPPID: usize = 6;
+pub const SYS_SPAWN: usize = 7;
+pub const SYS_EXEC: usize = 8;
+pub const SYS_WAIT: usize = 9;
+pub const SYS_YIELD: usize = 10;
+pub const SYS_MMAP: usize = 11;
+pub const SYS_MUNMAP: usize = 12;
+pub const SYS_MPROTECT: usize = 13;
+pub const SYS_BRK: usize = 14;
+pub const SYS_READDIR: usize = 15;
+pub const SYS_MEMINFO: usize = 16;
+pub const SYS_PROCINFO: usize = 17;
+pub const SYS_REBOOT: usize = 18;
+pub const SYS_FUTEX: usize = 19;
+
+pub type SyscallResult = Result<u64, Errno>;
+
+/// Implements a system call. The context is that of the calling user code, and may be modified
+/// for system calls that change what it runs next. The return value is written to x0 afterwards.
+pub type SyscallFn = fn(&mut Cpu_Context, SyscallArgs) -> SyscallResult;
+
+/// A system call table entry.
+#[derive(Clone, Copy)]
+pub struct Syscall {
+    pub name: &'static str,
+    /// Number of arguments the system call takes, only used for tracing
+    pub num_args: usize,
+    pub handler: SyscallFn,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    syscall: Syscall,
+    traced: bool,
+}
+
+static TABLE: RwLock<[Option<Entry>; MAX_SYSCALLS]> = RwLock::new([None; MAX_SYSCALLS]);
+static TRACE_ALL: AtomicBool = AtomicBool::new(false);
+
+/// Registers the kernel's system calls.
+pub fn init() -> Result<(), ()> {
+    let syscalls: [(usize, &'static str, usize, SyscallFn); 20] = [
+        (SYS_READ, "read", 3, file::sys_read),
+        (SYS_WRITE, "write", 3, file::sys_write),
+        (SYS_OPEN, "open", 2, file::sys_open),
+        (SYS_CLOSE, "close", 1, file::sys_close),
+        (SYS_EXIT, "exit", 1, process::sys_exit),
+        (SYS_GETPID, "getpid", 0, process::sys_getpid),
+        (SYS_GETPPID, "getppid", 0, process::sys_getppid),
+        (SYS_SPAWN, "spawn", 2, process::sys_spawn),
+        (SYS_EXEC, "exec", 2, process::sys_exec),
+        (SYS_WAIT, "wait", 2, process::sys_wait),
+        (SYS_YIELD, "yield", 0, process::sys_yield),
+        (SYS_MMAP, "mmap", 6, memory::sys_mmap),
+        (SYS_MUNMAP, "munmap", 2, memory::sys_munmap),
+        (SYS_MPROTECT, "mprotect", 3, memory::sys_mprotect),
+        (SYS_BRK, "brk", 1, memory::sys_brk),
+        (SYS_READDIR, "readdir", 3, file::sys_readdir),
+        (SYS_MEMINFO, "meminfo", 2, system::sys_meminfo),
+        (SYS_PROCINFO, "procinfo", 2, system::sys_procinfo),
+        (SYS_REBOOT, "reboot", 0, system::sys_reboot),
+        (SYS_FUTEX, "futex", 4, process::sys_futex),
+    ];
+    for (number, name, num_args, handler) in syscalls {
+        register(
+            number,
+            Syscall {
+                name,
+                num_args,
+                handler,
+            },
+        )?;
+    }
+    Ok(())
+}
+
+/// Installs ```syscall``` as system call ```number```.
+///
+/// Returns Err if the number is out of range or already taken.
+pub fn register(number: usize, syscall: Syscall) -> Result<(), ()> {
+    let mut table = TABLE.write();
+    let entry = table.get_mut(number).ok_or(())?;
+    if entry.is_some() {
+        return Err(());
+    }
+    *entry = Some(Entry {
+        syscall,
+        traced: false,
+    });
+    Ok(())
+}
+
+/// Controls whether calls of system call ```number``` are traced.
+///
+/// Returns Err if no such system call is registered.
+pub fn set_traced(number: usize, traced: bool) -> Result<(), ()> {
+    let mut table = TABLE.write();
+    let entry = table.get_mut(number).ok_or(())?.as_mut().ok_or(())?;
+    entry.traced = traced;
+    Ok(())
+}
+
+/// Controls whether every system call is traced, including calls of unregistered numbers.
+pub fn set_trace_all(traced: bool) {
+    TRACE_ALL.store(traced, Ordering::Relaxed);
+}
+
+/// The kernel's system call handler, registered with the raspi exception vectors.
+pub fn handle_syscall(context: &mut Cpu_Context) {
+    percpu::inc(percpu!(stats.syscalls));
+    let number = context.gpr[8] as usize;
+    let args = SyscallArgs::from_context(context);
+    // The entry is copied out, so the table is not locked while the system call runs
+    let entry = TABLE.read().get(number).copied().flatten();
+
+    let result = match entry {
+        Some(entry) => (entry.syscall.handler)(context, args),
+        None => Err(Errno::ENOSYS),
+    };
+
+    if TRACE_ALL.load(Ordering::Relaxed) || entry.map_or(false, |entry| entry.traced) {
+        trace(number, entry.map(|entry| entry.syscall), &args, &result);
+    }
+
+    match result {
+        Ok(value) => context.gpr[0] = value,
+        // Point back at the SVC, with the arguments still in place
+        Err(Errno::ERESTARTSYS) => context.elr_el1 -= 4,
+        Err(errno) => context.gpr[0] = errno.to_return_value(),
+    }
+
+    scheduler::reschedule(context);
+}
+
+fn trace(number: usize, syscall: Option<Syscall>, args: &SyscallArgs, result: &SyscallResult) {
+    let core = percpu!(core).get();
+    let (name, num_args) = match syscall {
+        Some(syscall) => (syscall.name, syscall.num_args),
+        // Without an entry the arguments are meaningless, but show them anyway
+        None => ("unknown", 6),
+    };
+    let args = TraceArgs(&args.0[..num_args.min(6)]);
+    match result {
+        Ok(value) => kprints!(core, "syscall {} {}({}) = {:#x}", number, name, args, value),
+        Err(errno) => kprints!(core, "syscall {} {}({}) = {}", number, name, args, errno),
+    }
+}
+
+struct TraceArgs<'a>(&'a [u64]);
+
+impl Display for TraceArgs<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (i, arg) in self.0.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{:#x}", arg)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+//! System calls that create, replace and wait for processes, and let them wait for each other
+
+use core::time::Duration;
+
+use alloc::{string::String, vec::Vec};
+use raspi::exception::Cpu_Context;
+
+use super::{Errno, SyscallArgs, SyscallResult, UserPtr};
+use crate::{
+    memory::user_access::{read_user, read_user_string, write_user},
+    process::{self, futex, scheduler},
+};
+
+/// Most arguments a program can be started with.
+pub const MAX_ARGS: usize = 64;
+/// Longest path or argument accepted from user code, excluding the terminator.
+pub const MAX_STRING_LEN: usize = 255;
+
+/// ```futex``` operation that sleeps while the word holds a value.
+pub const FUTEX_WAIT: u32 = 0;
+/// ```futex``` operation that wakes up processes sleeping on the word.
+pub const FUTEX_WAKE: u32 = 1;
+
+// Copies in a null terminated array of pointers to strings. A null array is an empty one
+fn read_argv(argv: UserPtr<u64>) -> Result<Vec<String>, Errno> {
+    let mut args = Vec::new();
+    if argv.is_null() {
+        return Ok(args);
+    }
+    loop {
+        let ptr = read_user(argv.add(args.len()).ok_or(Errno::EFAULT)?)?;
+        if ptr == 0 {
+            return Ok(args);
+        }
+        if args.len() == MAX_ARGS {
+            return Err(Errno::E2BIG);
+        }
+        let ptr = UserPtr::new(ptr).ok_or(Errno::EFAULT)?;
+        args.push(read_user_string(ptr, MAX_STRING_LEN)?);
+    }
+}
+
+/// exit(status: i32) -> !
+pub fn sys_exit(_context: &mut Cpu_Context, args: SyscallArgs) -> SyscallResult {
+    let (status,): (i32,) = args.decode()?;
+    process::exit_current(status);
+    // Never seen, the process does not run again
+    Ok(0)
+}
+
+/// getpid() -> pid
+pub fn sys_getpid(_context: &mut Cpu_Context, _args: SyscallArgs) -> SyscallResult {
+    let current = process::current().ok_or(Errno::ESRCH)?;
+    let pid = current.lock().pid();
+    Ok(pid)
+}
+
+/// getppid() -> pid, or 0 if the process has no parent
+pub fn sys_getppid(_context: &mut Cpu_Context, _args: SyscallArgs) -> SyscallResult {
+    let current = process::current().ok_or(Errno::ESRCH)?;
+    let parent = current.lock().parent();
+    Ok(parent.unwrap_or(0))
+}
+
+/// spawn(path: *const u8, argv: *const *const u8) -> pid
+pub fn sys_spawn(_context: &mut Cpu_Context, args: SyscallArgs) -> SyscallResult {
+    let (path, argv): (UserPtr<u8>, UserPtr<u64>) = args.decode()?;
+    let path = read_user_string(path, MAX_STRING_LEN)?;
+    let argv = read_argv(argv)?;
+    let argv: Vec<&str> = argv.iter().map(String::as_str).collect();
+
+    let parent = process::current().ok_or(Errno::ESRCH)?.lock().pid();
+    process::spawn(&path, &argv, Some(parent))
+}
+
+/// exec(path: *const u8, argv: *const *const u8) -> argc, in the new program
+pub fn sys_exec(context: &mut Cpu_Context, args: SyscallArgs) -> SyscallResult {
+    let (path, argv): (UserPtr<u8>, UserPtr<u64>) = args.decode()?;
+    let path = read_user_string(path, MAX_STRING_LEN)?;
+    let argv = read_argv(argv)?;
+    let argv: Vec<&str> = argv.iter().map(String::as_str).collect();
+
+    process::exec(context, &path, &argv)
+}
+
+/// wait(pid: i64, status: *mut i32) -> pid
+///
+/// Waits for any child if ```pid``` is -1. The exit status is only written if ```status``` is not
+/// null.
+pub fn sys_wait(_context: &mut Cpu_Context, args: SyscallArgs) -> SyscallResult {
+    let (pid, status): (i64, UserPtr<i32>) = args.decode()?;
+    let pid = match pid {
+        -1 => None,
+        pid if pid > 0 => Some(pid as u64),
+        _ => return Err(Errno::EINVAL),
+    };
+
+    match process::wait(pid)? {
+        Some((pid, exit_status)) => {
+            // The child is only reaped once its status was delivered, so a bad pointer loses nothing
+            if !status.is_null() {
+                write_user(status, &exit_status)?;
+            }
+            process::reap(pid);
+            Ok(pid)
+        }
+        None => Err(Errno::ERESTARTSYS),
+    }
+}
+
+/// yield() -> 0
+pub fn sys_yield(_context: &mut Cpu_Context, _args: SyscallArgs) -> SyscallResult {
+    scheduler::yield_current();
+    Ok(0)
+}
+
+/// futex(addr: *mut u32, op: u32, val: u32, timeout_ns: u64) -> 0, or the number of processes woken
+///
+/// With ```FUTEX_WAIT```, sleeps until woken up if the word at ```addr``` still holds ```val```,
+/// failing with EAGAIN otherwise. A ```timeout_ns``` of 0 waits without a timeout, otherwise the
+/// call fails with ETIMEDOUT once it expired. With ```FUTEX_WAKE```, wakes up at most ```val```
+/// processes sleeping on the word.
+pub fn sys_futex(_context: &mut Cpu_Context, args: SyscallArgs) -> SyscallResult {
+    let (addr, op, val, timeout_ns): (UserPtr<u32>, u32, u32, u64) = args.decode()?;
+    match op {
+        FUTEX_WAIT => {
+            let timeout = match timeout_ns {
+                0 => None,
+                ns => Some(Duration::from_nanos(ns)),
+            };
+            match futex::wait(addr, val, timeout)? {
+                true => Ok(0),
+                false => Err(Errno::ERESTARTSYS),
+            }
+        }
+        FUTEX_WAKE => futex::wake(addr, val),
+        _ => Err(Errno::EINVAL),
+    }
+}
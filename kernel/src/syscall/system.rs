@@ -0,0 +1,106 @@
+//! System calls that report on or control the system as a whole
+
+use alloc::format;
+use raspi::exception::Cpu_Context;
+
+use super::{SyscallArgs, SyscallResult, UserPtr};
+use crate::{
+    kprintln,
+    memory::{
+        memory_stats,
+        user_access::{copy_to_user, write_user, UserCopy},
+    },
+    peripherals::POWER,
+    process::{self, ProcessState},
+};
+
+/// Longest process name a ```ProcInfo``` can hold.
+pub const MAX_PROC_NAME_LEN: usize = 32;
+
+pub const PROC_RUNNABLE: u32 = 0;
+pub const PROC_RUNNING: u32 = 1;
+pub const PROC_WAITING: u32 = 2;
+pub const PROC_ZOMBIE: u32 = 3;
+
+/// A snapshot of a process, as filled in by ```procinfo```.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ProcInfo {
+    pub pid: u64,
+    /// 0 if the process has no parent
+    pub parent: u64,
+    pub resident_pages: u64,
+    /// One of the ```PROC_*``` constants
+    pub state: u32,
+    /// Only meaningful for zombies
+    pub exit_status: i32,
+    pub name_len: u64,
+    /// Not null terminated, and cut off if too long
+    pub name: [u8; MAX_PROC_NAME_LEN],
+}
+
+// SAFETY: Plain integers without padding
+unsafe impl UserCopy for ProcInfo {}
+
+/// meminfo(buf: *mut u8, len: usize) -> bytes written
+///
+/// Writes the physical memory usage as text, in the same format the kernel prints it in. The text
+/// is cut off if it does not fit.
+pub fn sys_meminfo(_context: &mut Cpu_Context, args: SyscallArgs) -> SyscallResult {
+    let (buf, len): (UserPtr<u8>, usize) = args.decode()?;
+    let text = format!("{}", memory_stats());
+    let len = text.len().min(len);
+    copy_to_user(buf, &text.as_bytes()[..len])?;
+    Ok(len as u64)
+}
+
+/// procinfo(index: usize, info: *mut ProcInfo) -> 1, or 0 past the last process
+///
+/// Processes are enumerated in order of pid. Processes that start or are reaped between calls may
+/// be skipped or reported twice.
+pub fn sys_procinfo(_context: &mut Cpu_Context, args: SyscallArgs) -> SyscallResult {
+    let (index, info): (usize, UserPtr<ProcInfo>) = args.decode()?;
+    let found = match process::nth(index) {
+        Some(found) => found,
+        None => return Ok(0),
+    };
+
+    let mut snapshot = ProcInfo {
+        pid: 0,
+        parent: 0,
+        resident_pages: 0,
+        state: 0,
+        exit_status: 0,
+        name_len: 0,
+        name: [0; MAX_PROC_NAME_LEN],
+    };
+    let space = {
+        let found = found.lock();
+        snapshot.pid = found.pid();
+        snapshot.parent = found.parent().unwrap_or(0);
+        (snapshot.state, snapshot.exit_status) = match found.state() {
+            ProcessState::Runnable => (PROC_RUNNABLE, 0),
+            ProcessState::Running => (PROC_RUNNING, 0),
+            ProcessState::Waiting => (PROC_WAITING, 0),
+            ProcessState::Zombie(status) => (PROC_ZOMBIE, status),
+        };
+        let name = found.name().as_bytes();
+        let name_len = name.len().min(MAX_PROC_NAME_LEN);
+        snapshot.name[..name_len].copy_from_slice(&name[..name_len]);
+        snapshot.name_len = name_len as u64;
+        found.address_space().cloned()
+    };
+    // The address space is not locked while holding the process lock
+    if let Some(space) = space {
+        snapshot.resident_pages = space.lock().resident_pages();
+    }
+
+    write_user(info, &snapshot)?;
+    Ok(1)
+}
+
+/// reboot() -> !
+pub fn sys_reboot(_context: &mut Cpu_Context, _args: SyscallArgs) -> SyscallResult {
+    kprintln!("Rebooting...");
+    POWER.lock().reset()
+}
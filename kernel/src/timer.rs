@@ -0,0 +1,116 @@
+//! Timeouts on the per-core timers
+//!
+//! Every core keeps its own queue of timeouts, ordered by deadline, and arms the EL1 physical timer
+//! of the ARM generic timer for the earliest one. A timeout is always queued on the core that adds
+//! it, since only that core can program its timer.
+//!
+//! The timer interrupt may arrive while the core holds any lock, so its handler only disarms the
+//! timer. The expired timeouts are run by ```run_expired``` once the core holds no locks anymore,
+//! which is on the way out to user code and in the idle loop. Both check often, so the earliest
+//! deadline of each core is published in a seqlock, which they read without taking the queue lock.
+
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+use raspi::{
+    concurrency::{mutex::Mutex, seqlock::SeqLock},
+    cpu::{core_id, NUM_CORES},
+    peripherals::timer::{arm_timer, disarm_timer, duration_to_cycles, timer_cycle_count},
+};
+
+/// A function to run once its deadline has passed. It runs on the core it was added on, without
+/// any locks held.
+pub type TimeoutFn = Box<dyn FnOnce() + Send>;
+
+// Keyed by deadline in timer cycles, then by a unique id to tell apart timeouts with equal deadlines
+type TimeoutQueue = BTreeMap<(u64, u64), TimeoutFn>;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_QUEUE: Mutex<TimeoutQueue> = Mutex::new(BTreeMap::new());
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_DEADLINE: SeqLock<u64> = SeqLock::new(u64::MAX);
+
+static QUEUES: [Mutex<TimeoutQueue>; NUM_CORES] = [EMPTY_QUEUE; NUM_CORES];
+
+// The earliest deadline in each core's queue, or u64::MAX if it is empty
+static NEXT_DEADLINES: [SeqLock<u64>; NUM_CORES] = [NO_DEADLINE; NUM_CORES];
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Identifies a queued timeout, so that it can be cancelled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeoutHandle {
+    core: usize,
+    key: (u64, u64),
+}
+
+// Programs the calling core's timer for the earliest deadline in its queue, if there is one
+fn rearm(core: usize, queue: &TimeoutQueue) {
+    match queue.keys().next() {
+        Some(&(deadline, _)) => {
+            NEXT_DEADLINES[core].write(deadline);
+            arm_timer(deadline);
+        }
+        None => {
+            NEXT_DEADLINES[core].write(u64::MAX);
+            disarm_timer();
+        }
+    }
+}
+
+/// Queues ```callback``` to run on the calling core once ```after``` has passed.
+pub fn add(after: Duration, callback: TimeoutFn) -> TimeoutHandle {
+    let deadline = timer_cycle_count().saturating_add(duration_to_cycles(after));
+    let key = (deadline, NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    let core = core_id() as usize;
+
+    let mut queue = QUEUES[core].lock();
+    queue.insert(key, callback);
+    // Only a new earliest deadline moves the timer
+    if queue.keys().next() == Some(&key) {
+        rearm(core, &queue);
+    }
+    TimeoutHandle { core, key }
+}
+
+/// Removes a timeout from its queue before it runs. Returns false if it already ran, or is about to.
+///
+/// Can be called from any core. The timer is left armed, and the core finds nothing to run once the
+/// cancelled deadline passes.
+pub fn cancel(handle: TimeoutHandle) -> bool {
+    QUEUES[handle.core].lock().remove(&handle.key).is_some()
+}
+
+/// Handles the timer interrupt of the calling core. Must be called from the IRQ handler.
+pub fn handle_irq() {
+    // The interrupt is level triggered, so it has to be withdrawn before returning. The deadline
+    // stays published, so the next call to run_expired finds it passed
+    disarm_timer();
+}
+
+/// Runs the timeouts of the calling core whose deadlines have passed, and arms the timer for the
+/// next one.
+///
+/// Must be called without any locks held, from code that has just handled an exception or that
+/// polls for work.
+pub fn run_expired() {
+    let core = core_id() as usize;
+    if NEXT_DEADLINES[core].read() > timer_cycle_count() {
+        return;
+    }
+
+    let expired: Vec<TimeoutFn> = {
+        let mut queue = QUEUES[core].lock();
+        // Everything with a deadline up to now, with any id
+        let pending = queue.split_off(&(timer_cycle_count() + 1, 0));
+        let expired = core::mem::replace(&mut *queue, pending);
+        rearm(core, &queue);
+        expired.into_values().collect()
+    };
+    for callback in expired {
+        callback();
+    }
+}
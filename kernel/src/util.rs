@@ -1,7 +1,3 @@
-use crate::peripherals::UART;
-use core::arch::asm;
-use core::panic::PanicInfo;
-
 extern "C" {
     static __PG_SIZE: u8;
     static __KERNEL_VIRT_START: u8;
@@ -55,26 +51,12 @@ macro_rules! kprints {
     };
 }
 
-#[panic_handler]
-fn panic(info: &PanicInfo) -> ! {
-    // Cant use kprint* macros here because it will result in jumbled text
-    // Need to handle the lock manually
+/// Prints a locking bug found by lockdep. Lockdep disables itself before calling this, so the
+/// kernel keeps running with validation off.
+#[cfg(feature = "lockdep")]
+pub fn report_lock_violation(violation: &raspi::concurrency::lockdep::Violation) {
+    use crate::peripherals::UART;
     use core::fmt::Write;
     let mut lock = UART.lock();
-
-    writeln!(lock, "\nKERNEL PANIC!").unwrap();
-    if let Some(location) = info.location() {
-        writeln!(lock, "Location: {}", location).unwrap();
-    }
-    if let Some(message) = info.message() {
-        writeln!(lock, "Reason: \n{}", message).unwrap();
-    }
-
-    loop {}
-}
-
-pub fn clear_tlb() {
-    unsafe {
-        asm!("TLBI VMALLE1", "DSB ISH", "ISB");
-    }
+    writeln!(lock, "\nLOCKDEP WARNING!\n{}", violation).unwrap();
 }
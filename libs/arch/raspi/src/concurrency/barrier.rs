@@ -1,35 +1,50 @@
-use super::mutex::Mutex;
+use core::sync::atomic::{AtomicU32, Ordering};
 
+use super::wait::{wait, wake};
+
+/// A reusable barrier for a fixed number of threads.
+///
+/// Every time the last thread arrives, the generation is advanced, which releases all waiting
+/// threads and starts a new round. The arrival count is reset before the generation changes, so a
+/// released thread can immediately wait on the barrier again.
 pub struct Barrier {
-    num_threads: u8,
-    num_arrived: Mutex<u8>,
+    num_threads: u32,
+    num_arrived: AtomicU32,
+    generation: AtomicU32,
 }
 
 impl Barrier {
-    pub fn new(num_threads: u8) -> Self {
+    pub const fn new(num_threads: u32) -> Self {
         Barrier {
             num_threads,
-            num_arrived: Mutex::new(0),
+            num_arrived: AtomicU32::new(0),
+            generation: AtomicU32::new(0),
         }
     }
 
-    pub fn wait(&self) {
-        {
-            let lock = self.num_arrived.lock();
-            if *lock == self.num_threads {
-                panic!("Attempt to reuse a single use barrier!");
-            }
-        }
+    /// Blocks until ```num_threads``` threads have called ```wait```.
+    ///
+    /// Returns true for exactly one thread of each round, the last one to arrive.
+    pub fn wait(&self) -> bool {
+        let generation = self.generation.load(Ordering::Acquire);
+        let arrived = self.num_arrived.fetch_add(1, Ordering::AcqRel) + 1;
 
-        {
-            *self.num_arrived.lock() += 1;
+        if arrived == self.num_threads {
+            self.num_arrived.store(0, Ordering::Relaxed);
+            self.generation.fetch_add(1, Ordering::Release);
+            wake(self.key(), true);
+            return true;
         }
 
-        loop {
-            let arrived = self.num_arrived.lock();
-            if *arrived == self.num_threads {
-                break;
-            }
+        while self.generation.load(Ordering::Acquire) == generation {
+            wait(self.key(), &|| {
+                self.generation.load(Ordering::Acquire) == generation
+            });
         }
+        false
+    }
+
+    fn key(&self) -> usize {
+        &self.generation as *const _ as usize
     }
 }
@@ -0,0 +1,61 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use lock_api::{MutexGuard, RawMutex};
+
+use super::wait::{wait, wake};
+
+/// A condition variable, usable with any ```lock_api``` mutex.
+///
+/// Every notification advances a sequence number. A waiter records the sequence number while still
+/// holding the mutex, so a notification sent between releasing the mutex and going to sleep is never
+/// missed.
+pub struct Condvar {
+    sequence: AtomicU32,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Condvar {
+            sequence: AtomicU32::new(0),
+        }
+    }
+
+    /// Releases the mutex behind ```guard``` and blocks until notified, then takes the mutex again.
+    ///
+    /// Wakeups may be spurious, so the caller must recheck its condition. See ```wait_while```.
+    pub fn wait<R: RawMutex, T>(&self, guard: &mut MutexGuard<'_, R, T>) {
+        let sequence = self.sequence.load(Ordering::Relaxed);
+        MutexGuard::unlocked(guard, || {
+            wait(self.key(), &|| {
+                self.sequence.load(Ordering::Acquire) == sequence
+            });
+        });
+    }
+
+    /// Blocks as long as ```condition``` returns true for the data behind ```guard```.
+    pub fn wait_while<R: RawMutex, T>(
+        &self,
+        guard: &mut MutexGuard<'_, R, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) {
+        while condition(&mut *guard) {
+            self.wait(guard);
+        }
+    }
+
+    /// Wakes up at least one thread waiting on this condition variable.
+    pub fn notify_one(&self) {
+        self.sequence.fetch_add(1, Ordering::Release);
+        wake(self.key(), false);
+    }
+
+    /// Wakes up all threads waiting on this condition variable.
+    pub fn notify_all(&self) {
+        self.sequence.fetch_add(1, Ordering::Release);
+        wake(self.key(), true);
+    }
+
+    fn key(&self) -> usize {
+        &self.sequence as *const _ as usize
+    }
+}
@@ -0,0 +1,484 @@
+//! Lock dependency validator
+//!
+//! When the ```lockdep``` feature is enabled, every acquisition of a ```Mutex```, ```IrqMutex``` or
+//! ```RwLock``` is recorded in a per-core stack of held locks. Locks are grouped into classes by their
+//! address. Whenever a lock is taken while others are held, the order is remembered as an edge in a
+//! global dependency graph, and the acquisition is checked for:
+//!
+//! - Recursive locking: the lock is already held by the same core
+//! - Order inversions: the graph already has a path from the new lock to one that is held, so two
+//!   cores taking them in their respective orders can deadlock
+//! - Interrupt safety: the lock is taken both in interrupt handlers and with interrupts enabled, so
+//!   a handler can interrupt a holder of the lock on the same core
+//!
+//! Call sites are recorded as the return address of the lock function, which points into the code
+//! that took the lock and can be resolved with ```addr2line```. After the first violation is reported
+//! lockdep turns itself off, since the held lock stacks can no longer be trusted.
+//!
+//! When a lock is dropped, its class is removed together with all of its edges, so that locks that
+//! come and go with the objects they protect do not use up the tables, and a lock allocated later at
+//! the same address does not inherit the history of the old one.
+
+use core::{
+    arch::asm,
+    cell::UnsafeCell,
+    fmt::Display,
+    hint, ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+};
+
+use crate::cpu::{core_id, disable_interrupts, interrupts_enabled, restore_interrupts, NUM_CORES};
+
+const MAX_CLASSES: usize = 128;
+const MAX_EDGES: usize = 1024;
+const MAX_HELD: usize = 16;
+const CLASS_WORDS: usize = MAX_CLASSES / 64;
+// Marks the class of a dropped lock. The slot can be reused, but lookups have to probe past it
+const REMOVED: usize = usize::MAX;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViolationKind {
+    Recursive,
+    OrderInversion,
+    IrqUnsafe,
+    /// The tables of lockdep are full, so it cannot keep tracking locks
+    OutOfResources,
+}
+
+/// A detected locking bug.
+///
+/// ```lock``` and ```site``` describe the acquisition that triggered the report. The meaning of the
+/// other fields depends on the kind of violation, see its ```Display``` implementation.
+#[derive(Clone, Copy, Debug)]
+pub struct Violation {
+    pub kind: ViolationKind,
+    pub core: u64,
+    pub lock: usize,
+    pub site: usize,
+    pub other_lock: usize,
+    pub other_site: usize,
+    pub previous_site: usize,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.kind {
+            ViolationKind::Recursive => {
+                writeln!(f, "Recursive locking on core {}", self.core)?;
+                writeln!(f, "  lock {:#x} acquired at {:#x}", self.lock, self.site)?;
+                write!(f, "  is already held since {:#x}", self.other_site)
+            }
+            ViolationKind::OrderInversion => {
+                writeln!(f, "Lock order inversion on core {}", self.core)?;
+                writeln!(f, "  lock {:#x} acquired at {:#x}", self.lock, self.site)?;
+                writeln!(
+                    f,
+                    "  while holding lock {:#x} acquired at {:#x}",
+                    self.other_lock, self.other_site
+                )?;
+                write!(
+                    f,
+                    "  but {:#x} was previously taken after {:#x} at {:#x}",
+                    self.other_lock, self.lock, self.previous_site
+                )
+            }
+            ViolationKind::IrqUnsafe => {
+                writeln!(
+                    f,
+                    "Lock {:#x} is used in interrupt handlers and with interrupts enabled",
+                    self.lock
+                )?;
+                writeln!(f, "  acquired at {:#x} on core {}", self.site, self.core)?;
+                write!(f, "  conflicting use at {:#x}", self.other_site)
+            }
+            ViolationKind::OutOfResources => write!(
+                f,
+                "Lockdep ran out of space at lock {:#x} acquired at {:#x}, validation is disabled",
+                self.lock, self.site
+            ),
+        }
+    }
+}
+
+/// Called with every violation. The handler runs with lockdep already disabled, so it may take locks.
+pub type ReportHandler = fn(&Violation);
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+// The registered ReportHandler, or null to panic
+static REPORT_HANDLER: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+struct Class {
+    // Address of the lock, 0 if the slot was never used, or REMOVED
+    lock: AtomicUsize,
+    // First call site that took the lock in an interrupt handler
+    irq_site: AtomicUsize,
+    // First call site that took the lock with interrupts enabled
+    irq_enabled_site: AtomicUsize,
+    // Bitmap of the classes that have been taken while holding this one
+    after: [AtomicU64; CLASS_WORDS],
+}
+
+// An edge is free if it goes from a class to itself, which a real dependency never does
+struct Edge {
+    from: AtomicU32,
+    to: AtomicU32,
+    site: AtomicUsize,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_EDGES: AtomicU64 = AtomicU64::new(0);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_CLASS: Class = Class {
+    lock: AtomicUsize::new(0),
+    irq_site: AtomicUsize::new(0),
+    irq_enabled_site: AtomicUsize::new(0),
+    after: [NO_EDGES; CLASS_WORDS],
+};
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_EDGE: Edge = Edge {
+    from: AtomicU32::new(0),
+    to: AtomicU32::new(0),
+    site: AtomicUsize::new(0),
+};
+
+static CLASSES: [Class; MAX_CLASSES] = [EMPTY_CLASS; MAX_CLASSES];
+static EDGES: [Edge; MAX_EDGES] = [EMPTY_EDGE; MAX_EDGES];
+// Serializes adding and removing classes and edges. Lookups do without
+static TABLES_LOCKED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy)]
+struct HeldLock {
+    class: usize,
+    site: usize,
+}
+
+struct HeldLocks {
+    locks: UnsafeCell<[HeldLock; MAX_HELD]>,
+    count: UnsafeCell<usize>,
+    // Depth of nested interrupt handlers running on this core
+    irq_depth: AtomicU32,
+}
+
+// SAFETY: The held locks of a core are only accessed by that core, with interrupts masked
+unsafe impl Sync for HeldLocks {}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_LOCKS: HeldLocks = HeldLocks {
+    locks: UnsafeCell::new([HeldLock { class: 0, site: 0 }; MAX_HELD]),
+    count: UnsafeCell::new(0),
+    irq_depth: AtomicU32::new(0),
+};
+
+static HELD: [HeldLocks; NUM_CORES] = [NO_LOCKS; NUM_CORES];
+
+/// Registers the function that violations are reported to. By default, violations panic.
+pub fn set_report_handler(handler: ReportHandler) {
+    REPORT_HANDLER.store(handler as *mut (), Ordering::Release);
+}
+
+/// Must be called by interrupt handlers before they take any locks.
+pub fn enter_interrupt() {
+    HELD[core_id() as usize]
+        .irq_depth
+        .fetch_add(1, Ordering::Relaxed);
+}
+
+/// Must be called by interrupt handlers once they released all their locks.
+pub fn exit_interrupt() {
+    HELD[core_id() as usize]
+        .irq_depth
+        .fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Returns the address the calling function will return to.
+///
+/// Must be inlined into a function that is not inlined itself, before it calls anything else.
+#[inline(always)]
+pub(crate) fn return_address() -> usize {
+    let lr: usize;
+    unsafe { asm!("mov {lr}, x30", lr = out(reg) lr, options(nomem, nostack, preserves_flags)) };
+    lr
+}
+
+/// Records that ```lock``` is about to be acquired at ```site```, and validates the acquisition.
+///
+/// Try-locks never wait, so they cannot deadlock on the lock order and only get recorded.
+pub(crate) fn acquire(lock: usize, site: usize, try_lock: bool) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let daif = disable_interrupts();
+    let result = validate(lock, site, try_lock, daif);
+    // SAFETY: daif was read on this core above
+    unsafe { restore_interrupts(daif) };
+
+    if let Err(violation) = result {
+        report(violation);
+    }
+}
+
+/// Records that ```lock``` has been released.
+pub(crate) fn release(lock: usize) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let daif = disable_interrupts();
+    if let Some(class) = find_class(lock) {
+        let held = &HELD[core_id() as usize];
+        // SAFETY: Only this core accesses its held locks, and interrupts are masked
+        unsafe {
+            let locks = &mut *held.locks.get();
+            let count = &mut *held.count.get();
+            // Locks are usually released in reverse order, but don't have to be
+            if let Some(idx) = locks[..*count].iter().rposition(|held| held.class == class) {
+                locks.copy_within(idx + 1..*count, idx);
+                *count -= 1;
+            }
+        }
+    }
+    // SAFETY: daif was read on this core above
+    unsafe { restore_interrupts(daif) };
+}
+
+/// Removes the class of ```lock``` and all of its edges. Must be called when the lock is dropped.
+pub(crate) fn forget(lock: usize) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let daif = disable_interrupts();
+    lock_tables();
+    if let Some(class) = find_class(lock) {
+        for other in &CLASSES {
+            other.after[class / 64].fetch_and(!(1 << (class % 64)), Ordering::Relaxed);
+        }
+        for edge in &EDGES {
+            let from = edge.from.load(Ordering::Relaxed) as usize;
+            let to = edge.to.load(Ordering::Relaxed) as usize;
+            if from != to && (from == class || to == class) {
+                edge.from.store(0, Ordering::Relaxed);
+                edge.to.store(0, Ordering::Relaxed);
+            }
+        }
+        for word in &CLASSES[class].after {
+            word.store(0, Ordering::Relaxed);
+        }
+        CLASSES[class].irq_site.store(0, Ordering::Relaxed);
+        CLASSES[class].irq_enabled_site.store(0, Ordering::Relaxed);
+        CLASSES[class].lock.store(REMOVED, Ordering::Release);
+    }
+    unlock_tables();
+    // SAFETY: daif was read on this core above
+    unsafe { restore_interrupts(daif) };
+}
+
+fn validate(lock: usize, site: usize, try_lock: bool, daif: u64) -> Result<(), Violation> {
+    let core = core_id();
+    let violation = |kind, other_lock, other_site, previous_site| Violation {
+        kind,
+        core,
+        lock,
+        site,
+        other_lock,
+        other_site,
+        previous_site,
+    };
+
+    let class = register_class(lock).ok_or(violation(ViolationKind::OutOfResources, 0, 0, 0))?;
+    let held = &HELD[core as usize];
+    // SAFETY: Only this core accesses its held locks, and interrupts are masked
+    let (locks, count) = unsafe { (&mut *held.locks.get(), &mut *held.count.get()) };
+
+    if held.irq_depth.load(Ordering::Relaxed) > 0 {
+        let _ =
+            CLASSES[class]
+                .irq_site
+                .compare_exchange(0, site, Ordering::Relaxed, Ordering::Relaxed);
+        let conflict = CLASSES[class].irq_enabled_site.load(Ordering::Relaxed);
+        if conflict != 0 {
+            return Err(violation(ViolationKind::IrqUnsafe, lock, conflict, 0));
+        }
+    } else if interrupts_enabled(daif) {
+        let _ = CLASSES[class].irq_enabled_site.compare_exchange(
+            0,
+            site,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        );
+        let conflict = CLASSES[class].irq_site.load(Ordering::Relaxed);
+        if conflict != 0 {
+            return Err(violation(ViolationKind::IrqUnsafe, lock, conflict, 0));
+        }
+    }
+
+    for held_lock in &locks[..*count] {
+        let held_addr = CLASSES[held_lock.class].lock.load(Ordering::Relaxed);
+        if held_lock.class == class {
+            return Err(violation(ViolationKind::Recursive, lock, held_lock.site, 0));
+        }
+        if try_lock {
+            continue;
+        }
+        if let Some(previous_site) = find_path(class, held_lock.class) {
+            return Err(violation(
+                ViolationKind::OrderInversion,
+                held_addr,
+                held_lock.site,
+                previous_site,
+            ));
+        }
+    }
+
+    if !try_lock {
+        for held_lock in &locks[..*count] {
+            add_edge(held_lock.class, class, site).ok_or(violation(
+                ViolationKind::OutOfResources,
+                0,
+                0,
+                0,
+            ))?;
+        }
+    }
+
+    if *count == MAX_HELD {
+        return Err(violation(ViolationKind::OutOfResources, 0, 0, 0));
+    }
+    locks[*count] = HeldLock { class, site };
+    *count += 1;
+    Ok(())
+}
+
+fn slot(lock: usize) -> usize {
+    // Locks are at least word aligned, so the low bits carry no information
+    (lock >> 3) % MAX_CLASSES
+}
+
+// Must be called with interrupts masked
+fn lock_tables() {
+    while TABLES_LOCKED
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        hint::spin_loop();
+    }
+}
+
+fn unlock_tables() {
+    TABLES_LOCKED.store(false, Ordering::Release);
+}
+
+fn probe(lock: usize) -> impl Iterator<Item = usize> {
+    let start = slot(lock);
+    (0..MAX_CLASSES).map(move |probe| (start + probe) % MAX_CLASSES)
+}
+
+fn find_class(lock: usize) -> Option<usize> {
+    for class in probe(lock) {
+        match CLASSES[class].lock.load(Ordering::Acquire) {
+            addr if addr == lock => return Some(class),
+            0 => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn register_class(lock: usize) -> Option<usize> {
+    if let Some(class) = find_class(lock) {
+        return Some(class);
+    }
+
+    lock_tables();
+    // Another core may have registered the lock while we waited
+    let class = find_class(lock).or_else(|| {
+        let class = probe(lock)
+            .find(|class| matches!(CLASSES[*class].lock.load(Ordering::Relaxed), 0 | REMOVED))?;
+        CLASSES[class].lock.store(lock, Ordering::Release);
+        Some(class)
+    });
+    unlock_tables();
+    class
+}
+
+fn has_edge(from: usize, to: usize) -> bool {
+    CLASSES[from].after[to / 64].load(Ordering::Relaxed) & (1 << (to % 64)) != 0
+}
+
+fn add_edge(from: usize, to: usize, site: usize) -> Option<()> {
+    if has_edge(from, to) {
+        return Some(());
+    }
+
+    lock_tables();
+    let added = has_edge(from, to) || {
+        let free = EDGES
+            .iter()
+            .find(|edge| edge.from.load(Ordering::Relaxed) == edge.to.load(Ordering::Relaxed));
+        if let Some(edge) = free {
+            edge.from.store(from as u32, Ordering::Relaxed);
+            edge.to.store(to as u32, Ordering::Relaxed);
+            edge.site.store(site, Ordering::Relaxed);
+            CLASSES[from].after[to / 64].fetch_or(1 << (to % 64), Ordering::Release);
+        }
+        free.is_some()
+    };
+    unlock_tables();
+    added.then_some(())
+}
+
+fn edge_site(from: usize, to: usize) -> usize {
+    EDGES
+        .iter()
+        .find(|edge| {
+            edge.from.load(Ordering::Relaxed) as usize == from
+                && edge.to.load(Ordering::Relaxed) as usize == to
+        })
+        .map_or(0, |edge| edge.site.load(Ordering::Relaxed))
+}
+
+/// Searches the dependency graph for a path ```from``` -> ```to```. If there is one, returns the call
+/// site of its last edge, where ```to``` was taken.
+fn find_path(from: usize, to: usize) -> Option<usize> {
+    let mut visited = [0u64; CLASS_WORDS];
+    let mut queue = [0u8; MAX_CLASSES];
+    let (mut head, mut tail) = (0, 1);
+    queue[0] = from as u8;
+    visited[from / 64] |= 1 << (from % 64);
+
+    while head < tail {
+        let class = queue[head] as usize;
+        head += 1;
+        for next in 0..MAX_CLASSES {
+            if visited[next / 64] & (1 << (next % 64)) != 0 || !has_edge(class, next) {
+                continue;
+            }
+            if next == to {
+                return Some(edge_site(class, to));
+            }
+            visited[next / 64] |= 1 << (next % 64);
+            queue[tail] = next as u8;
+            tail += 1;
+        }
+    }
+    None
+}
+
+fn report(violation: Violation) {
+    // Only the first violation is reported, later ones are likely caused by it
+    if !ENABLED.swap(false, Ordering::AcqRel) {
+        return;
+    }
+
+    let handler = REPORT_HANDLER.load(Ordering::Acquire);
+    if !handler.is_null() {
+        // SAFETY: The only non-null values ever stored are valid ReportHandler pointers
+        let handler = unsafe { core::mem::transmute::<*mut (), ReportHandler>(handler) };
+        handler(&violation);
+    } else {
+        panic!("{}", violation);
+    }
+}
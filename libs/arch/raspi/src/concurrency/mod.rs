@@ -1,3 +1,10 @@
 pub mod barrier;
+pub mod condvar;
 pub mod dummylock;
+#[cfg(feature = "lockdep")]
+pub mod lockdep;
 pub mod mutex;
+pub mod rwlock;
+pub mod semaphore;
+pub mod seqlock;
+pub mod wait;
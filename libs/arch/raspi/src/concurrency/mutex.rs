@@ -1,76 +1,196 @@
-use core::arch::asm;
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+};
 
 use lock_api::GuardSend;
 
-#[derive(Debug)]
-pub struct RawMutex(u64);
+#[cfg(feature = "lockdep")]
+use super::lockdep;
+use crate::cpu::{disable_interrupts, restore_interrupts};
 
-unsafe impl lock_api::RawMutex for RawMutex {
-    const INIT: Self = RawMutex(0);
-    type GuardMarker = GuardSend;
+/// A fair ticket spinlock.
+///
+/// The lock word holds the ticket currently being served in its low half, and the next ticket to
+/// hand out in its high half. Cores take a ticket and wait until it is served, so the lock is
+/// granted in the order it was requested.
+///
+/// Waiting cores sleep in WFE rather than spinning on the bus. A waiter arms the exclusive monitor on
+/// the lock word before each WFE, and the store that releases the lock clears the monitor, which
+/// generates the event that wakes it up again.
+#[derive(Debug)]
+pub struct RawMutex(AtomicU32);
 
-    fn lock(&self) {
+impl RawMutex {
+    fn raw_lock(&self) {
         unsafe {
             asm!(
                 "2:",
-                "ldaxr {temp}, [{lock_ptr}]",   // Read lock variable, setting exclusive monitor
-                "cmp {temp}, #1",   // Is this mutex already locked? Then try again...
-                "beq 2b",
-                "ldr {temp}, =1",   // Mutex was free during ldaxr, now we try to update the lock exclusively
-                "stlxr {res:w}, {temp}, [{lock_ptr}]",
-                "cmp {res:w}, #0",  // If res is 0, someone else mucked with this memory address since we called ldaxr!
-                                    // The store failed and we will have to try again...
-                "bne 2b",
-                temp = in(reg) 123,
-                lock_ptr = in(reg) (&(self.0)) as *const u64,
-                res = in(reg) 0,
+                "ldaxr {lock:w}, [{lock_ptr}]",         // Read the lock word, setting the exclusive monitor
+                "add {new:w}, {lock:w}, #(1 << 16)",    // Take a ticket by incrementing the next ticket
+                "stxr {res:w}, {new:w}, [{lock_ptr}]",
+                "cbnz {res:w}, 2b",                     // Someone else updated the lock word, try again
+                "eor {new:w}, {lock:w}, {lock:w}, ror #16", // Is our ticket already being served?
+                "cbz {new:w}, 4f",
+                "sevl",             // Make sure the first WFE falls through, the lock may have been released already
+                "3:",
+                "wfe",
+                "ldaxrh {new:w}, [{lock_ptr}]",         // Read the ticket being served, arming the monitor again
+                "eor {new:w}, {new:w}, {lock:w}, lsr #16",
+                "cbnz {new:w}, 3b",
+                "4:",
+                lock = out(reg) _,
+                new = out(reg) _,
+                res = out(reg) _,
+                lock_ptr = in(reg) self.0.as_ptr(),
             );
         }
     }
 
-    fn try_lock(&self) -> bool {
-        let mut lock_succeed = 1; // Zero means true in this context
+    fn raw_try_lock(&self) -> bool {
+        let lock_failed: u64;
         unsafe {
             asm!(
-                "ldaxr {temp}, [{lock_ptr}]",   // Read lock variable, setting exclusive monitor
-                "cmp {temp}, #1",   // Is this mutex already locked? Bail out and return false
-                "beq 2f",
-                "ldr {temp}, =1",   // Try to update the lock exclusively...
-                "stlxr {res:w}, {temp}, [{lock_ptr}]",  // Set lock_succeed to the value of res
-                                                        // If res is nonzero, we failed to update the lock...
                 "2:",
-                temp = in(reg) 123,
-                lock_ptr = in(reg) (&(self.0)) as *const u64,
-                res = inout(reg) lock_succeed,
+                "ldaxr {lock:w}, [{lock_ptr}]",
+                "eor {res:w}, {lock:w}, {lock:w}, ror #16", // Is anyone holding or waiting for the lock? Bail out
+                "cbnz {res:w}, 3f",
+                "add {lock:w}, {lock:w}, #(1 << 16)",
+                "stxr {res:w}, {lock:w}, [{lock_ptr}]",
+                "cbnz {res:w}, 2b", // The lock word changed under us, look at it again
+                "3:",
+                lock = out(reg) _,
+                res = out(reg) lock_failed,
+                lock_ptr = in(reg) self.0.as_ptr(),
             );
         }
-        lock_succeed == 0
+        lock_failed == 0
     }
 
-    unsafe fn unlock(&self) {
+    unsafe fn raw_unlock(&self) {
         unsafe {
             asm!(
-                "2:",
-                "ldaxr {temp}, [{lock_ptr}]", // Set the exclusive monitor
-                                              // Note that we don't actually care what the value of lock_ptr is
-                                              // This is because its already required by lock_api that an unlock
-                                              // always be paired with a corresponding lock. So this should never
-                                              // be called if the lock is free. Either way, unlock should always
-                                              // set the mutex to free regardless. (Going from free -> free is harmless)
-                "ldr {temp}, =0",
-                "stlxr {res:w}, {temp}, [{lock_ptr}]",  // Attempt to free the mutex
-                "cmp {res:w}, #0",  // We may have tried to free the mutex at the moment someone else was trying to lock it
-                                    // This will muck up both attempts to modify this address and both will fail.
-                                    // This means in order to properly unlock this mutex we have to try again, until the exclusive monitor
-                                    // informs us that we have successfully reset this address to 0.
-                "bne 2b",
-                temp = in(reg) 123,
-                lock_ptr = in(reg) (&(self.0)) as *const u64,
-                res = in(reg) 0,
+                "ldrh {owner:w}, [{lock_ptr}]", // Only the holder ever writes the ticket being served
+                "add {owner:w}, {owner:w}, #1",
+                "stlrh {owner:w}, [{lock_ptr}]", // Serve the next ticket, waking up any waiters
+                owner = out(reg) _,
+                lock_ptr = in(reg) self.0.as_ptr(),
             );
         }
     }
 }
 
+unsafe impl lock_api::RawMutex for RawMutex {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = RawMutex(AtomicU32::new(0));
+    type GuardMarker = GuardSend;
+
+    #[cfg_attr(feature = "lockdep", inline(never))]
+    fn lock(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self as *const _ as usize, lockdep::return_address(), false);
+        self.raw_lock();
+    }
+
+    #[cfg_attr(feature = "lockdep", inline(never))]
+    fn try_lock(&self) -> bool {
+        #[cfg(feature = "lockdep")]
+        let site = lockdep::return_address();
+        let locked = self.raw_try_lock();
+        #[cfg(feature = "lockdep")]
+        if locked {
+            lockdep::acquire(self as *const _ as usize, site, true);
+        }
+        locked
+    }
+
+    unsafe fn unlock(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self as *const _ as usize);
+        self.raw_unlock();
+    }
+
+    fn is_locked(&self) -> bool {
+        let lock = self.0.load(Ordering::Relaxed);
+        lock >> 16 != lock & 0xFFFF
+    }
+}
+
+#[cfg(feature = "lockdep")]
+impl Drop for RawMutex {
+    fn drop(&mut self) {
+        lockdep::forget(self as *const _ as usize);
+    }
+}
+
+/// A ticket spinlock that masks interrupts on the holding core for as long as it is held.
+///
+/// Data that is shared with an interrupt handler must be protected by this lock, since a handler
+/// that tries to take a regular lock already held by the code it interrupted would deadlock. The
+/// interrupt mask of the holder is restored when the lock is released, so these locks nest.
+#[derive(Debug)]
+pub struct RawIrqMutex {
+    inner: RawMutex,
+    // The DAIF flags of the holder from before the lock was taken
+    saved_daif: AtomicU64,
+}
+
+unsafe impl lock_api::RawMutex for RawIrqMutex {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = RawIrqMutex {
+        inner: <RawMutex as lock_api::RawMutex>::INIT,
+        saved_daif: AtomicU64::new(0),
+    };
+    type GuardMarker = GuardSend;
+
+    #[cfg_attr(feature = "lockdep", inline(never))]
+    fn lock(&self) {
+        #[cfg(feature = "lockdep")]
+        let site = lockdep::return_address();
+        let daif = disable_interrupts();
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self as *const _ as usize, site, false);
+        self.inner.raw_lock();
+        self.saved_daif.store(daif, Ordering::Relaxed);
+    }
+
+    #[cfg_attr(feature = "lockdep", inline(never))]
+    fn try_lock(&self) -> bool {
+        #[cfg(feature = "lockdep")]
+        let site = lockdep::return_address();
+        let daif = disable_interrupts();
+        if self.inner.raw_try_lock() {
+            #[cfg(feature = "lockdep")]
+            lockdep::acquire(self as *const _ as usize, site, true);
+            self.saved_daif.store(daif, Ordering::Relaxed);
+            true
+        } else {
+            unsafe { restore_interrupts(daif) };
+            false
+        }
+    }
+
+    unsafe fn unlock(&self) {
+        let daif = self.saved_daif.load(Ordering::Relaxed);
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self as *const _ as usize);
+        self.inner.raw_unlock();
+        restore_interrupts(daif);
+    }
+
+    fn is_locked(&self) -> bool {
+        lock_api::RawMutex::is_locked(&self.inner)
+    }
+}
+
+#[cfg(feature = "lockdep")]
+impl Drop for RawIrqMutex {
+    fn drop(&mut self) {
+        lockdep::forget(self as *const _ as usize);
+    }
+}
+
 pub type Mutex<T> = lock_api::Mutex<RawMutex, T>;
 pub type MutexGuard<'a, T> = lock_api::MutexGuard<'a, RawMutex, T>;
+pub type IrqMutex<T> = lock_api::Mutex<RawIrqMutex, T>;
+pub type IrqMutexGuard<'a, T> = lock_api::MutexGuard<'a, RawIrqMutex, T>;
@@ -0,0 +1,144 @@
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use lock_api::GuardSend;
+
+#[cfg(feature = "lockdep")]
+use super::lockdep;
+
+// The lock is held exclusively
+const WRITER: u32 = 1 << 31;
+// A writer is waiting, so no new readers are let in until it got the lock
+const WRITER_WAITING: u32 = 1 << 30;
+const READERS: u32 = WRITER_WAITING - 1;
+
+/// A reader-writer spinlock for read-mostly data.
+///
+/// Any number of readers may hold the lock at once. A waiting writer stops new readers from taking
+/// the lock, so a steady stream of readers cannot starve writers out. Waiting cores sleep in WFE, and
+/// releasing the lock sends an event to wake them up.
+#[derive(Debug)]
+pub struct RawRwLock(AtomicU32);
+
+impl RawRwLock {
+    fn wait() {
+        unsafe { asm!("wfe", options(nomem, nostack)) };
+    }
+
+    fn wake() {
+        unsafe { asm!("dsb ish", "sev", options(nostack)) };
+    }
+
+    fn raw_try_lock_shared(&self) -> bool {
+        self.0
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |state| {
+                if state & (WRITER | WRITER_WAITING) != 0 {
+                    None
+                } else {
+                    Some(state + 1)
+                }
+            })
+            .is_ok()
+    }
+
+    fn raw_try_lock_exclusive(&self) -> bool {
+        // Taking the lock clears the waiting flag. Any other waiting writer sets it again on its
+        // next attempt
+        self.0
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |state| {
+                if state & !WRITER_WAITING == 0 {
+                    Some(WRITER)
+                } else {
+                    None
+                }
+            })
+            .is_ok()
+    }
+}
+
+unsafe impl lock_api::RawRwLock for RawRwLock {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = RawRwLock(AtomicU32::new(0));
+    type GuardMarker = GuardSend;
+
+    #[cfg_attr(feature = "lockdep", inline(never))]
+    fn lock_shared(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self as *const _ as usize, lockdep::return_address(), false);
+        while !self.raw_try_lock_shared() {
+            RawRwLock::wait();
+        }
+    }
+
+    #[cfg_attr(feature = "lockdep", inline(never))]
+    fn try_lock_shared(&self) -> bool {
+        #[cfg(feature = "lockdep")]
+        let site = lockdep::return_address();
+        let locked = self.raw_try_lock_shared();
+        #[cfg(feature = "lockdep")]
+        if locked {
+            lockdep::acquire(self as *const _ as usize, site, true);
+        }
+        locked
+    }
+
+    unsafe fn unlock_shared(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self as *const _ as usize);
+        let state = self.0.fetch_sub(1, Ordering::Release);
+        // Only a writer can be waiting for the last reader to leave
+        if state & READERS == 1 {
+            RawRwLock::wake();
+        }
+    }
+
+    #[cfg_attr(feature = "lockdep", inline(never))]
+    fn lock_exclusive(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self as *const _ as usize, lockdep::return_address(), false);
+        while !self.raw_try_lock_exclusive() {
+            self.0.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+            RawRwLock::wait();
+        }
+    }
+
+    #[cfg_attr(feature = "lockdep", inline(never))]
+    fn try_lock_exclusive(&self) -> bool {
+        #[cfg(feature = "lockdep")]
+        let site = lockdep::return_address();
+        let locked = self.raw_try_lock_exclusive();
+        #[cfg(feature = "lockdep")]
+        if locked {
+            lockdep::acquire(self as *const _ as usize, site, true);
+        }
+        locked
+    }
+
+    unsafe fn unlock_exclusive(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self as *const _ as usize);
+        self.0.fetch_and(!WRITER, Ordering::Release);
+        RawRwLock::wake();
+    }
+
+    fn is_locked(&self) -> bool {
+        self.0.load(Ordering::Relaxed) & (WRITER | READERS) != 0
+    }
+
+    fn is_locked_exclusive(&self) -> bool {
+        self.0.load(Ordering::Relaxed) & WRITER != 0
+    }
+}
+
+#[cfg(feature = "lockdep")]
+impl Drop for RawRwLock {
+    fn drop(&mut self) {
+        lockdep::forget(self as *const _ as usize);
+    }
+}
+
+pub type RwLock<T> = lock_api::RwLock<RawRwLock, T>;
+pub type RwLockReadGuard<'a, T> = lock_api::RwLockReadGuard<'a, RawRwLock, T>;
+pub type RwLockWriteGuard<'a, T> = lock_api::RwLockWriteGuard<'a, RawRwLock, T>;
@@ -0,0 +1,47 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::wait::{wait, wake};
+
+/// A counting semaphore.
+pub struct Semaphore {
+    count: AtomicUsize,
+}
+
+impl Semaphore {
+    pub const fn new(count: usize) -> Self {
+        Semaphore {
+            count: AtomicUsize::new(count),
+        }
+    }
+
+    /// Takes one unit from the semaphore, blocking until one is available.
+    pub fn acquire(&self) {
+        while !self.try_acquire() {
+            wait(self.key(), &|| self.count.load(Ordering::Acquire) == 0);
+        }
+    }
+
+    /// Takes one unit from the semaphore if one is available, without blocking.
+    pub fn try_acquire(&self) -> bool {
+        self.count
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |count| {
+                count.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    /// Returns one unit to the semaphore, waking up a waiting thread.
+    pub fn release(&self) {
+        self.count.fetch_add(1, Ordering::Release);
+        wake(self.key(), false);
+    }
+
+    /// Returns the number of units that are currently available.
+    pub fn available(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    fn key(&self) -> usize {
+        &self.count as *const _ as usize
+    }
+}
@@ -0,0 +1,76 @@
+use core::{
+    cell::UnsafeCell,
+    hint,
+    ptr::{read_volatile, write_volatile},
+    sync::atomic::{fence, AtomicU32, Ordering},
+};
+
+use super::mutex::IrqMutex;
+
+/// A sequence lock for small values that are read far more often than they are written.
+///
+/// Readers never block writers and never write to shared memory. They copy the value out and retry
+/// if a write happened in the meantime, which they detect through the sequence number: it is odd
+/// while a write is in progress, and changes with every write.
+///
+/// Writers are serialized by a lock that masks interrupts, so the value can be updated from an
+/// interrupt handler. A reader that interrupts a writer on the same core would spin forever, so
+/// readers must not run in a context that can interrupt a writer.
+pub struct SeqLock<T: Copy> {
+    sequence: AtomicU32,
+    value: UnsafeCell<T>,
+    writer: IrqMutex<()>,
+}
+
+// SAFETY: Readers only ever copy the value out, and writers are serialized by the writer lock
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+
+impl<T: Copy> SeqLock<T> {
+    pub const fn new(value: T) -> Self {
+        SeqLock {
+            sequence: AtomicU32::new(0),
+            value: UnsafeCell::new(value),
+            writer: IrqMutex::new(()),
+        }
+    }
+
+    /// Returns a consistent copy of the value.
+    pub fn read(&self) -> T {
+        loop {
+            let before = self.sequence.load(Ordering::Acquire);
+            if before & 1 != 0 {
+                hint::spin_loop();
+                continue;
+            }
+
+            // The value may be torn by a concurrent write, but it is thrown away in that case
+            let value = unsafe { read_volatile(self.value.get()) };
+            fence(Ordering::Acquire);
+
+            if self.sequence.load(Ordering::Relaxed) == before {
+                return value;
+            }
+        }
+    }
+
+    /// Replaces the value.
+    pub fn write(&self, value: T) {
+        self.update(|old| *old = value);
+    }
+
+    /// Modifies the value in place with ```f```, while readers are held off.
+    pub fn update(&self, f: impl FnOnce(&mut T)) {
+        let _writer = self.writer.lock();
+        self.sequence.fetch_add(1, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        // SAFETY: Writers are serialized, and readers discard anything they read during the write
+        unsafe {
+            let mut value = read_volatile(self.value.get());
+            f(&mut value);
+            write_volatile(self.value.get(), value);
+        }
+
+        self.sequence.fetch_add(1, Ordering::Release);
+    }
+}
@@ -0,0 +1,60 @@
+//! Blocking for the sleeping synchronization primitives
+//!
+//! Barriers, semaphores and condition variables never block on their own. They describe what they
+//! are waiting for as a key, the address of the value they wait on, and hand that to the wait hooks.
+//! A scheduler registers hooks that block the current thread on a wait queue for the key. Until
+//! then, and on cores that are not running a thread, waiting cores sleep in WFE and are woken by SEV.
+
+use core::arch::asm;
+
+use generic_once_cell::OnceCell;
+
+use super::mutex::RawMutex;
+
+/// Blocks the calling thread on ```key``` if ```should_block``` still returns true.
+///
+/// The hook must evaluate ```should_block``` in a way that cannot miss a wakeup for ```key``` issued
+/// after it returned, for example while holding the lock of the wait queue. Returning without having
+/// been woken up is allowed, callers always check their condition again.
+pub type WaitHook = fn(key: usize, should_block: &dyn Fn() -> bool);
+
+/// Wakes up threads blocked on ```key```. If ```all``` is false, waking a single thread is enough.
+pub type WakeHook = fn(key: usize, all: bool);
+
+static HOOKS: OnceCell<RawMutex, (WaitHook, WakeHook)> = OnceCell::new();
+
+/// Makes all sleeping primitives block through ```wait``` and ```wake```, e.g. on scheduler wait
+/// queues.
+///
+/// The hooks can only be registered once. Cores that already sleep in WFE are still woken up, since
+/// every wakeup also sends an event.
+pub fn set_wait_hooks(wait: WaitHook, wake: WakeHook) -> Result<(), ()> {
+    HOOKS.set((wait, wake)).map_err(|_| ())
+}
+
+/// Blocks until woken up for ```key``` or until ```should_block``` returns false. May return spuriously.
+pub(crate) fn wait(key: usize, should_block: &dyn Fn() -> bool) {
+    match HOOKS.get() {
+        Some((wait, _)) => wait(key, should_block),
+        None => wait_for_event(should_block),
+    }
+}
+
+/// Sleeps in WFE if ```should_block``` returns true. Meant for wait hooks that are called outside
+/// of a thread.
+pub fn wait_for_event(should_block: &dyn Fn() -> bool) {
+    if should_block() {
+        // A SEV sent after the check sets the event register, so this WFE returns immediately
+        // rather than missing the wakeup
+        unsafe { asm!("wfe", options(nomem, nostack)) };
+    }
+}
+
+/// Wakes up threads waiting on ```key```.
+pub(crate) fn wake(key: usize, all: bool) {
+    if let Some((_, wake)) = HOOKS.get() {
+        wake(key, all);
+    }
+    // WFE cannot target a single core, so every sleeping core wakes up and rechecks
+    unsafe { asm!("dsb ish", "sev", options(nostack)) };
+}
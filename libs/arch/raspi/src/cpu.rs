@@ -0,0 +1,110 @@
+use core::arch::asm;
+
+use tock_registers::interfaces::Readable;
+
+/// The number of cores present on every supported Raspberry Pi board.
+pub const NUM_CORES: usize = 4;
+
+/// Returns the index of the core executing this function.
+///
+/// All supported boards are single-cluster, so the index is read directly from the Aff0 field of
+/// MPIDR_EL1.
+pub fn core_id() -> u64 {
+    aarch64_cpu::registers::MPIDR_EL1.get() & 0xFF
+}
+
+/// Masks IRQs and FIQs on the calling core, returning the previous contents of DAIF.
+pub fn disable_interrupts() -> u64 {
+    let daif: u64;
+    unsafe {
+        asm!(
+            "mrs {daif}, daif",
+            "msr daifset, #0b0011",
+            daif = out(reg) daif,
+            options(nostack, preserves_flags)
+        );
+    }
+    daif
+}
+
+/// Restores the interrupt mask of the calling core to a value returned by ```disable_interrupts```.
+///
+/// # Safety
+///
+/// ```daif``` must have been read on this core, and unmasking interrupts must not break any
+/// invariants of the surrounding code.
+pub unsafe fn restore_interrupts(daif: u64) {
+    asm!("msr daif, {daif}", daif = in(reg) daif, options(nostack, preserves_flags));
+}
+
+/// Returns true if IRQs are unmasked in ```daif```, a value returned by ```disable_interrupts```.
+pub fn interrupts_enabled(daif: u64) -> bool {
+    // DAIF.I is bit 7
+    daif & (1 << 7) == 0
+}
+
+/// Stores the address of the calling core's per-CPU data block in TPIDR_EL1.
+///
+/// # Safety
+///
+/// Code reading the pointer back with ```percpu_base``` relies on it pointing to the data block of
+/// the calling core, for as long as the core is running.
+pub unsafe fn set_percpu_base(base: u64) {
+    asm!("msr tpidr_el1, {base}", base = in(reg) base, options(nomem, nostack, preserves_flags));
+}
+
+/// Returns the address stored with ```set_percpu_base``` on the calling core.
+#[inline(always)]
+pub fn percpu_base() -> u64 {
+    let base: u64;
+    unsafe {
+        asm!("mrs {base}, tpidr_el1", base = out(reg) base, options(nomem, nostack, preserves_flags));
+    }
+    base
+}
+
+/// Unmasks IRQs on the calling core.
+///
+/// # Safety
+///
+/// Every interrupt source that is enabled must have a handler ready to acknowledge it.
+pub unsafe fn enable_interrupts() {
+    asm!(
+        "msr daifclr, #0b0010",
+        options(nomem, nostack, preserves_flags)
+    );
+}
+
+/// Enables Privileged Access Never on the calling core if it implements it, so that the kernel
+/// faults when it touches user accessible memory with regular loads and stores. Clears
+/// SCTLR_EL1.SPAN as well, so that PAN is also set on every exception taken to EL1.
+///
+/// Returns false if the core does not implement PAN.
+pub fn enable_pan() -> bool {
+    let mmfr1: u64;
+    unsafe {
+        asm!(
+            "mrs {mmfr1}, id_aa64mmfr1_el1",
+            mmfr1 = out(reg) mmfr1,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+    // ID_AA64MMFR1_EL1.PAN is bits 23:20
+    if (mmfr1 >> 20) & 0xF == 0 {
+        return false;
+    }
+
+    unsafe {
+        asm!(
+            "mrs {tmp}, sctlr_el1",
+            "bic {tmp}, {tmp}, #(1 << 23)",
+            "msr sctlr_el1, {tmp}",
+            "isb",
+            // MSR PAN, #1, encoded by hand since the baseline target does not include PAN
+            ".inst 0xd500419f",
+            tmp = out(reg) _,
+            options(nostack, preserves_flags)
+        );
+    }
+    true
+}
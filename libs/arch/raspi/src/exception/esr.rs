@@ -0,0 +1,97 @@
+use bitfield::bitfield;
+
+/// Exception class of an SVC instruction executed in AArch64 state
+pub const EC_SVC64: u64 = 0x15;
+/// Exception class of an instruction abort taken from a lower exception level
+pub const EC_INSTRUCTION_ABORT_LOWER_EL: u64 = 0x20;
+/// Exception class of an instruction abort taken without a change in exception level
+pub const EC_INSTRUCTION_ABORT_SAME_EL: u64 = 0x21;
+/// Exception class of a data abort taken from a lower exception level
+pub const EC_DATA_ABORT_LOWER_EL: u64 = 0x24;
+/// Exception class of a data abort taken without a change in exception level
+pub const EC_DATA_ABORT_SAME_EL: u64 = 0x25;
+
+bitfield! {
+    /// The Exception Syndrome Register, describing the cause of a synchronous exception
+    #[derive(Clone, Copy)]
+    pub struct Esr(u64);
+    impl Debug;
+    pub iss, _: 24, 0;
+    pub il, _: 25;
+    pub ec, _: 31, 26;
+    // Fields of the ISS that are specific to instruction and data aborts
+    fault_status, _: 5, 0;
+    write_not_read, _: 6;
+    far_not_valid, _: 10;
+}
+
+/// The reason the MMU rejected an access.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultKind {
+    /// No valid descriptor exists for the address at the given level
+    Translation(u8),
+    /// The descriptor is valid, but its access flag is not set
+    AccessFlag(u8),
+    /// The descriptor is valid, but does not permit the access
+    Permission(u8),
+    /// Any other fault, such as an alignment or external abort, identified by its status code
+    Other(u64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessType {
+    Read,
+    Write,
+    Execute,
+}
+
+/// A decoded instruction or data abort.
+#[derive(Clone, Copy, Debug)]
+pub struct Abort {
+    pub kind: FaultKind,
+    pub access: AccessType,
+    /// The abort was caused by code running at EL0
+    pub from_user: bool,
+    /// The faulting virtual address, if the CPU reported one
+    pub address: Option<u64>,
+}
+
+impl Esr {
+    /// Decodes the syndrome of an instruction or data abort. ```far``` is the value of FAR_EL1 at
+    /// the time of the exception.
+    ///
+    /// Returns None if the exception was not an abort.
+    pub fn abort(&self, far: u64) -> Option<Abort> {
+        let (access, from_user) = match self.ec() {
+            EC_INSTRUCTION_ABORT_LOWER_EL => (AccessType::Execute, true),
+            EC_INSTRUCTION_ABORT_SAME_EL => (AccessType::Execute, false),
+            EC_DATA_ABORT_LOWER_EL | EC_DATA_ABORT_SAME_EL => {
+                let access = match self.write_not_read() {
+                    true => AccessType::Write,
+                    false => AccessType::Read,
+                };
+                (access, self.ec() == EC_DATA_ABORT_LOWER_EL)
+            }
+            _ => return None,
+        };
+
+        let status = self.fault_status();
+        let level = (status & 0b11) as u8;
+        let kind = match status >> 2 {
+            0b0001 => FaultKind::Translation(level),
+            0b0010 => FaultKind::AccessFlag(level),
+            0b0011 => FaultKind::Permission(level),
+            _ => FaultKind::Other(status),
+        };
+
+        Some(Abort {
+            kind,
+            access,
+            from_user,
+            address: match self.far_not_valid() {
+                true => None,
+                false => Some(far),
+            },
+        })
+    }
+}
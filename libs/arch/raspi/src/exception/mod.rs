@@ -1,25 +1,99 @@
 use core::{
     arch::{asm, global_asm},
     fmt::Display,
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
 };
 
+use self::esr::{Esr, EC_SVC64};
+use crate::cpu::disable_interrupts;
+
+pub mod esr;
+
 global_asm!(include_str!("context.S"));
 global_asm!(include_str!("vector.S"));
 global_asm!(include_str!("trampoline.S"));
 
+/// The state of the interrupted code, as saved on the exception stack by ```context.S```.
+///
+/// Any changes made to the general purpose registers, ELR_EL1, SPSR_EL1 or SP_EL0 are written back
+/// to the CPU when the exception returns.
 #[repr(C)]
-struct Cpu_Context {
-    gpr: [u64; 31],
-    sp: u64,
-    fpr: [f64; 32],
-    esr_el1: u64,
-    elr_el1: u64,
-    spsr_el1: u64,
-    lr: u64,
-    far_el1: u64,
-    unused: u64,
+#[derive(Clone, Copy)]
+pub struct Cpu_Context {
+    pub gpr: [u64; 31],
+    /// The stack pointer in use at the time the exception was taken
+    pub sp: u64,
+    pub fpr: [u128; 32],
+    pub esr_el1: u64,
+    pub elr_el1: u64,
+    pub spsr_el1: u64,
+    pub lr: u64,
+    pub far_el1: u64,
+    pub sp_el0: u64,
+}
+
+impl Cpu_Context {
+    /// Returns a context that starts executing at ```entry``` in EL0 on the stack ending at ```sp```,
+    /// with interrupts unmasked and every other register zeroed.
+    pub fn new_user(entry: u64, sp: u64) -> Self {
+        Cpu_Context {
+            gpr: [0; 31],
+            sp,
+            fpr: [0; 32],
+            esr_el1: 0,
+            elr_el1: entry,
+            // EL0t, with all of DAIF clear
+            spsr_el1: 0,
+            lr: 0,
+            far_el1: 0,
+            sp_el0: sp,
+        }
+    }
+
+    /// Returns a context that starts executing at ```entry``` in EL1 on the stack ending at ```sp```,
+    /// which is used as SP_EL0 like all regular kernel code. Interrupts are unmasked and every
+    /// other register is zeroed.
+    pub fn new_kernel(entry: u64, sp: u64) -> Self {
+        Cpu_Context {
+            // EL1t, with all of DAIF clear
+            spsr_el1: 0b0100,
+            ..Cpu_Context::new_user(entry, sp)
+        }
+    }
+
+    /// Returns the decoded syndrome of the exception.
+    pub fn esr(&self) -> Esr {
+        Esr(self.esr_el1)
+    }
+
+    /// Returns true if the exception was taken from EL0.
+    pub fn from_user(&self) -> bool {
+        // SPSR_EL1.M[3:0] is 0b0000 for EL0t
+        self.spsr_el1 & 0xF == 0
+    }
 }
 
+/// A handler for synchronous exceptions. Returns true if the exception was handled and execution
+/// can resume, or false if it should be treated as an uncaught exception.
+pub type ExceptionHandler = fn(&mut Cpu_Context) -> bool;
+
+// The registered ExceptionHandler, or null if none has been registered
+static SYNCHRONOUS_HANDLER: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// A handler for IRQs. It must acknowledge the interrupt at its source before returning.
+pub type IrqHandler = fn(&mut Cpu_Context);
+
+// The registered IrqHandler, or null if none has been registered
+static IRQ_HANDLER: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// A handler for system calls, made with SVC from EL0. The system call number and arguments are in
+/// the saved general purpose registers, and any results must be written back to them.
+pub type SyscallHandler = fn(&mut Cpu_Context);
+
+// The registered SyscallHandler, or null if none has been registered
+static SYSCALL_HANDLER: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
 pub fn install_exception_handlers() -> u64 {
     let mut addr: u64 = 0;
     unsafe {
@@ -28,6 +102,65 @@ pub fn install_exception_handlers() -> u64 {
     addr
 }
 
+/// Registers a handler that is given the first chance to handle synchronous exceptions on all cores.
+pub fn set_synchronous_handler(handler: ExceptionHandler) {
+    SYNCHRONOUS_HANDLER.store(handler as *mut (), Ordering::Release);
+}
+
+/// Registers the handler for IRQs on all cores.
+pub fn set_irq_handler(handler: IrqHandler) {
+    IRQ_HANDLER.store(handler as *mut (), Ordering::Release);
+}
+
+/// Registers the handler for system calls on all cores.
+pub fn set_syscall_handler(handler: SyscallHandler) {
+    SYSCALL_HANDLER.store(handler as *mut (), Ordering::Release);
+}
+
+/// Moves the calling core onto a dedicated exception stack.
+///
+/// The current stack pointer is carried over into SP_EL0, and the core switches to using SP_EL0 for
+/// regular execution. SP_EL1 is set to ```stack_top```, so every exception taken from now on runs on
+/// its own stack. This means an exception caused by overflowing the regular stack can still be
+/// reported.
+///
+/// # Safety
+/// ```stack_top``` must be the 16 byte aligned top of a mapped region that is exclusively used by
+/// this core, and large enough to hold a nested exception frame. Must be called at most once per
+/// core, while still running on SP_EL1.
+pub unsafe fn use_exception_stack(stack_top: u64) {
+    asm!(
+        "mov {tmp}, sp",
+        "mov sp, {top}",
+        "msr sp_el0, {tmp}",
+        "msr spsel, #0",
+        top = in(reg) stack_top,
+        tmp = out(reg) _,
+    );
+}
+
+/// Abandons the calling code and resumes ```context``` instead, as if returning from the exception
+/// that saved it. This is how a core first drops to EL0.
+///
+/// # Safety
+/// Must be called on the regular stack (SP_EL0), which is abandoned along with anything still living
+/// on it. ```stack_top``` must be the top of the calling core's exception stack, as passed to
+/// ```use_exception_stack```.
+pub unsafe fn enter_context(context: &Cpu_Context, stack_top: u64) -> ! {
+    // An interrupt taken now would build its frame right where ours goes. The saved SPSR decides
+    // whether they are unmasked again
+    disable_interrupts();
+    let frame = (stack_top - core::mem::size_of::<Cpu_Context>() as u64) as *mut Cpu_Context;
+    frame.write(*context);
+    asm!(
+        "msr spsel, #1",
+        "mov sp, {frame}",
+        "b restore_cpu_context",
+        frame = in(reg) frame,
+        options(noreturn)
+    );
+}
+
 impl Display for Cpu_Context {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         writeln!(f, "Exception Syndrome: {:#x}", self.esr_el1)?;
@@ -36,6 +169,7 @@ impl Display for Cpu_Context {
         writeln!(f, "Exception Link Register: {:#x}", self.elr_el1)?;
         writeln!(f, "Link Register: {:#x}", self.lr)?;
         writeln!(f, "Stack Pointer: {:#x}", self.sp)?;
+        writeln!(f, "Saved SP_EL0: {:#x}", self.sp_el0)?;
         writeln!(f, "")?;
         writeln!(f, "General Purpose Registers:")?;
         for i in (0..30).step_by(2) {
@@ -54,11 +188,11 @@ impl Display for Cpu_Context {
         for i in (0..32).step_by(2) {
             writeln!(
                 f,
-                "Q{:02}: {:#018x}  Q{:02}: {:#018x}",
+                "Q{:02}: {:#034x}  Q{:02}: {:#034x}",
                 i,
-                self.fpr[i] as u64,
+                self.fpr[i],
                 i + 1,
-                self.fpr[i + 1] as u64
+                self.fpr[i + 1]
             )?;
         }
 
@@ -67,21 +201,60 @@ impl Display for Cpu_Context {
 }
 
 #[no_mangle]
-extern "C" fn current_elx_synchronous(context: Cpu_Context) {
+extern "C" fn current_elx_synchronous(context: &mut Cpu_Context) {
+    let handler = SYNCHRONOUS_HANDLER.load(Ordering::Acquire);
+    if !handler.is_null() {
+        // SAFETY: The only non-null values ever stored are valid ExceptionHandler pointers
+        let handler = unsafe { core::mem::transmute::<*mut (), ExceptionHandler>(handler) };
+        if handler(context) {
+            return;
+        }
+    }
     panic!("Uncaught exception! Dumping CPU State: \n\n{}", context);
 }
 
 #[no_mangle]
-extern "C" fn current_elx_irq(context: Cpu_Context) {
+extern "C" fn lower_el_synchronous(context: &mut Cpu_Context) {
+    if context.esr().ec() == EC_SVC64 {
+        let handler = SYSCALL_HANDLER.load(Ordering::Acquire);
+        if !handler.is_null() {
+            // SAFETY: The only non-null values ever stored are valid SyscallHandler pointers
+            let handler = unsafe { core::mem::transmute::<*mut (), SyscallHandler>(handler) };
+            handler(context);
+            return;
+        }
+    }
+    // Everything else, such as aborts, is handled the same way as in the kernel itself. The
+    // context records that it came from EL0
+    current_elx_synchronous(context);
+}
+
+#[no_mangle]
+extern "C" fn lower_el_aarch32(context: &mut Cpu_Context) {
+    panic!(
+        "Exception from AArch32 EL0! Dumping CPU State: \n{}",
+        context
+    );
+}
+
+#[no_mangle]
+extern "C" fn current_elx_irq(context: &mut Cpu_Context) {
+    let handler = IRQ_HANDLER.load(Ordering::Acquire);
+    if !handler.is_null() {
+        // SAFETY: The only non-null values ever stored are valid IrqHandler pointers
+        let handler = unsafe { core::mem::transmute::<*mut (), IrqHandler>(handler) };
+        handler(context);
+        return;
+    }
     panic!("Uncaught exception! Dumping CPU State: \n{}", context);
 }
 
 #[no_mangle]
-extern "C" fn current_elx_fiq(context: Cpu_Context) {
+extern "C" fn current_elx_fiq(context: &mut Cpu_Context) {
     panic!("Uncaught exception! Dumping CPU State: \n{}", context);
 }
 
 #[no_mangle]
-extern "C" fn current_elx_serror(context: Cpu_Context) {
+extern "C" fn current_elx_serror(context: &mut Cpu_Context) {
     panic!("Uncaught exception! Dumping CPU State: \n{}", context);
 }
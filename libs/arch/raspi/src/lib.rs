@@ -4,6 +4,7 @@
 #![feature(int_roundings)]
 
 pub mod concurrency;
+pub mod cpu;
 pub mod exception;
 pub mod memory;
 pub mod peripherals;
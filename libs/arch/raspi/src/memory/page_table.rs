@@ -19,6 +19,76 @@ impl MemoryType {
     pub const NORMAL_CACHEABLE: MemoryType = MemoryType(1);
 }
 
+/// Defines the access permissions of a mapping.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Protection {
+    /// The mapping can be written to. Mappings are always readable
+    pub writable: bool,
+    /// Instructions can be fetched from the mapping
+    pub executable: bool,
+    /// The mapping can be accessed from EL0. Kernel code is never allowed to execute from user
+    /// accessible mappings
+    pub user: bool,
+}
+
+impl Protection {
+    /// The protection used by ```map_page```, readable, writable and executable by the kernel only
+    pub const KERNEL_RWX: Protection = Protection {
+        writable: true,
+        executable: true,
+        user: false,
+    };
+    pub const KERNEL_RW: Protection = Protection {
+        writable: true,
+        executable: false,
+        user: false,
+    };
+    pub const KERNEL_RO: Protection = Protection {
+        writable: false,
+        executable: false,
+        user: false,
+    };
+
+    // Encodes the protection into the AP[2:1], PXN and UXN fields of a descriptor
+    fn ap(&self) -> u64 {
+        match (self.user, self.writable) {
+            (false, true) => 0b00,
+            (true, true) => 0b01,
+            (false, false) => 0b10,
+            (true, false) => 0b11,
+        }
+    }
+
+    fn pxn(&self) -> bool {
+        !self.executable || self.user
+    }
+
+    fn uxn(&self) -> bool {
+        !self.executable || !self.user
+    }
+
+    fn from_descriptor(descriptor: &PageDescriptor) -> Protection {
+        let ap = descriptor.ap();
+        Protection {
+            writable: ap & 0b10 == 0,
+            executable: match ap & 0b01 == 1 {
+                true => !descriptor.uxn(),
+                false => !descriptor.pxn(),
+            },
+            user: ap & 0b01 == 1,
+        }
+    }
+}
+
+/// A snapshot of a single 4KiB page mapping.
+#[derive(Clone, Copy, Debug)]
+pub struct PageEntry {
+    pub phys_addr: u64,
+    pub protection: Protection,
+    /// The four bits of the descriptor reserved for software use, which the MMU ignores
+    pub software_bits: u8,
+}
+
 pub trait PageAlloc {
     /// Returns a new, zero-initialized frame of memory.
     fn allocate_frame(&mut self) -> Result<*mut u8, ()>;
@@ -27,9 +97,16 @@ pub trait PageAlloc {
 }
 
 /// Represents a single Aarch64 page table, supporting 4KiB granularity.
+///
+/// Tables are always stored by physical address in their descriptors. Whenever the page table needs
+/// to read or write one of its tables, it adds ```phys_offset``` to that physical address. This allows
+/// a page table to be edited both before the MMU is enabled (or while physical memory is identity
+/// mapped), where the offset is zero, and through a linear mapping of physical memory afterwards.
 pub struct PageTable<'a, S: RawMutex, T: PageAlloc> {
     allocator: &'a Mutex<S, T>,
     lvl0_table: &'a mut [Lvl0TableDescriptor],
+    phys_offset: u64,
+    non_global: bool,
 }
 
 impl<'a, S: RawMutex, T: PageAlloc> PageTable<'a, S, T> {
@@ -37,44 +114,82 @@ impl<'a, S: RawMutex, T: PageAlloc> PageTable<'a, S, T> {
     ///
     /// All page tables allocate memory for the lvl0 table, even if they are empty and contain no mappings.
     pub fn new(allocator: &'a Mutex<S, T>) -> Result<Self, ()> {
+        PageTable::new_with_offset(allocator, 0)
+    }
+
+    /// Constructs a new, empty page table whose tables are accessed through a linear mapping of
+    /// physical memory starting at ```phys_offset```.
+    ///
+    /// ```allocator``` must still return the physical addresses of the frames it allocates.
+    pub fn new_with_offset(allocator: &'a Mutex<S, T>, phys_offset: u64) -> Result<Self, ()> {
         // Allocate a single page for the Level 0 table
-        let page = allocator.lock().allocate_frame()? as *mut Lvl0TableDescriptor;
+        let page = allocator.lock().allocate_frame()? as u64;
         unsafe {
             Ok(PageTable {
                 allocator,
-                lvl0_table: from_raw_parts_mut(page, 512),
+                lvl0_table: from_raw_parts_mut(
+                    (page + phys_offset) as *mut Lvl0TableDescriptor,
+                    512,
+                ),
+                phys_offset,
+                non_global: false,
             })
         }
     }
 
     /// Provides access to the underlying raw pointer, for example to store the pointer in a
     /// register.
+    ///
+    /// The returned pointer is always the physical address of the lvl0 table.
     pub fn as_raw_ptr(&self) -> *const Lvl0TableDescriptor {
-        self.lvl0_table.as_ptr()
+        (self.lvl0_table.as_ptr() as u64 - self.phys_offset) as *const Lvl0TableDescriptor
     }
 
     pub unsafe fn from_raw_ptr(
         ptr: *const Lvl0TableDescriptor,
         allocator: &'a Mutex<S, T>,
+    ) -> Self {
+        PageTable::from_raw_ptr_with_offset(ptr, allocator, 0)
+    }
+
+    /// Constructs a page table from the physical address of an existing lvl0 table, accessing
+    /// its tables through a linear mapping of physical memory starting at ```phys_offset```.
+    pub unsafe fn from_raw_ptr_with_offset(
+        ptr: *const Lvl0TableDescriptor,
+        allocator: &'a Mutex<S, T>,
+        phys_offset: u64,
     ) -> Self {
         PageTable {
             allocator,
-            lvl0_table: from_raw_parts_mut(ptr.cast_mut(), 512),
+            lvl0_table: from_raw_parts_mut((ptr as u64 + phys_offset) as *mut _, 512),
+            phys_offset,
+            non_global: false,
         }
     }
 
+    /// Controls whether new mappings are marked as non-global.
+    ///
+    /// Non-global mappings are tagged in the TLB with the ASID that was active when they were
+    /// loaded, which is required for any table that is installed in TTBR0 alongside other address
+    /// spaces. Mappings that already exist are left untouched.
+    pub fn set_non_global(&mut self, non_global: bool) {
+        self.non_global = non_global;
+    }
+
     /// Performs a page table walk, translating a Virtual address into a Physical address.
     ///
     /// Returns Err if the page table walk fails for any reason, for example if the requested virtual
     /// address is not mapped.
     pub fn virt_to_phys(&self, virt_addr: VirtualAddr) -> Result<u64, ()> {
+        let offset = self.phys_offset;
         let lvl0_table = &self.lvl0_table;
         let lvl0_descriptor = lvl0_table[virt_addr.lvl0_idx() as usize];
         if !lvl0_descriptor.valid() {
             return Err(());
         }
 
-        let lvl1_table_ptr = (lvl0_descriptor.next_table_addr() << 12) as *mut Lvl1TableDescriptor;
+        let lvl1_table_ptr =
+            ((lvl0_descriptor.next_table_addr() << 12) + offset) as *mut Lvl1TableDescriptor;
         let lvl1_table = unsafe { from_raw_parts_mut(lvl1_table_ptr, 512) };
         let lvl1_descriptor = lvl1_table[virt_addr.lvl1_idx() as usize];
         if !lvl1_descriptor.valid() {
@@ -85,7 +200,8 @@ impl<'a, S: RawMutex, T: PageAlloc> PageTable<'a, S, T> {
             return Ok((lvl1_block_descriptor.output_addr() << 12) | lower);
         }
 
-        let lvl2_table_ptr = (lvl1_descriptor.next_table_addr() << 12) as *mut Lvl2TableDescriptor;
+        let lvl2_table_ptr =
+            ((lvl1_descriptor.next_table_addr() << 12) + offset) as *mut Lvl2TableDescriptor;
         let lvl2_table = unsafe { from_raw_parts_mut(lvl2_table_ptr, 512) };
         let lvl2_descriptor = lvl2_table[virt_addr.lvl2_idx() as usize];
         if !lvl2_descriptor.valid() {
@@ -96,7 +212,8 @@ impl<'a, S: RawMutex, T: PageAlloc> PageTable<'a, S, T> {
             return Ok((lvl2_block_descriptor.output_addr() << 12) | lower);
         }
 
-        let page_table_ptr = (lvl2_descriptor.next_table_addr() << 12) as *mut PageDescriptor;
+        let page_table_ptr =
+            ((lvl2_descriptor.next_table_addr() << 12) + offset) as *mut PageDescriptor;
         let page_table = unsafe { from_raw_parts_mut(page_table_ptr, 512) };
         let page_descriptor = page_table[virt_addr.lvl3_idx() as usize];
 
@@ -113,13 +230,15 @@ impl<'a, S: RawMutex, T: PageAlloc> PageTable<'a, S, T> {
             return false;
         }
 
+        let offset = self.phys_offset;
         let lvl0_table = &mut self.lvl0_table;
         let lvl0_descriptor = lvl0_table[virt_addr.lvl0_idx() as usize];
         if !lvl0_descriptor.valid() {
             return false;
         }
 
-        let lvl1_table_ptr = (lvl0_descriptor.next_table_addr() << 12) as *mut Lvl1BlockDescriptor;
+        let lvl1_table_ptr =
+            ((lvl0_descriptor.next_table_addr() << 12) + offset) as *mut Lvl1BlockDescriptor;
         let lvl1_table = unsafe { from_raw_parts_mut(lvl1_table_ptr, 512) };
         let lvl1_descriptor = &mut lvl1_table[virt_addr.lvl1_idx() as usize];
         if !lvl1_descriptor.valid() || lvl1_descriptor.is_table() {
@@ -147,6 +266,7 @@ impl<'a, S: RawMutex, T: PageAlloc> PageTable<'a, S, T> {
             return Err(());
         }
 
+        let offset = self.phys_offset;
         let lvl0_table = &mut self.lvl0_table;
         let lvl0_descriptor = &mut lvl0_table[virt_addr.lvl0_idx() as usize];
         if !lvl0_descriptor.valid() {
@@ -158,7 +278,8 @@ impl<'a, S: RawMutex, T: PageAlloc> PageTable<'a, S, T> {
             lvl0_descriptor.set_next_table_addr(table_addr.bit_range(47, 12));
         }
 
-        let lvl1_table_ptr = (lvl0_descriptor.next_table_addr() << 12) as *mut Lvl1BlockDescriptor;
+        let lvl1_table_ptr =
+            ((lvl0_descriptor.next_table_addr() << 12) + offset) as *mut Lvl1BlockDescriptor;
         let lvl1_table = unsafe { from_raw_parts_mut(lvl1_table_ptr, 512) };
         let lvl1_block_descriptor = &mut lvl1_table[virt_addr.lvl1_idx() as usize];
         if lvl1_block_descriptor.valid() {
@@ -167,6 +288,7 @@ impl<'a, S: RawMutex, T: PageAlloc> PageTable<'a, S, T> {
             lvl1_block_descriptor.set_valid(true);
             lvl1_block_descriptor.set_is_table(false);
             lvl1_block_descriptor.set_access_flag(true);
+            lvl1_block_descriptor.set_not_global(self.non_global);
             lvl1_block_descriptor.set_attrib_idx(memory_type.0.into());
             lvl1_block_descriptor.set_output_addr(phys_addr.bit_range(47, 30));
             Ok(())
@@ -190,6 +312,7 @@ impl<'a, S: RawMutex, T: PageAlloc> PageTable<'a, S, T> {
             return Err(());
         }
 
+        let offset = self.phys_offset;
         let lvl0_table = &mut self.lvl0_table;
         let lvl0_descriptor = &mut lvl0_table[virt_addr.lvl0_idx() as usize];
         if !lvl0_descriptor.valid() {
@@ -201,7 +324,8 @@ impl<'a, S: RawMutex, T: PageAlloc> PageTable<'a, S, T> {
             lvl0_descriptor.set_next_table_addr(table_addr.bit_range(47, 12));
         }
 
-        let lvl1_table_ptr = (lvl0_descriptor.next_table_addr() << 12) as *mut Lvl1TableDescriptor;
+        let lvl1_table_ptr =
+            ((lvl0_descriptor.next_table_addr() << 12) + offset) as *mut Lvl1TableDescriptor;
         let lvl1_table = unsafe { from_raw_parts_mut(lvl1_table_ptr, 512) };
         let lvl1_descriptor = &mut lvl1_table[virt_addr.lvl1_idx() as usize];
         if !lvl1_descriptor.valid() {
@@ -212,7 +336,8 @@ impl<'a, S: RawMutex, T: PageAlloc> PageTable<'a, S, T> {
             lvl1_descriptor.set_next_table_addr(table_addr.bit_range(47, 12));
         }
 
-        let lvl2_table_ptr = (lvl1_descriptor.next_table_addr() << 12) as *mut Lvl2BlockDescriptor;
+        let lvl2_table_ptr =
+            ((lvl1_descriptor.next_table_addr() << 12) + offset) as *mut Lvl2BlockDescriptor;
         let lvl2_table = unsafe { from_raw_parts_mut(lvl2_table_ptr, 512) };
         let lvl2_block_descriptor = &mut lvl2_table[virt_addr.lvl2_idx() as usize];
         if lvl2_block_descriptor.valid() {
@@ -221,6 +346,7 @@ impl<'a, S: RawMutex, T: PageAlloc> PageTable<'a, S, T> {
             lvl2_block_descriptor.set_valid(true);
             lvl2_block_descriptor.set_is_table(false);
             lvl2_block_descriptor.set_access_flag(true);
+            lvl2_block_descriptor.set_not_global(self.non_global);
             lvl2_block_descriptor.set_attrib_idx(memory_type.0.into());
             lvl2_block_descriptor.set_output_addr(phys_addr.bit_range(47, 21));
             Ok(())
@@ -239,11 +365,26 @@ impl<'a, S: RawMutex, T: PageAlloc> PageTable<'a, S, T> {
         phys_addr: u64,
         virt_addr: VirtualAddr,
         mem_type: MemoryType,
+    ) -> Result<(), ()> {
+        self.map_page_with_protection(phys_addr, virt_addr, mem_type, Protection::KERNEL_RWX)
+    }
+
+    /// Maps a single 4KiB page of physical memory starting at ```phys_addr``` to ```virt_addr```,
+    /// with the access permissions given by ```protection```.
+    ///
+    /// Behaves identically to ```map_page``` otherwise.
+    pub fn map_page_with_protection(
+        &mut self,
+        phys_addr: u64,
+        virt_addr: VirtualAddr,
+        mem_type: MemoryType,
+        protection: Protection,
     ) -> Result<(), ()> {
         if phys_addr % (4 * KIB) != 0 || virt_addr.0 % (4 * KIB) != 0 {
             return Err(());
         }
 
+        let offset = self.phys_offset;
         let lvl0_table = &mut self.lvl0_table;
         let lvl0_descriptor = &mut lvl0_table[virt_addr.lvl0_idx() as usize];
         if !lvl0_descriptor.valid() {
@@ -255,7 +396,8 @@ impl<'a, S: RawMutex, T: PageAlloc> PageTable<'a, S, T> {
             lvl0_descriptor.set_next_table_addr(table_addr.bit_range(47, 12));
         }
 
-        let lvl1_table_ptr = (lvl0_descriptor.next_table_addr() << 12) as *mut Lvl1TableDescriptor;
+        let lvl1_table_ptr =
+            ((lvl0_descriptor.next_table_addr() << 12) + offset) as *mut Lvl1TableDescriptor;
         let lvl1_table = unsafe { from_raw_parts_mut(lvl1_table_ptr, 512) };
         let lvl1_descriptor = &mut lvl1_table[virt_addr.lvl1_idx() as usize];
         if !lvl1_descriptor.valid() {
@@ -266,7 +408,8 @@ impl<'a, S: RawMutex, T: PageAlloc> PageTable<'a, S, T> {
             lvl1_descriptor.set_next_table_addr(table_addr.bit_range(47, 12));
         }
 
-        let lvl2_table_ptr = (lvl1_descriptor.next_table_addr() << 12) as *mut Lvl2TableDescriptor;
+        let lvl2_table_ptr =
+            ((lvl1_descriptor.next_table_addr() << 12) + offset) as *mut Lvl2TableDescriptor;
         let lvl2_table = unsafe { from_raw_parts_mut(lvl2_table_ptr, 512) };
         let lvl2_descriptor = &mut lvl2_table[virt_addr.lvl2_idx() as usize];
         if !lvl2_descriptor.valid() {
@@ -277,56 +420,184 @@ impl<'a, S: RawMutex, T: PageAlloc> PageTable<'a, S, T> {
             lvl2_descriptor.set_next_table_addr(table_addr.bit_range(47, 12));
         }
 
-        let page_table_ptr = (lvl2_descriptor.next_table_addr() << 12) as *mut PageDescriptor;
+        let page_table_ptr =
+            ((lvl2_descriptor.next_table_addr() << 12) + offset) as *mut PageDescriptor;
         let page_table = unsafe { from_raw_parts_mut(page_table_ptr, 512) };
         let page_descriptor = &mut page_table[virt_addr.lvl3_idx() as usize];
 
         page_descriptor.set_valid(true);
         page_descriptor.set_is_page(true);
         page_descriptor.set_access_flag(true);
+        page_descriptor.set_not_global(self.non_global);
         page_descriptor.set_output_addr(phys_addr.bit_range(47, 12));
         page_descriptor.set_attrix_idx(mem_type.0.into());
+        page_descriptor.set_ap(protection.ap());
+        page_descriptor.set_pxn(protection.pxn());
+        page_descriptor.set_uxn(protection.uxn());
 
         Ok(())
     }
+
+    /// Returns the access permissions of the 4KiB page mapped at ```virt_addr```, or None if it is
+    /// not mapped by a 4KiB page.
+    pub fn page_protection(&self, virt_addr: VirtualAddr) -> Option<Protection> {
+        let page_descriptor = unsafe { &*self.page_descriptor_ptr(virt_addr)? };
+        match page_descriptor.valid() {
+            true => Some(Protection::from_descriptor(page_descriptor)),
+            false => None,
+        }
+    }
+
+    /// Returns the mapping of the 4KiB page at ```virt_addr```, or None if it is not mapped by a 4KiB
+    /// page.
+    pub fn page_entry(&self, virt_addr: VirtualAddr) -> Option<PageEntry> {
+        let page_descriptor = unsafe { &*self.page_descriptor_ptr(virt_addr)? };
+        match page_descriptor.valid() {
+            true => Some(PageEntry {
+                phys_addr: page_descriptor.output_addr() << 12,
+                protection: Protection::from_descriptor(page_descriptor),
+                software_bits: page_descriptor.software_use() as u8,
+            }),
+            false => None,
+        }
+    }
+
+    /// Replaces the software use bits of the 4KiB page mapped at ```virt_addr```. Only the lower
+    /// four bits of ```bits``` are used.
+    ///
+    /// Returns Err if ```virt_addr``` is not mapped by a 4KiB page.
+    pub fn set_software_bits(&mut self, virt_addr: VirtualAddr, bits: u8) -> Result<(), ()> {
+        let page_descriptor = self.page_descriptor_mut(virt_addr).ok_or(())?;
+        if !page_descriptor.valid() {
+            return Err(());
+        }
+
+        page_descriptor.set_software_use((bits & 0xF).into());
+        Ok(())
+    }
+
+    /// Changes the access permissions of the 4KiB page mapped at ```virt_addr```.
+    ///
+    /// Returns Err if ```virt_addr``` is not mapped by a 4KiB page. It is the caller's responsibility
+    /// to invalidate any stale TLB entries.
+    pub fn protect_page(
+        &mut self,
+        virt_addr: VirtualAddr,
+        protection: Protection,
+    ) -> Result<(), ()> {
+        let page_descriptor = self.page_descriptor_mut(virt_addr).ok_or(())?;
+        if !page_descriptor.valid() {
+            return Err(());
+        }
+
+        page_descriptor.set_ap(protection.ap());
+        page_descriptor.set_pxn(protection.pxn());
+        page_descriptor.set_uxn(protection.uxn());
+        Ok(())
+    }
+
+    /// Unmaps a single 4KiB page starting at ```virt_addr```.
+    ///
+    /// Returns the physical address the page was mapped to, or Err if ```virt_addr``` is not aligned
+    /// on a 4KiB boundary or is not mapped by a 4KiB page. Tables that become empty are not freed,
+    /// and it is the caller's responsibility to invalidate any stale TLB entries.
+    pub fn unmap_page(&mut self, virt_addr: VirtualAddr) -> Result<u64, ()> {
+        if virt_addr.0 % (4 * KIB) != 0 {
+            return Err(());
+        }
+
+        let page_descriptor = self.page_descriptor_mut(virt_addr).ok_or(())?;
+        if !page_descriptor.valid() {
+            return Err(());
+        }
+
+        let phys_addr = page_descriptor.output_addr() << 12;
+        page_descriptor.0 = 0;
+        Ok(phys_addr)
+    }
+
+    /// Walks the page table to the lvl3 descriptor for ```virt_addr```, without allocating any
+    /// tables.
+    ///
+    /// Returns None if one of the intermediate tables does not exist, or if ```virt_addr``` is
+    /// covered by a block mapping.
+    fn page_descriptor_mut(&mut self, virt_addr: VirtualAddr) -> Option<&mut PageDescriptor> {
+        // SAFETY: The exclusive borrow of the page table covers all of its descriptors
+        self.page_descriptor_ptr(virt_addr)
+            .map(|descriptor| unsafe { &mut *descriptor })
+    }
+
+    // Non-allocating walk shared by page_descriptor_mut and the read-only lookups
+    fn page_descriptor_ptr(&self, virt_addr: VirtualAddr) -> Option<*mut PageDescriptor> {
+        let offset = self.phys_offset;
+        let lvl0_descriptor = self.lvl0_table[virt_addr.lvl0_idx() as usize];
+        if !lvl0_descriptor.valid() {
+            return None;
+        }
+
+        let lvl1_table_ptr =
+            ((lvl0_descriptor.next_table_addr() << 12) + offset) as *mut Lvl1TableDescriptor;
+        let lvl1_table = unsafe { from_raw_parts_mut(lvl1_table_ptr, 512) };
+        let lvl1_descriptor = lvl1_table[virt_addr.lvl1_idx() as usize];
+        if !lvl1_descriptor.valid() || !lvl1_descriptor.is_table() {
+            return None;
+        }
+
+        let lvl2_table_ptr =
+            ((lvl1_descriptor.next_table_addr() << 12) + offset) as *mut Lvl2TableDescriptor;
+        let lvl2_table = unsafe { from_raw_parts_mut(lvl2_table_ptr, 512) };
+        let lvl2_descriptor = lvl2_table[virt_addr.lvl2_idx() as usize];
+        if !lvl2_descriptor.valid() || !lvl2_descriptor.is_table() {
+            return None;
+        }
+
+        let page_table_ptr =
+            ((lvl2_descriptor.next_table_addr() << 12) + offset) as *mut PageDescriptor;
+        Some(unsafe { page_table_ptr.add(virt_addr.lvl3_idx() as usize) })
+    }
 }
 
 impl<S: RawMutex, T: PageAlloc> Drop for PageTable<'_, S, T> {
     /// Walks the entire allocated page table, freeing each frame
     fn drop(&mut self) {
+        let offset = self.phys_offset;
         for lvl0_descriptor in &mut *self.lvl0_table {
             if lvl0_descriptor.valid() && lvl0_descriptor.is_table() {
-                let lvl1_table_ptr =
-                    (lvl0_descriptor.next_table_addr() << 12) as *mut Lvl1TableDescriptor;
-                let lvl1_table = unsafe { from_raw_parts_mut(lvl1_table_ptr, 512) };
+                let lvl1_table_addr = lvl0_descriptor.next_table_addr() << 12;
+                let lvl1_table = unsafe {
+                    from_raw_parts_mut((lvl1_table_addr + offset) as *mut Lvl1TableDescriptor, 512)
+                };
 
                 for lvl1_descriptor in &mut *lvl1_table {
                     if lvl1_descriptor.valid() && lvl1_descriptor.is_table() {
-                        let lvl2_table_ptr =
-                            (lvl1_descriptor.next_table_addr() << 12) as *mut Lvl2TableDescriptor;
-                        let lvl2_table = unsafe { from_raw_parts_mut(lvl2_table_ptr, 512) };
+                        let lvl2_table_addr = lvl1_descriptor.next_table_addr() << 12;
+                        let lvl2_table = unsafe {
+                            from_raw_parts_mut(
+                                (lvl2_table_addr + offset) as *mut Lvl2TableDescriptor,
+                                512,
+                            )
+                        };
 
                         for lvl2_descriptor in &mut *lvl2_table {
-                            let page_table_ptr =
-                                (lvl2_descriptor.next_table_addr() << 12) as *mut PageDescriptor;
-
-                            self.allocator
-                                .lock()
-                                .deallocate_frame(page_table_ptr as *mut u8);
+                            if lvl2_descriptor.valid() && lvl2_descriptor.is_table() {
+                                let page_table_addr = lvl2_descriptor.next_table_addr() << 12;
+                                self.allocator
+                                    .lock()
+                                    .deallocate_frame(page_table_addr as *mut u8);
+                            }
                         }
                         self.allocator
                             .lock()
-                            .deallocate_frame(lvl2_table_ptr as *mut u8);
+                            .deallocate_frame(lvl2_table_addr as *mut u8);
                     }
                 }
                 self.allocator
                     .lock()
-                    .deallocate_frame(lvl1_table_ptr as *mut u8);
+                    .deallocate_frame(lvl1_table_addr as *mut u8);
             }
         }
-        self.allocator
-            .lock()
-            .deallocate_frame(self.lvl0_table.as_ptr() as *mut u8);
+        let lvl0_table_addr = self.as_raw_ptr() as *mut u8;
+        self.allocator.lock().deallocate_frame(lvl0_table_addr);
     }
 }
 
@@ -0,0 +1,81 @@
+use super::{mmio_read, mmio_write};
+
+/// The per-core mailboxes of the BCM2836 ARM local peripherals, used by the Raspberry Pi 3 to
+/// interrupt other cores.
+///
+/// Every core has four 32 bit mailboxes. Writing to the set register of a mailbox ORs the value
+/// into it, and raises an IRQ on the owning core for as long as any bit is set. The owner clears
+/// bits by writing them to the clear register. Only mailbox 0 is used here.
+///
+/// The same block also routes the interrupts of each core's timers, which are used alongside the
+/// mailboxes.
+pub struct CoreMailboxes {
+    mmio_base: u64,
+}
+
+impl CoreMailboxes {
+    const TIMER_INT_CONTROL_OFFSET: u64 = 0x40;
+    const MAILBOX_INT_CONTROL_OFFSET: u64 = 0x50;
+    const IRQ_SOURCE_OFFSET: u64 = 0x60;
+    const MAILBOX_SET_OFFSET: u64 = 0x80;
+    const MAILBOX_CLEAR_OFFSET: u64 = 0xC0;
+
+    // Bit of the core IRQ source register signalling a pending mailbox 0 interrupt
+    const MAILBOX0_IRQ_PENDING: u32 = 1 << 4;
+    // Bit of both the timer control and the IRQ source register for the EL1 physical timer
+    const PHYS_TIMER_IRQ: u32 = 1 << 1;
+
+    /// Creates a handle for the local peripherals mapped at ```mmio_base```.
+    ///
+    /// # Safety
+    /// ```mmio_base``` must be the virtual address of the ARM local peripherals.
+    pub unsafe fn new(mmio_base: u64) -> Self {
+        CoreMailboxes { mmio_base }
+    }
+
+    /// Lets mailbox 0 of ```core``` raise IRQs on it.
+    pub fn enable(&self, core: u64) {
+        mmio_write(
+            self.mmio_base + CoreMailboxes::MAILBOX_INT_CONTROL_OFFSET + 4 * core,
+            1,
+        );
+    }
+
+    /// Lets the EL1 physical timer of ```core``` raise IRQs on it.
+    pub fn enable_timer(&self, core: u64) {
+        mmio_write(
+            self.mmio_base + CoreMailboxes::TIMER_INT_CONTROL_OFFSET + 4 * core,
+            CoreMailboxes::PHYS_TIMER_IRQ,
+        );
+    }
+
+    /// Returns true if the EL1 physical timer of ```core``` is the source of a pending IRQ.
+    pub fn is_timer_pending(&self, core: u64) -> bool {
+        mmio_read(self.mmio_base + CoreMailboxes::IRQ_SOURCE_OFFSET + 4 * core)
+            & CoreMailboxes::PHYS_TIMER_IRQ
+            != 0
+    }
+
+    /// Sets ```bits``` in mailbox 0 of ```core```.
+    pub fn send(&self, core: u64, bits: u32) {
+        mmio_write(
+            self.mmio_base + CoreMailboxes::MAILBOX_SET_OFFSET + 0x10 * core,
+            bits,
+        );
+    }
+
+    /// Returns true if mailbox 0 of ```core``` is the source of a pending IRQ.
+    pub fn is_pending(&self, core: u64) -> bool {
+        mmio_read(self.mmio_base + CoreMailboxes::IRQ_SOURCE_OFFSET + 4 * core)
+            & CoreMailboxes::MAILBOX0_IRQ_PENDING
+            != 0
+    }
+
+    /// Reads and clears the bits set in mailbox 0 of ```core```.
+    pub fn take(&self, core: u64) -> u32 {
+        let clear_reg = self.mmio_base + CoreMailboxes::MAILBOX_CLEAR_OFFSET + 0x10 * core;
+        let bits = mmio_read(clear_reg);
+        mmio_write(clear_reg, bits);
+        bits
+    }
+}
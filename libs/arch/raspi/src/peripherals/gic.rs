@@ -0,0 +1,90 @@
+use super::{mmio_read, mmio_write};
+
+/// The GIC-400 interrupt controller of the Raspberry Pi 4.
+///
+/// Software generated interrupts (SGIs) are how the GIC lets cores interrupt each other. SGIs 0 to 15
+/// are always routed to the core they are sent to. The only other interrupts used are private
+/// peripheral interrupts (PPIs), such as the one of each core's timer.
+pub struct Gic {
+    dist_base: u64,
+    cpu_base: u64,
+}
+
+impl Gic {
+    const GICD_CTLR: u64 = 0x000;
+    const GICD_ISENABLER: u64 = 0x100;
+    const GICD_IPRIORITYR: u64 = 0x400;
+    const GICD_SGIR: u64 = 0xF00;
+
+    const GICC_CTLR: u64 = 0x000;
+    const GICC_PMR: u64 = 0x004;
+    const GICC_IAR: u64 = 0x00C;
+    const GICC_EOIR: u64 = 0x010;
+
+    pub const NUM_SGIS: u32 = 16;
+    /// PPI raised by the EL1 physical timer of a core.
+    pub const TIMER_PPI: u32 = 30;
+    /// Returned by ```acknowledge``` when no interrupt is pending.
+    pub const SPURIOUS_INTERRUPT: u32 = 1023;
+
+    /// Creates a handle for the distributor and CPU interface mapped at the given addresses.
+    ///
+    /// # Safety
+    /// The addresses must be the virtual addresses of the GIC-400 distributor and CPU interface.
+    pub unsafe fn new(dist_base: u64, cpu_base: u64) -> Self {
+        Gic {
+            dist_base,
+            cpu_base,
+        }
+    }
+
+    /// Enables forwarding of interrupts from the distributor. Must be called once, by any core.
+    pub fn init_distributor(&self) {
+        mmio_write(self.dist_base + Gic::GICD_CTLR, 1);
+    }
+
+    /// Enables the CPU interface of the calling core, and unmasks its SGIs. The SGI enable and
+    /// priority registers are banked per core, so every core has to call this.
+    pub fn init_cpu_interface(&self) {
+        // One priority byte per interrupt, with 0 the most urgent
+        for reg in 0..(Gic::NUM_SGIS / 4) as u64 {
+            mmio_write(self.dist_base + Gic::GICD_IPRIORITYR + 4 * reg, 0);
+        }
+        mmio_write(self.dist_base + Gic::GICD_ISENABLER, 0xFFFF);
+        // Let interrupts of any priority through
+        mmio_write(self.cpu_base + Gic::GICC_PMR, 0xFF);
+        mmio_write(self.cpu_base + Gic::GICC_CTLR, 1);
+    }
+
+    /// Unmasks the PPI ```id``` of the calling core, with the same priority as the SGIs. Like those of
+    /// the SGIs, the registers involved are banked per core.
+    pub fn enable_ppi(&self, id: u32) {
+        // Four priority bytes per register, so keep the other three
+        let priority_reg = self.dist_base + Gic::GICD_IPRIORITYR + 4 * (id / 4) as u64;
+        let shift = 8 * (id % 4);
+        mmio_write(priority_reg, mmio_read(priority_reg) & !(0xFF << shift));
+        // Writing zeros has no effect, so only this interrupt is enabled
+        mmio_write(self.dist_base + Gic::GICD_ISENABLER, 1 << id);
+    }
+
+    /// Raises SGI ```sgi``` on ```core```.
+    pub fn send_sgi(&self, core: u64, sgi: u32) {
+        mmio_write(
+            self.dist_base + Gic::GICD_SGIR,
+            (1 << (16 + core)) | (sgi & 0xF),
+        );
+    }
+
+    /// Acknowledges the highest priority pending interrupt, returning the raw IAR value.
+    ///
+    /// The interrupt ID is in bits 9:0. Every value other than ```SPURIOUS_INTERRUPT``` must be
+    /// passed to ```end_of_interrupt``` once the interrupt has been handled.
+    pub fn acknowledge(&self) -> u32 {
+        mmio_read(self.cpu_base + Gic::GICC_IAR)
+    }
+
+    /// Signals that handling the interrupt ```iar``` returned by ```acknowledge``` is complete.
+    pub fn end_of_interrupt(&self, iar: u32) {
+        mmio_write(self.cpu_base + Gic::GICC_EOIR, iar);
+    }
+}
@@ -9,6 +9,9 @@ impl ConstantsRaspi3 {
     pub const MMIO_OFFSET: u64 =
         ConstantsRaspi3::MMIO_PHYS_BASE - ConstantsRaspi3::PERIPHERALS_PHYS_BASE;
     pub const EMMC_OFFSET_FROM_MMIO_BASE: u64 = 0x300000;
+    // The BCM2836 ARM local peripherals, holding the core mailboxes. They lie past the end of the
+    // regular peripherals, and of RAM
+    pub const LOCAL_PERIPHERALS_PHYS_BASE: u64 = 0x40000000;
 }
 
 pub struct ConstantsRaspi4;
@@ -21,6 +24,9 @@ impl ConstantsRaspi4 {
     // Sourced from: https://github.com/librerpi/rpi-open-firmware/blob/master/docs/rpi4-gpio-mux.dot
     // Seems very poorly documented
     pub const EMMC_OFFSET_FROM_MMIO_BASE: u64 = 0x340000;
+    // GIC-400 distributor and CPU interface
+    pub const GIC_DIST_PHYS_BASE: u64 = 0xFF841000;
+    pub const GIC_CPU_PHYS_BASE: u64 = 0xFF842000;
 }
 
 #[derive(PartialEq)]
@@ -95,7 +101,10 @@ fn mmio_write(reg: u64, val: u32) {
     unsafe { core::intrinsics::volatile_store(reg as *mut u32, val) }
 }
 
+pub mod core_mailbox;
 pub mod emmc;
+pub mod gic;
 pub mod mailbox;
+pub mod power;
 pub mod timer;
 pub mod uart;
@@ -0,0 +1,50 @@
+use core::hint;
+
+use super::{get_default_mmio_base, mmio_read, mmio_write};
+
+/// The power management block, whose watchdog is the only way to reset the board.
+pub struct PowerManagement {
+    mmio_base: u64,
+}
+
+impl PowerManagement {
+    pub const PM_BASE_OFFSET: u64 = 0x100000;
+    pub const PM_RSTC_OFFSET: u64 = PowerManagement::PM_BASE_OFFSET + 0x1c;
+    pub const PM_WDOG_OFFSET: u64 = PowerManagement::PM_BASE_OFFSET + 0x24;
+
+    // Every write to a PM register must carry this in its top byte, or it is ignored
+    const PASSWORD: u32 = 0x5a000000;
+    const RSTC_WRCFG_MASK: u32 = 0x30;
+    const RSTC_WRCFG_FULL_RESET: u32 = 0x20;
+    // Watchdog ticks are roughly 16us
+    const RESET_TICKS: u32 = 10;
+
+    pub fn new() -> Self {
+        PowerManagement {
+            mmio_base: get_default_mmio_base(),
+        }
+    }
+
+    pub fn update_mmio_base(&mut self, mmio_base: u64) {
+        self.mmio_base = mmio_base;
+    }
+
+    /// Resets the whole board by letting the watchdog expire almost immediately.
+    pub fn reset(&mut self) -> ! {
+        mmio_write(
+            self.mmio_base + PowerManagement::PM_WDOG_OFFSET,
+            PowerManagement::PASSWORD | PowerManagement::RESET_TICKS,
+        );
+        let rstc = mmio_read(self.mmio_base + PowerManagement::PM_RSTC_OFFSET);
+        mmio_write(
+            self.mmio_base + PowerManagement::PM_RSTC_OFFSET,
+            PowerManagement::PASSWORD
+                | (rstc & !PowerManagement::RSTC_WRCFG_MASK)
+                | PowerManagement::RSTC_WRCFG_FULL_RESET,
+        );
+
+        loop {
+            hint::spin_loop();
+        }
+    }
+}
@@ -1,7 +1,8 @@
 use core::{hint, time::Duration};
 
+use aarch64_cpu::registers::{CNTP_CTL_EL0, CNTP_CVAL_EL0};
 use bitfield::BitRange;
-use tock_registers::interfaces::Readable;
+use tock_registers::interfaces::{Readable, Writeable};
 
 pub fn timer_freq() -> u32 {
     aarch64_cpu::registers::CNTFRQ_EL0.get().bit_range(31, 0)
@@ -20,6 +21,19 @@ pub fn duration_to_cycles(duration: Duration) -> u64 {
     (duration.as_secs_f64() * timer_freq() as f64) as u64
 }
 
+/// Arms the EL1 physical timer of the calling core, so that it raises its interrupt once the counter
+/// reaches ```deadline``` cycles. The interrupt stays raised until the timer is disarmed, or armed
+/// again for a later deadline.
+pub fn arm_timer(deadline: u64) {
+    CNTP_CVAL_EL0.set(deadline);
+    CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::SET + CNTP_CTL_EL0::IMASK::CLEAR);
+}
+
+/// Stops the EL1 physical timer of the calling core, withdrawing its interrupt.
+pub fn disarm_timer() {
+    CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::CLEAR);
+}
+
 pub fn wait_for(time: Duration) {
     let end_cycles = timer_cycle_count() + duration_to_cycles(time);
 
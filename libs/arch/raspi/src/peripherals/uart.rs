@@ -11,6 +11,7 @@ impl Uart {
 
     pub const UART0_BASE_OFFSET: u64 = 0x201000;
     pub const UART0_WRITE_OFFSET: u64 = Uart::UART0_BASE_OFFSET;
+    pub const UART0_READ_OFFSET: u64 = Uart::UART0_BASE_OFFSET;
     pub const UART0_FR_OFFSET: u64 = Uart::UART0_BASE_OFFSET + 0x18;
     pub const UART0_IBRD_OFFSET: u64 = Uart::UART0_BASE_OFFSET + 0x24;
     pub const UART0_FBRD_OFFSET: u64 = Uart::UART0_BASE_OFFSET + 0x28;
@@ -76,6 +77,16 @@ impl Uart {
         }
         mmio_write(self.mmio_base + Uart::UART0_WRITE_OFFSET, byte as u32);
     }
+
+    /// Returns the next received byte, or None if the receive FIFO is empty. Never blocks.
+    pub fn try_receive_byte(&mut self) -> Option<u8> {
+        // RXFE, set while the receive FIFO is empty
+        if mmio_read(self.mmio_base + Uart::UART0_FR_OFFSET).bit(4) {
+            return None;
+        }
+        // The upper bits of the data register hold the error flags of the byte
+        Some(mmio_read(self.mmio_base + Uart::UART0_READ_OFFSET) as u8)
+    }
 }
 
 impl Write for Uart {
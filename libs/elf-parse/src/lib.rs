@@ -1,6 +1,6 @@
 #![no_std]
 
-use core::{ffi::CStr, mem::size_of, ptr, slice::from_raw_parts};
+use core::{ffi::CStr, mem::size_of, ptr};
 
 const IDENT_SZ: usize = 16;
 
@@ -79,14 +79,12 @@ impl Iterator for ProgramHeaderIter<'_> {
         let phdr_start = (self.idx * self.entsize) as usize;
         let phdr_end_exclusive = ((self.idx + 1) * self.entsize) as usize;
 
-        let res = if self.idx >= self.len.into() {
+        let res = if self.idx >= self.len.into() || (self.entsize as usize) < size_of::<Elf64PHdr>()
+        {
             None
         } else {
-            unsafe {
-                Some(ptr::read(
-                    self.program_table[phdr_start..phdr_end_exclusive].as_ptr() as *const Elf64PHdr,
-                ))
-            }
+            let entry = self.program_table.get(phdr_start..phdr_end_exclusive)?;
+            unsafe { Some(ptr::read_unaligned(entry.as_ptr() as *const Elf64PHdr)) }
         };
 
         self.idx += 1;
@@ -108,14 +106,12 @@ impl Iterator for SectionHeaderIter<'_> {
         let shdr_start = (self.idx * self.entsize) as usize;
         let shdr_end_exclusive = ((self.idx + 1) * self.entsize) as usize;
 
-        let res = if self.idx >= self.len.into() {
+        let res = if self.idx >= self.len.into() || (self.entsize as usize) < size_of::<Elf64SHdr>()
+        {
             None
         } else {
-            unsafe {
-                Some(ptr::read(
-                    self.section_table[shdr_start..shdr_end_exclusive].as_ptr() as *const Elf64SHdr,
-                ))
-            }
+            let entry = self.section_table.get(shdr_start..shdr_end_exclusive)?;
+            unsafe { Some(ptr::read_unaligned(entry.as_ptr() as *const Elf64SHdr)) }
         };
 
         self.idx += 1;
@@ -147,7 +143,7 @@ impl<'b: 'a, 'a> ElfFile<'b> {
         let mut file = ElfFile::<'b> {
             bytes,
             string_table: None,
-            hdr: unsafe { ptr::read((&bytes[0..hdr_size]).as_ptr() as *const Elf64EHdr) },
+            hdr: unsafe { ptr::read_unaligned((&bytes[0..hdr_size]).as_ptr() as *const Elf64EHdr) },
         };
 
         if !file.verify_magic() {
@@ -169,11 +165,14 @@ impl<'b: 'a, 'a> ElfFile<'b> {
     }
 
     pub fn section_headers(&self) -> Option<SectionHeaderIter> {
-        let table_end = (self.hdr.sh_off + (self.hdr.sh_num * self.hdr.sh_entsize) as u64) as usize;
+        let table_end = self
+            .hdr
+            .sh_off
+            .checked_add(self.hdr.sh_num as u64 * self.hdr.sh_entsize as u64)?;
         match self.hdr.sh_off {
             0 => None,
             off => Some(SectionHeaderIter {
-                section_table: &self.bytes[off as usize..table_end],
+                section_table: self.bytes.get(off as usize..table_end as usize)?,
                 entsize: self.hdr.sh_entsize.into(),
                 len: self.hdr.sh_num,
                 idx: 0,
@@ -182,11 +181,14 @@ impl<'b: 'a, 'a> ElfFile<'b> {
     }
 
     pub fn program_headers(&self) -> Option<ProgramHeaderIter> {
-        let table_end = (self.hdr.ph_off + (self.hdr.ph_num * self.hdr.ph_entsize) as u64) as usize;
+        let table_end = self
+            .hdr
+            .ph_off
+            .checked_add(self.hdr.ph_num as u64 * self.hdr.ph_entsize as u64)?;
         match self.hdr.ph_off {
             0 => None,
             off => Some(ProgramHeaderIter {
-                program_table: &self.bytes[off as usize..table_end],
+                program_table: self.bytes.get(off as usize..table_end as usize)?,
                 entsize: self.hdr.ph_entsize.into(),
                 len: self.hdr.ph_num,
                 idx: 0,
@@ -195,7 +197,7 @@ impl<'b: 'a, 'a> ElfFile<'b> {
     }
 
     pub fn get_section_name(&self, hdr: &Elf64SHdr) -> Option<&CStr> {
-        CStr::from_bytes_until_nul(&self.string_table?[hdr.name as usize..]).ok()
+        CStr::from_bytes_until_nul(self.string_table?.get(hdr.name as usize..)?).ok()
     }
 
     fn find_string_table_offset(&self) -> Option<&'a [u8]> {
@@ -209,11 +211,7 @@ impl<'b: 'a, 'a> ElfFile<'b> {
             return None;
         }
 
-        unsafe {
-            Some(from_raw_parts(
-                self.bytes.as_ptr().add(hdr.offset as usize),
-                (hdr.offset + hdr.size) as usize,
-            ))
-        }
+        let bytes: &'b [u8] = self.bytes;
+        bytes.get(hdr.offset as usize..hdr.offset.checked_add(hdr.size)? as usize)
     }
 }
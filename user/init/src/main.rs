@@ -0,0 +1,178 @@
+//! The first user process, a minimal interactive shell on the console
+//!
+//! Besides its built-in commands, the shell runs any other command as a program from the SD card
+//! and waits for it to finish.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use lantern_rt::{
+    fs,
+    io::{self, Read, Write},
+    print, println, process,
+    system::{self, ProcessState},
+};
+
+const PROMPT: &str = "lantern> ";
+const MAX_LINE: usize = 256;
+
+lantern_rt::entry!(main);
+
+fn main() {
+    println!("LanternOS init shell. Type 'help' for a list of commands.");
+    let mut line = [0u8; MAX_LINE];
+    loop {
+        print!("{}", PROMPT);
+        let len = read_line(&mut line);
+        let line = match core::str::from_utf8(&line[..len]) {
+            Ok(line) => line,
+            Err(_) => {
+                println!("Input is not valid UTF-8");
+                continue;
+            }
+        };
+
+        let args: Vec<&str> = line.split_ascii_whitespace().collect();
+        if !args.is_empty() {
+            run_command(&args);
+        }
+    }
+}
+
+// Reads a line from the console, echoing it back since the UART has no terminal driver behind it.
+// Returns the length of the line, without the line ending
+fn read_line(buf: &mut [u8]) -> usize {
+    let mut stdin = io::stdin();
+    let mut stdout = io::stdout();
+    let mut len = 0;
+    loop {
+        let mut byte = [0u8];
+        match stdin.read(&mut byte) {
+            Ok(1) => {}
+            _ => continue,
+        }
+        // There is nowhere to report a failure to echo
+        let _ = match byte[0] {
+            b'\r' | b'\n' => {
+                let _ = stdout.write_all(b"\n");
+                return len;
+            }
+            // Backspace or delete
+            0x08 | 0x7f if len > 0 => {
+                len -= 1;
+                stdout.write_all(b"\x08 \x08")
+            }
+            byte if len < buf.len() && !byte.is_ascii_control() => {
+                buf[len] = byte;
+                len += 1;
+                stdout.write_all(&[byte])
+            }
+            _ => Ok(()),
+        };
+    }
+}
+
+fn run_command(args: &[&str]) {
+    match args[0] {
+        "help" => {
+            println!("Built-in commands:");
+            println!("  ls [dir]      List the files in a directory");
+            println!("  cat <file>... Print the contents of files");
+            println!("  echo [arg]... Print the arguments");
+            println!("  mem           Show physical memory usage");
+            println!("  ps            List processes");
+            println!("  reboot        Restart the system");
+            println!("Anything else is run as a program from the SD card.");
+        }
+        "ls" => ls(args.get(1).copied().unwrap_or("/")),
+        "cat" => {
+            for path in &args[1..] {
+                cat(path);
+            }
+        }
+        "echo" => println!("{}", args[1..].join(" ")),
+        "mem" => match system::memory_info() {
+            Ok(info) => print!("{}", info),
+            Err(error) => println!("mem: {}", error),
+        },
+        "ps" => ps(),
+        "reboot" => println!("reboot: {}", system::reboot()),
+        _ => run_program(args),
+    }
+}
+
+fn ls(path: &str) {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(error) => return println!("ls: {}", error),
+    };
+    for entry in entries {
+        match entry {
+            Ok(entry) if entry.is_dir => println!("{}/", entry.name),
+            Ok(entry) => println!("{:<16}{:>10}", entry.name, entry.size),
+            Err(error) => return println!("ls: {}", error),
+        }
+    }
+}
+
+fn cat(path: &str) {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(error) => return println!("cat: {}: {}", path, error),
+    };
+
+    let mut buf = [0u8; 512];
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => {
+                let _ = io::stdout().write_all(&buf[..read]);
+            }
+            Err(error) => return println!("cat: {}: {}", path, error),
+        }
+    }
+}
+
+fn ps() {
+    println!(
+        "{:>5} {:>5} {:<9} {:>8} NAME",
+        "PID", "PPID", "STATE", "PAGES"
+    );
+    for info in system::processes() {
+        let info = match info {
+            Ok(info) => info,
+            Err(error) => return println!("ps: {}", error),
+        };
+        let state = match info.state {
+            ProcessState::Runnable => "runnable",
+            ProcessState::Running => "running",
+            ProcessState::Waiting => "waiting",
+            ProcessState::Zombie(_) => "zombie",
+        };
+        print!(
+            "{:>5} {:>5} {:<9} {:>8} {}",
+            info.pid, info.parent, state, info.resident_pages, info.name
+        );
+        match info.state {
+            ProcessState::Zombie(status) => println!(" (exited with status {})", status),
+            _ => println!(),
+        }
+    }
+}
+
+fn run_program(args: &[&str]) {
+    // argv[0] doubles as the path
+    let pid = match process::spawn(args[0], args) {
+        Ok(pid) => pid,
+        Err(error) => return println!("{}: failed to start: {}", args[0], error),
+    };
+
+    match process::wait(pid) {
+        Ok(0) => {}
+        Ok(status) => println!("{}: exited with status {}", args[0], status),
+        Err(error) => println!("{}: failed to wait: {}", args[0], error),
+    }
+}
@@ -0,0 +1,104 @@
+//! Arguments and environment of the running program
+
+use core::{
+    ffi::CStr,
+    ptr,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+
+static ARGC: AtomicUsize = AtomicUsize::new(0);
+static ARGV: AtomicPtr<*const u8> = AtomicPtr::new(ptr::null_mut());
+static ENVP: AtomicPtr<*const u8> = AtomicPtr::new(ptr::null_mut());
+
+/// Records the arrays the kernel passed to the program.
+///
+/// # Safety
+///
+/// ```argv``` must point to ```argc``` null terminated strings, and ```envp``` to a null
+/// terminated array of them, all of which live for the rest of the program.
+pub(crate) unsafe fn init(argc: usize, argv: *const *const u8, envp: *const *const u8) {
+    ARGC.store(argc, Ordering::Relaxed);
+    ARGV.store(argv as *mut _, Ordering::Relaxed);
+    ENVP.store(envp as *mut _, Ordering::Relaxed);
+}
+
+// SAFETY: Only called on the pointers recorded by init
+unsafe fn to_str(s: *const u8) -> &'static str {
+    // The kernel only accepts UTF-8 arguments, so this only fails if the program changed them
+    CStr::from_ptr(s.cast()).to_str().unwrap_or("")
+}
+
+/// An iterator over the arguments of the program, starting with its name.
+pub struct Args {
+    index: usize,
+}
+
+impl Iterator for Args {
+    type Item = &'static str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= ARGC.load(Ordering::Relaxed) {
+            return None;
+        }
+        // SAFETY: The index is in bounds of the argument array
+        let arg = unsafe { to_str(*ARGV.load(Ordering::Relaxed).add(self.index)) };
+        self.index += 1;
+        Some(arg)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = ARGC.load(Ordering::Relaxed).saturating_sub(self.index);
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for Args {}
+
+/// Returns the arguments of the program, starting with its name.
+pub fn args() -> Args {
+    Args { index: 0 }
+}
+
+/// Returns the name the program was started with, or an empty string if it was given none.
+pub fn program_name() -> &'static str {
+    args().next().unwrap_or("")
+}
+
+/// An iterator over the environment of the program, as ```KEY=value``` strings.
+pub struct Vars {
+    next: *const *const u8,
+}
+
+impl Iterator for Vars {
+    type Item = &'static str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next.is_null() {
+            return None;
+        }
+        // SAFETY: The array is terminated by a null pointer, which is never stepped past
+        unsafe {
+            if (*self.next).is_null() {
+                return None;
+            }
+            let var = to_str(*self.next);
+            self.next = self.next.add(1);
+            Some(var)
+        }
+    }
+}
+
+/// Returns the environment of the program.
+pub fn vars() -> Vars {
+    Vars {
+        next: ENVP.load(Ordering::Relaxed),
+    }
+}
+
+/// Returns the value of the environment variable ```key```.
+pub fn var(key: &str) -> Option<&'static str> {
+    vars().find_map(|var| {
+        var.strip_prefix(key)
+            .and_then(|rest| rest.strip_prefix('='))
+    })
+}
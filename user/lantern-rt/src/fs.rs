@@ -0,0 +1,129 @@
+//! Files on the SD card
+//!
+//! The filesystem is read-only, so files can only be opened for reading.
+
+use alloc::{string::String, vec::Vec};
+
+use crate::{
+    io::Read,
+    sys::{self, Dirent},
+    Errno, Result,
+};
+
+// The kernel limits paths to 255 bytes plus the null terminator
+const MAX_PATH_LEN: usize = 255;
+
+// Returns a null terminated copy of path
+fn c_path(path: &str) -> Result<Vec<u8>> {
+    if path.len() > MAX_PATH_LEN || path.contains('\0') {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    let mut c_path = Vec::with_capacity(path.len() + 1);
+    c_path.extend_from_slice(path.as_bytes());
+    c_path.push(0);
+    Ok(c_path)
+}
+
+/// An open file, which is closed when dropped.
+pub struct File {
+    fd: u64,
+}
+
+impl File {
+    /// Opens the file at ```path``` for reading.
+    pub fn open(path: &str) -> Result<File> {
+        let fd = sys::open(&c_path(path)?, sys::O_RDONLY)?;
+        Ok(File { fd })
+    }
+
+    /// Returns the file descriptor of the file, which stays owned by the ```File```.
+    pub fn fd(&self) -> u64 {
+        self.fd
+    }
+}
+
+impl Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        sys::read(self.fd, buf)
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        // The descriptor is known to be open, so this cannot fail
+        let _ = sys::close(self.fd);
+    }
+}
+
+/// Reads the entire file at ```path```.
+pub fn read(path: &str) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Reads the entire file at ```path``` as text. Invalid UTF-8 is replaced rather than rejected.
+pub fn read_to_string(path: &str) -> Result<String> {
+    Ok(String::from_utf8_lossy(&read(path)?).into_owned())
+}
+
+/// An entry of a directory.
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    /// Only the 8.3 name is available
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+}
+
+/// An iterator over the entries of a directory.
+pub struct ReadDir {
+    path: Vec<u8>,
+    index: usize,
+    done: bool,
+}
+
+impl Iterator for ReadDir {
+    type Item = Result<DirEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let mut entry = Dirent {
+            size: 0,
+            is_dir: 0,
+            name_len: 0,
+            name: [0; 256],
+        };
+        match sys::readdir(&self.path, self.index, &mut entry) {
+            Ok(true) => {
+                self.index += 1;
+                let name = &entry.name[..(entry.name_len as usize).min(entry.name.len())];
+                Some(Ok(DirEntry {
+                    name: String::from_utf8_lossy(name).into_owned(),
+                    is_dir: entry.is_dir != 0,
+                    size: entry.size,
+                }))
+            }
+            Ok(false) => {
+                self.done = true;
+                None
+            }
+            Err(error) => {
+                self.done = true;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+/// Returns the entries of the directory at ```path```. Entries are looked up one at a time, so
+/// changes to the directory while iterating may skip or repeat entries.
+pub fn read_dir(path: &str) -> Result<ReadDir> {
+    Ok(ReadDir {
+        path: c_path(path)?,
+        index: 0,
+        done: false,
+    })
+}
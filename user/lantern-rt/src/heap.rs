@@ -0,0 +1,151 @@
+//! The program heap
+//!
+//! Small allocations come from a stack of arenas at the program break, each of which is managed by
+//! its own linked list allocator. When no arena can satisfy an allocation, the break is moved up to
+//! make room for a new one. Large allocations get their own anonymous mapping instead, so that
+//! their memory goes back to the kernel as soon as they are freed.
+
+use core::{
+    alloc::{Allocator, GlobalAlloc, Layout},
+    ptr::{null_mut, NonNull},
+};
+
+use allocators::allocators::linked_list_allocator::LinkedListAlloc;
+use arrayvec::ArrayVec;
+
+use crate::{
+    sync::{RawSpinlock, Spinlock},
+    sys,
+};
+
+// Page size of the kernel, which mappings and the break are rounded to
+const PAGE_SIZE: u64 = 0x1000;
+
+// Allocations of at least this many bytes are mapped on their own
+const MMAP_THRESHOLD: usize = 0x20000;
+// Minimum size of a new arena
+const GROWTH_SIZE: u64 = 0x10000;
+const MAX_ARENAS: usize = 64;
+
+struct Arena {
+    start: u64,
+    end: u64,
+    allocator: LinkedListAlloc<RawSpinlock>,
+}
+
+struct Heap {
+    arenas: ArrayVec<Arena, MAX_ARENAS>,
+    // The current program break, or 0 before the first arena was created
+    brk: u64,
+}
+
+// Safety: The arenas own the memory they point to, and the heap is only ever accessed through the
+// lock inside the ProgramHeap
+unsafe impl Send for Heap {}
+
+impl Heap {
+    fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        // Try the newest arenas first, the older ones are more likely to be fragmented
+        if let Some(ptr) = self
+            .arenas
+            .iter()
+            .rev()
+            .find_map(|arena| arena.allocator.allocate(layout).ok())
+        {
+            return Some(ptr.cast());
+        }
+
+        // Leave a page of slack for the allocator's own bookkeeping
+        let size = (layout.size() + layout.align()) as u64 + PAGE_SIZE;
+        self.grow(size.next_multiple_of(PAGE_SIZE).max(GROWTH_SIZE))?;
+        let ptr = self.arenas.last()?.allocator.allocate(layout).ok()?;
+        Some(ptr.cast())
+    }
+
+    fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let addr = ptr.as_ptr() as u64;
+        let arena = self
+            .arenas
+            .iter()
+            .find(|arena| (arena.start..arena.end).contains(&addr))
+            .expect("Attempted to free memory that does not belong to the heap");
+        // Safety: The pointer was handed out by this arena with the same layout
+        unsafe { arena.allocator.deallocate(ptr, layout) };
+    }
+
+    /// Moves the program break up by ```size``` bytes and makes the new memory an arena.
+    fn grow(&mut self, size: u64) -> Option<()> {
+        if self.arenas.is_full() {
+            return None;
+        }
+        if self.brk == 0 {
+            // Safety: Querying the break does not change it
+            self.brk = unsafe { sys::brk(0) };
+        }
+
+        let start = self.brk;
+        let end = start.checked_add(size)?;
+        // Safety: Nothing but the heap uses memory above the break
+        let brk = unsafe { sys::brk(end) };
+        if brk < end {
+            return None;
+        }
+        self.brk = brk;
+
+        // Safety: The range was just added to the heap, and belongs exclusively to this arena
+        let allocator = unsafe { LinkedListAlloc::new(start as *mut u8, end as *mut u8) };
+        self.arenas.push(Arena {
+            start,
+            end,
+            allocator,
+        });
+        Some(())
+    }
+}
+
+pub struct ProgramHeap(Spinlock<Heap>);
+
+#[global_allocator]
+static PROGRAM_HEAP: ProgramHeap = ProgramHeap(Spinlock::new(Heap {
+    arenas: ArrayVec::new_const(),
+    brk: 0,
+}));
+
+fn is_large(layout: Layout) -> bool {
+    layout.size() >= MMAP_THRESHOLD
+}
+
+unsafe impl GlobalAlloc for ProgramHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if !is_large(layout) {
+            return self
+                .0
+                .lock()
+                .allocate(layout)
+                .map_or(null_mut(), |x| x.as_ptr());
+        }
+
+        // Mappings are only ever page aligned
+        if layout.align() as u64 > PAGE_SIZE {
+            return null_mut();
+        }
+        let mapped = sys::mmap(
+            0,
+            layout.size() as u64,
+            sys::PROT_READ | sys::PROT_WRITE,
+            sys::MAP_PRIVATE | sys::MAP_ANONYMOUS,
+            0,
+            0,
+        );
+        mapped.map_or(null_mut(), |addr| addr as *mut u8)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if !is_large(layout) {
+            let ptr = NonNull::new(ptr).expect("Passed null ptr to global allocator");
+            return self.0.lock().deallocate(ptr, layout);
+        }
+
+        sys::munmap(ptr as u64, layout.size() as u64).expect("Failed to unmap a large allocation");
+    }
+}
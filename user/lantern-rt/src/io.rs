@@ -0,0 +1,135 @@
+//! Console I/O
+//!
+//! Standard input, output and error are all connected to the console by the kernel. Output is not
+//! buffered, every ```print!``` is written straight through.
+
+use alloc::vec::Vec;
+use core::fmt::{self, Write as _};
+
+use crate::{sys, Result};
+
+/// A source of bytes.
+pub trait Read {
+    /// Reads at most ```buf.len()``` bytes, returning how many were read. A short read does not mean
+    /// the end of the input, only a read of 0 bytes does.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+    /// Reads until the end of the input, appending to ```buf```. Returns the number of bytes read.
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+        let start = buf.len();
+        let mut chunk = [0u8; 512];
+        loop {
+            match self.read(&mut chunk)? {
+                0 => return Ok(buf.len() - start),
+                read => buf.extend_from_slice(&chunk[..read]),
+            }
+        }
+    }
+}
+
+/// A sink for bytes.
+pub trait Write {
+    /// Writes at most ```buf.len()``` bytes, returning how many were written.
+    fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+    /// Writes all of ```buf```, however many writes it takes.
+    fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            let written = self.write(buf)?;
+            buf = &buf[written..];
+        }
+        Ok(())
+    }
+}
+
+pub struct Stdin;
+pub struct Stdout;
+pub struct Stderr;
+
+pub fn stdin() -> Stdin {
+    Stdin
+}
+
+pub fn stdout() -> Stdout {
+    Stdout
+}
+
+pub fn stderr() -> Stderr {
+    Stderr
+}
+
+impl Read for Stdin {
+    /// Blocks until at least one byte has been received.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        sys::read(sys::STDIN, buf)
+    }
+}
+
+impl Write for Stdout {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        sys::write(sys::STDOUT, buf)
+    }
+}
+
+impl Write for Stderr {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        sys::write(sys::STDERR, buf)
+    }
+}
+
+impl fmt::Write for Stdout {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_all(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+impl fmt::Write for Stderr {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_all(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    // There is nowhere to report a failure to write to the console
+    let _ = Stdout.write_fmt(args);
+}
+
+#[doc(hidden)]
+pub fn _eprint(args: fmt::Arguments) {
+    let _ = Stderr.write_fmt(args);
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => {
+        $crate::io::_print(format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! println {
+    () => {
+        $crate::print!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::io::_print(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => {
+        $crate::io::_eprint(format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    () => {
+        $crate::eprint!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::io::_eprint(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
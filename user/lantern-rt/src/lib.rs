@@ -0,0 +1,49 @@
+//! Runtime for LanternOS user programs
+//!
+//! Provides the program entry point, a heap, console and file I/O and the panic handler, so that a
+//! program only has to supply its ```main```:
+//!
+//! ```ignore
+//! #![no_std]
+//! #![no_main]
+//!
+//! lantern_rt::entry!(main);
+//!
+//! fn main() {
+//!     lantern_rt::println!("Hello from userspace!");
+//! }
+//! ```
+//!
+//! Programs are built for the ```aarch64-lantern``` target spec in the ```user``` directory, which
+//! links them with this crate's linker script.
+
+#![no_std]
+#![feature(allocator_api)]
+
+extern crate alloc;
+
+pub mod env;
+pub mod fs;
+mod heap;
+pub mod io;
+pub mod process;
+mod start;
+pub mod sync;
+pub mod sys;
+pub mod system;
+
+use core::panic::PanicInfo;
+
+pub use sys::Errno;
+
+/// The result of a system call wrapper.
+pub type Result<T> = core::result::Result<T, Errno>;
+
+/// Exit status of a process that panicked.
+pub const PANIC_STATUS: i32 = 101;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    eprintln!("{} panicked: {}", env::program_name(), info);
+    process::exit(PANIC_STATUS)
+}
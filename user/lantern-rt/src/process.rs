@@ -0,0 +1,111 @@
+//! Starting, waiting for and leaving processes
+
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+use crate::{eprintln, sys, Errno, Result};
+
+/// Exit status of a program whose ```main``` returned an error.
+pub const FAILURE_STATUS: i32 = 1;
+
+/// Something ```main``` can return, which decides the exit status of the process.
+pub trait Termination {
+    fn report(self) -> i32;
+}
+
+impl Termination for () {
+    fn report(self) -> i32 {
+        0
+    }
+}
+
+impl Termination for i32 {
+    fn report(self) -> i32 {
+        self
+    }
+}
+
+impl<T: Termination, E: Debug> Termination for core::result::Result<T, E> {
+    fn report(self) -> i32 {
+        match self {
+            Ok(value) => value.report(),
+            Err(error) => {
+                eprintln!("Error: {:?}", error);
+                FAILURE_STATUS
+            }
+        }
+    }
+}
+
+/// Terminates the process with ```status```.
+pub fn exit(status: i32) -> ! {
+    sys::exit(status)
+}
+
+pub fn id() -> u64 {
+    sys::getpid()
+}
+
+/// Returns the pid of the parent, or 0 if the process has none.
+pub fn parent_id() -> u64 {
+    sys::getppid()
+}
+
+/// Lets the other processes run before this one continues.
+pub fn yield_now() {
+    sys::sched_yield()
+}
+
+// The null terminated copies of a path and its arguments the kernel expects. The pointers in argv
+// point into strings, which must not be touched while they are in use
+struct CArgs {
+    strings: Vec<u8>,
+    argv: Vec<*const u8>,
+}
+
+impl CArgs {
+    fn new(path: &str, args: &[&str]) -> Self {
+        let mut strings = Vec::new();
+        let mut offsets = Vec::with_capacity(args.len());
+        for s in core::iter::once(&path).chain(args) {
+            offsets.push(strings.len());
+            strings.extend_from_slice(s.as_bytes());
+            strings.push(0);
+        }
+        // The first string is the path, which is not an argument
+        let mut argv: Vec<*const u8> = offsets[1..]
+            .iter()
+            .map(|offset| strings[*offset..].as_ptr())
+            .collect();
+        argv.push(core::ptr::null());
+        CArgs { strings, argv }
+    }
+}
+
+/// Starts the program at ```path``` as a child process, returning its pid. By convention, the
+/// first argument is the name of the program.
+pub fn spawn(path: &str, args: &[&str]) -> Result<u64> {
+    let c_args = CArgs::new(path, args);
+    sys::spawn(&c_args.strings, &c_args.argv)
+}
+
+/// Replaces the running program with the one at ```path```. Only returns if it could not be
+/// started.
+pub fn exec(path: &str, args: &[&str]) -> Errno {
+    let c_args = CArgs::new(path, args);
+    sys::exec(&c_args.strings, &c_args.argv)
+}
+
+/// Waits for the child ```pid``` to exit, returning its exit status.
+pub fn wait(pid: u64) -> Result<i32> {
+    let mut status = 0;
+    sys::wait(pid as i64, &mut status)?;
+    Ok(status)
+}
+
+/// Waits for any child to exit, returning its pid and exit status.
+pub fn wait_any() -> Result<(u64, i32)> {
+    let mut status = 0;
+    let pid = sys::wait(-1, &mut status)?;
+    Ok((pid, status))
+}
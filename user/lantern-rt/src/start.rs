@@ -0,0 +1,46 @@
+//! Program entry point
+
+use core::arch::global_asm;
+
+use crate::{env, process};
+
+// The kernel passes argc, argv and envp in x0 to x2 already, so all that is left is to terminate
+// the frame chain for debuggers
+global_asm!(
+    r#"
+.section .text._start
+.globl _start
+_start:
+    mov x29, xzr
+    mov x30, xzr
+    bl __lantern_start
+    b .
+"#
+);
+
+extern "Rust" {
+    // Defined by the program through the ```entry``` macro
+    fn __lantern_main() -> i32;
+}
+
+#[no_mangle]
+extern "C" fn __lantern_start(argc: usize, argv: *const *const u8, envp: *const *const u8) -> ! {
+    // SAFETY: The kernel sets up the argument and environment arrays on the initial stack
+    unsafe { env::init(argc, argv, envp) };
+    let status = unsafe { __lantern_main() };
+    process::exit(status)
+}
+
+/// Declares the ```main``` function of a program, which is called once the runtime is set up.
+///
+/// ```main``` takes no arguments and returns anything that implements
+/// ```process::Termination```. Returning from it exits the process with the matching status.
+#[macro_export]
+macro_rules! entry {
+    ($main:path) => {
+        #[no_mangle]
+        fn __lantern_main() -> i32 {
+            $crate::process::Termination::report($main())
+        }
+    };
+}
@@ -0,0 +1,151 @@
+//! Locks and condition variables for user programs
+//!
+//! ```Mutex``` and ```Condvar``` put waiters to sleep on a futex until they can continue, while a
+//! ```Spinlock``` keeps its waiters runnable and suits locks that are only held briefly.
+
+use core::{
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    time::Duration,
+};
+
+use lock_api::GuardSend;
+
+use crate::sys::{self, Errno};
+
+/// A spinlock that gives up the rest of its time slice whenever the lock is taken.
+///
+/// Processes are only switched when they enter the kernel, so a waiter has to yield for the holder
+/// to get a chance to run on its core and release the lock.
+pub struct RawSpinlock(AtomicBool);
+
+unsafe impl lock_api::RawMutex for RawSpinlock {
+    const INIT: Self = RawSpinlock(AtomicBool::new(false));
+
+    type GuardMarker = GuardSend;
+
+    fn lock(&self) {
+        while !self.try_lock() {
+            sys::sched_yield();
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        self.0
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    unsafe fn unlock(&self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+pub type Spinlock<T> = lock_api::Mutex<RawSpinlock, T>;
+pub type SpinlockGuard<'a, T> = lock_api::MutexGuard<'a, RawSpinlock, T>;
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+// Locked, and there may be processes sleeping on the lock
+const CONTENDED: u32 = 2;
+
+/// A lock whose waiters sleep on a futex until it is released.
+///
+/// Releasing the lock only makes a system call if it was contended.
+pub struct RawFutexMutex(AtomicU32);
+
+unsafe impl lock_api::RawMutex for RawFutexMutex {
+    const INIT: Self = RawFutexMutex(AtomicU32::new(UNLOCKED));
+
+    type GuardMarker = GuardSend;
+
+    fn lock(&self) {
+        if self.try_lock() {
+            return;
+        }
+        // Whoever takes the lock from here on cannot know whether others still sleep, so it marks
+        // the lock contended to have its release wake them up
+        while self.0.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            // Returns early if the lock has been released in the meantime
+            let _ = sys::futex_wait(&self.0, CONTENDED, None);
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        self.0
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    unsafe fn unlock(&self) {
+        if self.0.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            let _ = sys::futex_wake(&self.0, 1);
+        }
+    }
+}
+
+pub type Mutex<T> = lock_api::Mutex<RawFutexMutex, T>;
+pub type MutexGuard<'a, T> = lock_api::MutexGuard<'a, RawFutexMutex, T>;
+
+/// Lets processes sleep until a condition protected by a ```Mutex``` may have changed.
+///
+/// As with any condition variable, wakeups may be spurious, so waiters have to check their
+/// condition again.
+pub struct Condvar {
+    // Changed by every notification, so that a waiter that released the mutex before a
+    // notification does not go to sleep after it
+    sequence: AtomicU32,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Condvar {
+            sequence: AtomicU32::new(0),
+        }
+    }
+
+    /// Releases the mutex held by ```guard``` and sleeps until notified, then takes the mutex again.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        self.wait_with(guard, None).0
+    }
+
+    /// Like ```wait```, but gives up once ```timeout``` has passed. The returned flag tells whether
+    /// the timeout expired.
+    pub fn wait_timeout<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Duration,
+    ) -> (MutexGuard<'a, T>, bool) {
+        self.wait_with(guard, Some(timeout))
+    }
+
+    fn wait_with<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Option<Duration>,
+    ) -> (MutexGuard<'a, T>, bool) {
+        // Read while the mutex is held, so any notification that comes after changes it
+        let sequence = self.sequence.load(Ordering::Relaxed);
+        let mutex = MutexGuard::mutex(&guard);
+        drop(guard);
+        let result = sys::futex_wait(&self.sequence, sequence, timeout);
+        (mutex.lock(), result == Err(Errno::ETIMEDOUT))
+    }
+
+    /// Wakes up one of the waiting processes, if there is any.
+    pub fn notify_one(&self) {
+        self.sequence.fetch_add(1, Ordering::Relaxed);
+        let _ = sys::futex_wake(&self.sequence, 1);
+    }
+
+    /// Wakes up all waiting processes.
+    pub fn notify_all(&self) {
+        self.sequence.fetch_add(1, Ordering::Relaxed);
+        let _ = sys::futex_wake(&self.sequence, u32::MAX);
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Condvar::new()
+    }
+}
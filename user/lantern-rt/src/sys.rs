@@ -0,0 +1,364 @@
+//! Raw system calls
+//!
+//! A system call is made with ```svc #0```, with its number in x8 and up to six arguments in x0 to
+//! x5. The result comes back in x0, which is a negated errno on failure. The constants and
+//! structures here mirror the kernel's ```syscall``` module.
+
+use core::{arch::asm, fmt::Display, sync::atomic::AtomicU32, time::Duration};
+
+use crate::Result;
+
+pub const SYS_READ: u64 = 0;
+pub const SYS_WRITE: u64 = 1;
+pub const SYS_OPEN: u64 = 2;
+pub const SYS_CLOSE: u64 = 3;
+pub const SYS_EXIT: u64 = 4;
+pub const SYS_GETPID: u64 = 5;
+pub const SYS_GETPPID: u64 = 6;
+pub const SYS_SPAWN: u64 = 7;
+pub const SYS_EXEC: u64 = 8;
+pub const SYS_WAIT: u64 = 9;
+pub const SYS_YIELD: u64 = 10;
+pub const SYS_MMAP: u64 = 11;
+pub const SYS_MUNMAP: u64 = 12;
+pub const SYS_MPROTECT: u64 = 13;
+pub const SYS_BRK: u64 = 14;
+pub const SYS_READDIR: u64 = 15;
+pub const SYS_MEMINFO: u64 = 16;
+pub const SYS_PROCINFO: u64 = 17;
+pub const SYS_REBOOT: u64 = 18;
+pub const SYS_FUTEX: u64 = 19;
+
+pub const STDIN: u64 = 0;
+pub const STDOUT: u64 = 1;
+pub const STDERR: u64 = 2;
+
+pub const O_RDONLY: u64 = 0;
+
+pub const PROT_NONE: u64 = 0;
+pub const PROT_READ: u64 = 1;
+pub const PROT_WRITE: u64 = 2;
+pub const PROT_EXEC: u64 = 4;
+
+pub const MAP_SHARED: u64 = 1;
+pub const MAP_PRIVATE: u64 = 2;
+pub const MAP_FIXED: u64 = 0x10;
+pub const MAP_ANONYMOUS: u64 = 0x20;
+
+pub const PROC_RUNNABLE: u32 = 0;
+pub const PROC_RUNNING: u32 = 1;
+pub const PROC_WAITING: u32 = 2;
+pub const PROC_ZOMBIE: u32 = 3;
+
+pub const FUTEX_WAIT: u64 = 0;
+pub const FUTEX_WAKE: u64 = 1;
+
+/// The reason a system call failed, with the same values as the kernel's ```Errno```.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Errno(pub u64);
+
+impl Errno {
+    pub const EPERM: Errno = Errno(1);
+    pub const ENOENT: Errno = Errno(2);
+    pub const ESRCH: Errno = Errno(3);
+    pub const EINTR: Errno = Errno(4);
+    pub const EIO: Errno = Errno(5);
+    pub const E2BIG: Errno = Errno(7);
+    pub const ENOEXEC: Errno = Errno(8);
+    pub const EBADF: Errno = Errno(9);
+    pub const ECHILD: Errno = Errno(10);
+    pub const EAGAIN: Errno = Errno(11);
+    pub const ENOMEM: Errno = Errno(12);
+    pub const EACCES: Errno = Errno(13);
+    pub const EFAULT: Errno = Errno(14);
+    pub const EEXIST: Errno = Errno(17);
+    pub const ENOTDIR: Errno = Errno(20);
+    pub const EISDIR: Errno = Errno(21);
+    pub const EINVAL: Errno = Errno(22);
+    pub const EMFILE: Errno = Errno(24);
+    pub const EROFS: Errno = Errno(30);
+    pub const ENAMETOOLONG: Errno = Errno(36);
+    pub const ENOSYS: Errno = Errno(38);
+    pub const ETIMEDOUT: Errno = Errno(110);
+}
+
+impl Display for Errno {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "error {}", self.0)
+    }
+}
+
+/// Mirrors the kernel's ```Dirent```.
+#[repr(C)]
+pub struct Dirent {
+    pub size: u64,
+    pub is_dir: u32,
+    pub name_len: u32,
+    pub name: [u8; 256],
+}
+
+/// Mirrors the kernel's ```ProcInfo```.
+#[repr(C)]
+pub struct ProcInfo {
+    pub pid: u64,
+    pub parent: u64,
+    pub resident_pages: u64,
+    pub state: u32,
+    pub exit_status: i32,
+    pub name_len: u64,
+    pub name: [u8; 32],
+}
+
+/// Splits the raw result of a system call into its value or the error it failed with.
+pub fn check(result: u64) -> Result<u64> {
+    match result > (-4096i64) as u64 {
+        true => Err(Errno(result.wrapping_neg())),
+        false => Ok(result),
+    }
+}
+
+/// Makes system call ```number``` with no arguments.
+///
+/// # Safety
+///
+/// Any memory the arguments refer to must be valid for the system call to access.
+pub unsafe fn syscall0(number: u64) -> u64 {
+    let result;
+    asm!("svc #0", in("x8") number, lateout("x0") result, options(nostack));
+    result
+}
+
+/// Makes system call ```number``` with 1 argument.
+///
+/// # Safety
+///
+/// Any memory the arguments refer to must be valid for the system call to access.
+pub unsafe fn syscall1(number: u64, a0: u64) -> u64 {
+    let result;
+    asm!("svc #0", in("x8") number, inlateout("x0") a0 => result, options(nostack));
+    result
+}
+
+/// Makes system call ```number``` with 2 arguments.
+///
+/// # Safety
+///
+/// Any memory the arguments refer to must be valid for the system call to access.
+pub unsafe fn syscall2(number: u64, a0: u64, a1: u64) -> u64 {
+    let result;
+    asm!(
+        "svc #0",
+        in("x8") number,
+        inlateout("x0") a0 => result,
+        in("x1") a1,
+        options(nostack)
+    );
+    result
+}
+
+/// Makes system call ```number``` with 3 arguments.
+///
+/// # Safety
+///
+/// Any memory the arguments refer to must be valid for the system call to access.
+pub unsafe fn syscall3(number: u64, a0: u64, a1: u64, a2: u64) -> u64 {
+    let result;
+    asm!(
+        "svc #0",
+        in("x8") number,
+        inlateout("x0") a0 => result,
+        in("x1") a1,
+        in("x2") a2,
+        options(nostack)
+    );
+    result
+}
+
+/// Makes system call ```number``` with 4 arguments.
+///
+/// # Safety
+///
+/// Any memory the arguments refer to must be valid for the system call to access.
+pub unsafe fn syscall4(number: u64, a0: u64, a1: u64, a2: u64, a3: u64) -> u64 {
+    let result;
+    asm!(
+        "svc #0",
+        in("x8") number,
+        inlateout("x0") a0 => result,
+        in("x1") a1,
+        in("x2") a2,
+        in("x3") a3,
+        options(nostack)
+    );
+    result
+}
+
+/// Makes system call ```number``` with 6 arguments.
+///
+/// # Safety
+///
+/// Any memory the arguments refer to must be valid for the system call to access.
+pub unsafe fn syscall6(number: u64, a0: u64, a1: u64, a2: u64, a3: u64, a4: u64, a5: u64) -> u64 {
+    let result;
+    asm!(
+        "svc #0",
+        in("x8") number,
+        inlateout("x0") a0 => result,
+        in("x1") a1,
+        in("x2") a2,
+        in("x3") a3,
+        in("x4") a4,
+        in("x5") a5,
+        options(nostack)
+    );
+    result
+}
+
+pub fn read(fd: u64, buf: &mut [u8]) -> Result<usize> {
+    let result = unsafe { syscall3(SYS_READ, fd, buf.as_mut_ptr() as u64, buf.len() as u64) };
+    check(result).map(|read| read as usize)
+}
+
+pub fn write(fd: u64, buf: &[u8]) -> Result<usize> {
+    let result = unsafe { syscall3(SYS_WRITE, fd, buf.as_ptr() as u64, buf.len() as u64) };
+    check(result).map(|written| written as usize)
+}
+
+/// ```path``` must be null terminated.
+pub fn open(path: &[u8], flags: u64) -> Result<u64> {
+    check(unsafe { syscall2(SYS_OPEN, path.as_ptr() as u64, flags) })
+}
+
+pub fn close(fd: u64) -> Result<()> {
+    check(unsafe { syscall1(SYS_CLOSE, fd) }).map(|_| ())
+}
+
+pub fn exit(status: i32) -> ! {
+    unsafe { syscall1(SYS_EXIT, status as u64) };
+    // The kernel never returns from exit
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+pub fn getpid() -> u64 {
+    unsafe { syscall0(SYS_GETPID) }
+}
+
+pub fn getppid() -> u64 {
+    unsafe { syscall0(SYS_GETPPID) }
+}
+
+/// ```path``` and every argument must be null terminated, and ```argv``` must end with a null
+/// pointer.
+pub fn spawn(path: &[u8], argv: &[*const u8]) -> Result<u64> {
+    check(unsafe { syscall2(SYS_SPAWN, path.as_ptr() as u64, argv.as_ptr() as u64) })
+}
+
+/// Same requirements as ```spawn```. Only returns if the program could not be started.
+pub fn exec(path: &[u8], argv: &[*const u8]) -> Errno {
+    let result = unsafe { syscall2(SYS_EXEC, path.as_ptr() as u64, argv.as_ptr() as u64) };
+    check(result).expect_err("exec returned without an error")
+}
+
+/// Waits for ```pid```, or any child if -1. Returns the pid of the child that exited.
+pub fn wait(pid: i64, status: &mut i32) -> Result<u64> {
+    check(unsafe { syscall2(SYS_WAIT, pid as u64, status as *mut i32 as u64) })
+}
+
+pub fn sched_yield() {
+    unsafe { syscall0(SYS_YIELD) };
+}
+
+/// # Safety
+///
+/// With ```MAP_FIXED```, anything mapped in the range before is replaced, which must not be in use.
+pub unsafe fn mmap(
+    addr: u64,
+    len: u64,
+    prot: u64,
+    flags: u64,
+    fd: u64,
+    offset: u64,
+) -> Result<u64> {
+    check(syscall6(SYS_MMAP, addr, len, prot, flags, fd, offset))
+}
+
+/// # Safety
+///
+/// Nothing in the range may be in use anymore.
+pub unsafe fn munmap(addr: u64, len: u64) -> Result<()> {
+    check(syscall2(SYS_MUNMAP, addr, len)).map(|_| ())
+}
+
+/// # Safety
+///
+/// Nothing in the range may be accessed in a way the new protection forbids.
+pub unsafe fn mprotect(addr: u64, len: u64, prot: u64) -> Result<()> {
+    check(syscall3(SYS_MPROTECT, addr, len, prot)).map(|_| ())
+}
+
+/// Moves the program break to ```addr```, or leaves it alone if 0. Returns the new break, which is
+/// the old one if it could not be moved.
+///
+/// # Safety
+///
+/// Nothing above a lowered break may be in use anymore.
+pub unsafe fn brk(addr: u64) -> u64 {
+    syscall1(SYS_BRK, addr)
+}
+
+/// ```path``` must be null terminated. Returns false past the last entry.
+pub fn readdir(path: &[u8], index: usize, entry: &mut Dirent) -> Result<bool> {
+    let result = unsafe {
+        syscall3(
+            SYS_READDIR,
+            path.as_ptr() as u64,
+            index as u64,
+            entry as *mut Dirent as u64,
+        )
+    };
+    check(result).map(|found| found != 0)
+}
+
+pub fn meminfo(buf: &mut [u8]) -> Result<usize> {
+    let result = unsafe { syscall2(SYS_MEMINFO, buf.as_mut_ptr() as u64, buf.len() as u64) };
+    check(result).map(|len| len as usize)
+}
+
+/// Returns false past the last process.
+pub fn procinfo(index: usize, info: &mut ProcInfo) -> Result<bool> {
+    let result = unsafe { syscall2(SYS_PROCINFO, index as u64, info as *mut ProcInfo as u64) };
+    check(result).map(|found| found != 0)
+}
+
+/// Only returns if the system could not be restarted.
+pub fn reboot() -> Errno {
+    check(unsafe { syscall0(SYS_REBOOT) }).expect_err("reboot returned without an error")
+}
+
+/// Sleeps until woken up through ```word```, if it still holds ```expected```. Fails with EAGAIN if
+/// it does not, and with ETIMEDOUT once ```timeout``` passed. Wakeups may be spurious.
+pub fn futex_wait(word: &AtomicU32, expected: u32, timeout: Option<Duration>) -> Result<()> {
+    // The kernel takes 0 as no timeout, so anything shorter than a nanosecond is rounded up
+    let timeout_ns = match timeout {
+        Some(timeout) => timeout.as_nanos().clamp(1, u64::MAX as u128) as u64,
+        None => 0,
+    };
+    let result = unsafe {
+        syscall4(
+            SYS_FUTEX,
+            word.as_ptr() as u64,
+            FUTEX_WAIT,
+            expected as u64,
+            timeout_ns,
+        )
+    };
+    check(result).map(|_| ())
+}
+
+/// Wakes up at most ```count``` processes sleeping on ```word```. Returns how many were woken up.
+pub fn futex_wake(word: &AtomicU32, count: u32) -> Result<u64> {
+    let result = unsafe { syscall4(SYS_FUTEX, word.as_ptr() as u64, FUTEX_WAKE, count as u64, 0) };
+    check(result)
+}
@@ -0,0 +1,76 @@
+//! Information about and control of the system as a whole
+
+use alloc::string::String;
+
+use crate::{sys, Errno, Result};
+
+/// State of a process, as reported by ```process_info```.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProcessState {
+    Runnable,
+    Running,
+    Waiting,
+    /// Exited with the given status, but not waited for yet
+    Zombie(i32),
+}
+
+/// A snapshot of a process.
+#[derive(Clone, Debug)]
+pub struct ProcessInfo {
+    pub pid: u64,
+    /// 0 if the process has no parent
+    pub parent: u64,
+    pub resident_pages: u64,
+    pub state: ProcessState,
+    pub name: String,
+}
+
+/// Returns the ```index```th process in order of pid, or None past the last process.
+pub fn process_info(index: usize) -> Result<Option<ProcessInfo>> {
+    let mut info = sys::ProcInfo {
+        pid: 0,
+        parent: 0,
+        resident_pages: 0,
+        state: 0,
+        exit_status: 0,
+        name_len: 0,
+        name: [0; 32],
+    };
+    if !sys::procinfo(index, &mut info)? {
+        return Ok(None);
+    }
+
+    let state = match info.state {
+        sys::PROC_RUNNABLE => ProcessState::Runnable,
+        sys::PROC_RUNNING => ProcessState::Running,
+        sys::PROC_WAITING => ProcessState::Waiting,
+        sys::PROC_ZOMBIE => ProcessState::Zombie(info.exit_status),
+        _ => return Err(Errno::EINVAL),
+    };
+    let name = &info.name[..(info.name_len as usize).min(info.name.len())];
+    Ok(Some(ProcessInfo {
+        pid: info.pid,
+        parent: info.parent,
+        resident_pages: info.resident_pages,
+        state,
+        name: String::from_utf8_lossy(name).into_owned(),
+    }))
+}
+
+/// Returns a snapshot of all processes. Processes that start or are reaped meanwhile may be
+/// missing or reported twice.
+pub fn processes() -> impl Iterator<Item = Result<ProcessInfo>> {
+    (0..).map_while(|index| process_info(index).transpose())
+}
+
+/// Returns the physical memory usage, in the same format the kernel prints it in.
+pub fn memory_info() -> Result<String> {
+    let mut buf = [0u8; 2048];
+    let len = sys::meminfo(&mut buf)?;
+    Ok(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+/// Restarts the system. Only returns if that is not possible.
+pub fn reboot() -> Errno {
+    sys::reboot()
+}